@@ -0,0 +1,77 @@
+//! Allocations and time per `render_table` call.
+//!
+//! Run with `cargo bench --features table`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use bb_drivelist::{render_table, BusType, DeviceDescriptor, MountPoint};
+
+/// Counts allocations made through the global allocator
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const ITERATIONS: u32 = 10_000;
+
+fn devices() -> Vec<DeviceDescriptor> {
+    (0..8)
+        .map(|i| DeviceDescriptor {
+            device: format!("/dev/sd{}", (b'a' + i) as char),
+            size: 16_000_000_000 * (i as u64 + 1),
+            bus_type: Some(if i % 2 == 0 {
+                BusType::Usb
+            } else {
+                BusType::Sata
+            }),
+            mountpoints: (0..i % 3)
+                .map(|j| MountPoint::new(format!("/media/disk{}-{}", i, j)))
+                .collect(),
+            is_removable: i % 2 == 0,
+            is_system: i == 1,
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn main() {
+    let devices = devices();
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(render_table(black_box(&devices)));
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    println!(
+        "render_table, {} devices: {} allocations, {:?} per call",
+        devices.len(),
+        allocations / ITERATIONS as usize,
+        elapsed / ITERATIONS
+    );
+}
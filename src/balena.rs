@@ -0,0 +1,209 @@
+//! JSON in the shape emitted by Balena's `drivelist`
+
+use std::fmt::Write;
+
+use crate::{DeviceDescriptor, MountPoint, PartitionTableType};
+
+fn string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0C}' => out.push_str("\\f"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn optional(out: &mut String, value: Option<impl ToString>, f: fn(&mut String, &str)) {
+    match value {
+        Some(x) => f(out, &x.to_string()),
+        None => out.push_str("null"),
+    }
+}
+
+fn raw(out: &mut String, value: &str) {
+    out.push_str(value);
+}
+
+/// Write `"key":`, preceded by a comma unless it's the first key
+fn key(out: &mut String, key: &str) {
+    if !out.ends_with('{') {
+        out.push(',');
+    }
+    string(out, key);
+    out.push(':');
+}
+
+fn mountpoint(out: &mut String, mp: &MountPoint) {
+    out.push('{');
+    key(out, "path");
+    string(out, &mp.path);
+    key(out, "label");
+    optional(out, mp.label.as_deref(), string);
+    key(out, "totalBytes");
+    optional(out, mp.total_bytes, raw);
+    key(out, "availableBytes");
+    optional(out, mp.available_bytes, raw);
+    out.push('}');
+}
+
+fn device(out: &mut String, drive: &DeviceDescriptor) {
+    out.push('{');
+    key(out, "enumerator");
+    string(out, &drive.enumerator);
+    key(out, "busType");
+    match &drive.bus_type {
+        Some(x) => string(out, &x.to_string()),
+        None => string(out, "UNKNOWN"),
+    }
+    key(out, "busVersion");
+    optional(out, drive.bus_version.as_deref(), string);
+    key(out, "device");
+    string(out, &drive.device);
+    key(out, "devicePath");
+    optional(out, drive.device_path.as_deref(), string);
+    key(out, "raw");
+    string(out, &drive.raw);
+    key(out, "description");
+    string(out, &drive.description);
+    key(out, "error");
+    optional(out, drive.error.as_deref(), string);
+    key(out, "partitionTableType");
+    optional(
+        out,
+        drive
+            .partition_table_type
+            .as_ref()
+            .filter(|x| matches!(x, PartitionTableType::Mbr | PartitionTableType::Gpt)),
+        string,
+    );
+    key(out, "size");
+    raw(out, &drive.size.to_string());
+    key(out, "blockSize");
+    raw(out, &drive.block_size.to_string());
+    key(out, "logicalBlockSize");
+    raw(out, &drive.logical_block_size.to_string());
+    key(out, "mountpoints");
+    out.push('[');
+    for (i, mp) in drive.mountpoints.iter().enumerate() {
+        if i != 0 {
+            out.push(',');
+        }
+        mountpoint(out, mp);
+    }
+    out.push(']');
+    key(out, "isReadOnly");
+    raw(out, &drive.is_readonly.to_string());
+    key(out, "isSystem");
+    raw(out, &drive.is_system.to_string());
+    key(out, "isVirtual");
+    raw(out, &drive.is_virtual.to_string());
+    key(out, "isRemovable");
+    raw(out, &drive.is_removable.to_string());
+    key(out, "isCard");
+    raw(out, &drive.is_card.to_string());
+    key(out, "isSCSI");
+    raw(out, &drive.is_scsi.to_string());
+    key(out, "isUSB");
+    raw(out, &drive.is_usb.to_string());
+    key(out, "isUAS");
+    optional(out, drive.is_uas, raw);
+    out.push('}');
+}
+
+/// Serialize `drives` like `JSON.stringify` of the Node library's `list()` result
+pub(crate) fn to_json(drives: &[DeviceDescriptor]) -> String {
+    let mut out = String::from("[");
+
+    for (i, drive) in drives.iter().enumerate() {
+        if i != 0 {
+            out.push(',');
+        }
+        device(&mut out, drive);
+    }
+
+    out.push(']');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BusType;
+
+    fn mountpoint(path: &str, label: Option<&str>, total: u64, available: u64) -> MountPoint {
+        MountPoint {
+            label: label.map(String::from),
+            total_bytes: Some(total),
+            available_bytes: Some(available),
+            ..MountPoint::new(path)
+        }
+    }
+
+    /// The same drives as `fixtures/balena-drivelist.json`
+    fn drives() -> Vec<DeviceDescriptor> {
+        vec![
+            DeviceDescriptor {
+                enumerator: "USBSTOR".to_string(),
+                bus_type: Some(BusType::Usb),
+                bus_version: Some("2.0".to_string()),
+                device: r"\\.\PhysicalDrive1".to_string(),
+                raw: r"\\.\PhysicalDrive1".to_string(),
+                description: "SanDisk Cruzer Blade USB Device".to_string(),
+                partition_table_type: Some(PartitionTableType::Mbr),
+                size: 15631122432,
+                block_size: 512,
+                logical_block_size: 512,
+                mountpoints: vec![mountpoint(r"E:\", Some("CRUZER"), 15623913472, 15598239744)],
+                is_removable: true,
+                is_usb: true,
+                is_uas: Some(false),
+                ..Default::default()
+            },
+            DeviceDescriptor {
+                enumerator: "SCSI".to_string(),
+                bus_type: Some(BusType::Nvme),
+                bus_version: Some("2.0".to_string()),
+                device: r"\\.\PhysicalDrive0".to_string(),
+                raw: r"\\.\PhysicalDrive0".to_string(),
+                description: "Samsung SSD 970 EVO Plus 500GB".to_string(),
+                partition_table_type: Some(PartitionTableType::Gpt),
+                size: 500107862016,
+                block_size: 512,
+                logical_block_size: 512,
+                mountpoints: vec![mountpoint(r"C:\", None, 499461910528, 212345131008)],
+                is_system: true,
+                is_scsi: true,
+                is_uas: Some(false),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn matches_reference() {
+        let reference = include_str!("../fixtures/balena-drivelist.json").trim_end();
+
+        assert_eq!(to_json(&drives()), reference);
+    }
+
+    #[test]
+    fn escapes_strings() {
+        let drive = DeviceDescriptor {
+            description: "\"Quoted\"\t\\\u{1}".to_string(),
+            ..Default::default()
+        };
+
+        assert!(to_json(&[drive]).contains(r#""description":"\"Quoted\"\t\\\u0001""#));
+    }
+}
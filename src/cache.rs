@@ -0,0 +1,96 @@
+use std::{
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::{DeviceDescriptor, DriveListQuery};
+
+/// Drive enumerator which reuses its last result for a configurable time.
+///
+/// Call [`CachedEnumerator::invalidate`] (e.g. from a hotplug handler) to force the next call to
+/// enumerate again. Can be shared between threads.
+pub struct CachedEnumerator {
+    query: DriveListQuery,
+    ttl: Duration,
+    cache: Mutex<Option<(Instant, Vec<DeviceDescriptor>)>>,
+}
+
+impl CachedEnumerator {
+    pub fn new(ttl: Duration) -> Self {
+        Self::with_query(DriveListQuery::new(), ttl)
+    }
+
+    pub fn with_query(query: DriveListQuery, ttl: Duration) -> Self {
+        Self {
+            query,
+            ttl,
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// Get the cached list of drives, enumerating again if it is older than the TTL
+    pub fn list(&self) -> anyhow::Result<Vec<DeviceDescriptor>> {
+        let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some((time, drives)) = cache.as_ref()
+            && time.elapsed() < self.ttl
+        {
+            return Ok(drives.clone());
+        }
+
+        let drives = self.query.list()?;
+        *cache = Some((Instant::now(), drives.clone()));
+
+        Ok(drives)
+    }
+
+    /// Drop the cached list so the next call enumerates again
+    pub fn invalidate(&self) {
+        *self.cache.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pal::{with_pal, Scripted};
+
+    fn devices(drives: anyhow::Result<Vec<DeviceDescriptor>>) -> Vec<String> {
+        drives.unwrap().into_iter().map(|x| x.device).collect()
+    }
+
+    fn drive(device: &str) -> DeviceDescriptor {
+        DeviceDescriptor {
+            device: device.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The backend lists `/dev/sdb` from its second call on, so seeing it means it was called again
+    fn scripted() -> Scripted {
+        Scripted::new(vec![vec![drive("/dev/sda")], vec![drive("/dev/sdb")]])
+    }
+
+    #[test]
+    fn reused_within_ttl() {
+        with_pal(scripted(), || {
+            let cache = CachedEnumerator::new(Duration::from_secs(3600));
+
+            assert_eq!(devices(cache.list()), ["/dev/sda"]);
+            assert_eq!(devices(cache.list()), ["/dev/sda"]);
+
+            cache.invalidate();
+            assert_eq!(devices(cache.list()), ["/dev/sdb"]);
+        });
+    }
+
+    #[test]
+    fn expired() {
+        with_pal(scripted(), || {
+            let cache = CachedEnumerator::new(Duration::ZERO);
+
+            assert_eq!(devices(cache.list()), ["/dev/sda"]);
+            assert_eq!(devices(cache.list()), ["/dev/sdb"]);
+        });
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+};
+
+use crate::{
+    checked::{checked_mul_u64, safe_div},
+    DeviceDescriptor, DriveListError,
+};
+
+/// Smallest real capacity considered when looking for wrapped addresses
+const MIN_REAL_CAPACITY: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+/// Result of [`verify_capacity_readonly`](crate::verify_capacity_readonly)
+pub struct CapacityCheck {
+    pub device: String,
+    /// Size reported by the platform in bytes
+    pub claimed_size: u64,
+    /// The last claimed sector could be read
+    pub last_sector_readable: bool,
+    /// Capacity in bytes after which the last sector repeats earlier data, i.e. the likely real
+    /// capacity of a card whose controller wraps addresses
+    pub wraps_at: Option<u64>,
+}
+
+impl CapacityCheck {
+    /// The device probably has less storage than it claims
+    pub fn likely_fake(&self) -> bool {
+        !self.last_sector_readable || self.wraps_at.is_some()
+    }
+}
+
+fn read_sector(file: &mut File, offset: u64, buffer: &mut [u8]) -> std::io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buffer)
+}
+
+/// Read the last claimed sector of `drive` and compare it with the sectors one power of two
+/// capacity before it.
+///
+/// Uniform sectors (e.g. all zeros) are never treated as wrapped, since they are indistinguishable
+/// from unused space.
+pub(crate) fn verify(drive: &DeviceDescriptor) -> anyhow::Result<CapacityCheck> {
+    let sector_size = drive.sector_size() as u64;
+    let mut check = CapacityCheck {
+        device: drive.device.clone(),
+        claimed_size: drive.size,
+        last_sector_readable: false,
+        wraps_at: None,
+    };
+
+    if drive.size < sector_size {
+        return Ok(check);
+    }
+
+    let mut file = File::open(&drive.device).map_err(DriveListError::Io)?;
+    let sectors = safe_div(drive.size, sector_size).unwrap_or_default();
+    let last = (sectors - 1) * sector_size;
+    let mut last_sector = vec![0_u8; sector_size as usize];
+
+    if read_sector(&mut file, last, &mut last_sector).is_err() {
+        return Ok(check);
+    }
+    check.last_sector_readable = true;
+
+    if last_sector.iter().all(|x| *x == last_sector[0]) {
+        return Ok(check);
+    }
+
+    let mut sector = vec![0_u8; sector_size as usize];
+    let mut capacity = MIN_REAL_CAPACITY;
+
+    while capacity <= last {
+        if read_sector(&mut file, last - capacity, &mut sector).is_ok() && sector == last_sector {
+            check.wraps_at = Some(capacity);
+            break;
+        }
+
+        match checked_mul_u64(capacity, 2) {
+            Some(x) => capacity = x,
+            None => break,
+        }
+    }
+
+    Ok(check)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, path::PathBuf};
+
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    /// Sparse file of `len` bytes with `sector` written at each of `offsets`, removed on drop
+    struct Backing(PathBuf);
+
+    impl Backing {
+        fn new(name: &str, len: u64, sector: &[u8], offsets: &[u64]) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "bb-drivelist-{}-{}.img",
+                std::process::id(),
+                name
+            ));
+            let mut file = File::create(&path).unwrap();
+            file.set_len(len).unwrap();
+
+            for offset in offsets {
+                file.seek(SeekFrom::Start(*offset)).unwrap();
+                file.write_all(sector).unwrap();
+            }
+
+            Self(path)
+        }
+
+        fn drive(&self, size: u64) -> DeviceDescriptor {
+            DeviceDescriptor {
+                device: self.0.to_string_lossy().to_string(),
+                size,
+                ..Default::default()
+            }
+        }
+    }
+
+    impl Drop for Backing {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn pattern() -> Vec<u8> {
+        (0..512).map(|x| (x * 7 % 251) as u8).collect()
+    }
+
+    /// A 64 MiB card claiming 256 MiB, whose controller maps the last sector onto the last sector
+    /// of the real capacity
+    #[test]
+    fn wrapped_pattern() {
+        let last = 256 * MIB - 512;
+        let backing = Backing::new("wrapped", 256 * MIB, &pattern(), &[last - 64 * MIB, last]);
+
+        let check = verify(&backing.drive(256 * MIB)).unwrap();
+
+        assert!(check.last_sector_readable);
+        assert_eq!(check.wraps_at, Some(64 * MIB));
+        assert!(check.likely_fake());
+    }
+
+    #[test]
+    fn genuine() {
+        let backing = Backing::new("genuine", 256 * MIB, &pattern(), &[256 * MIB - 512]);
+
+        let check = verify(&backing.drive(256 * MIB)).unwrap();
+
+        assert!(check.last_sector_readable);
+        assert_eq!(check.wraps_at, None);
+        assert!(!check.likely_fake());
+    }
+
+    #[test]
+    fn truncated_backing_store() {
+        let backing = Backing::new("truncated", 64 * MIB, &pattern(), &[0]);
+
+        let check = verify(&backing.drive(256 * MIB)).unwrap();
+
+        assert_eq!(check.claimed_size, 256 * MIB);
+        assert!(!check.last_sector_readable);
+        assert!(check.likely_fake());
+    }
+}
@@ -0,0 +1,36 @@
+//! Arithmetic helpers for values reported by devices.
+//!
+//! Broken or counterfeit devices can report nonsense geometry, so nothing computed from device
+//! data should be able to overflow or divide by zero.
+
+/// Divide `a` by `b`. Returns `None` if `b` is zero.
+pub(crate) fn safe_div(a: u64, b: u64) -> Option<u64> {
+    a.checked_div(b)
+}
+
+/// Multiply `a` by `b`. Returns `None` on overflow.
+pub(crate) fn checked_mul_u64(a: u64, b: u64) -> Option<u64> {
+    a.checked_mul(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_divisor() {
+        assert_eq!(safe_div(4096, 512), Some(8));
+        assert_eq!(safe_div(4096, 0), None);
+        assert_eq!(safe_div(0, 0), None);
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(
+            checked_mul_u64(u32::MAX as u64, 512),
+            Some(u32::MAX as u64 * 512)
+        );
+        assert_eq!(checked_mul_u64(u64::MAX / 2 + 1, 2), None);
+        assert_eq!(checked_mul_u64(u64::MAX, 0), Some(0));
+    }
+}
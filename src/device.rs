@@ -1,10 +1,29 @@
+use std::{borrow::Cow, collections::BTreeSet};
+
+use crate::checked::safe_div;
+
 #[derive(Debug, Default, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "camelCase")
+)]
 /// Mountpoints of a drive
 pub struct MountPoint {
     pub path: String,
     pub label: Option<String>,
     pub total_bytes: Option<u64>,
     pub available_bytes: Option<u64>,
+    /// `available_bytes` is shared with other volumes (e.g. APFS volumes in the same container)
+    pub available_bytes_shared: bool,
+    /// Filesystem treats file names as case-sensitive
+    pub case_sensitive: Option<bool>,
+    /// Filesystem compression is enabled
+    pub compressed: Option<bool>,
+    /// Problems which make the other fields unreliable, e.g. `"volume dirty"`
+    pub warnings: Vec<String>,
+    /// Mountpoint contains the current user's home directory, if it is known
+    pub is_user_home: Option<bool>,
 }
 
 impl MountPoint {
@@ -14,43 +33,418 @@ impl MountPoint {
             label: None,
             total_bytes: None,
             available_bytes: None,
+            available_bytes_shared: false,
+            case_sensitive: None,
+            compressed: None,
+            warnings: Vec::new(),
+            is_user_home: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "camelCase")
+)]
+/// Partition of a drive
+pub struct PartitionInfo {
+    /// Device node or path of the partition
+    pub path: String,
+    /// Partition name (GPT partition label)
+    pub name: Option<String>,
+    /// Filesystem label, also for partitions which aren't mounted
+    pub label: Option<String>,
+    /// GPT partition type GUID, MBR partition type (`0xNN`) or platform specific content type
+    pub partition_type: Option<String>,
+    /// Offset from the start of the drive in bytes
+    pub offset: Option<u64>,
+    pub size: Option<u64>,
+    /// Offset is a multiple of the drive's physical block size
+    pub aligned_physical: Option<bool>,
+    /// Offset is a multiple of 1 MiB
+    pub aligned_mib: Option<bool>,
+    /// Active (boot indicator 0x80) partition of an MBR partition table
+    pub bootable: Option<bool>,
+}
+
+/// GPT partition type of the EFI System Partition
+const ESP_PARTITION_TYPE: &str = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b";
+
+impl PartitionInfo {
+    pub fn new(path: impl ToString) -> Self {
+        Self {
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Partition is an EFI System Partition (ESP)
+    pub fn is_efi_system_partition(&self) -> bool {
+        self.partition_type.as_deref().is_some_and(|x| {
+            x.eq_ignore_ascii_case(ESP_PARTITION_TYPE)
+                || x.eq_ignore_ascii_case("0xef")
+                // macOS diskutil content type
+                || x == "EFI"
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "String", from = "String")
+)]
+/// Bus or protocol a drive is connected through
+pub enum BusType {
+    Unknown,
+    Scsi,
+    Atapi,
+    Ata,
+    /// IEEE 1394
+    Ieee1394,
+    Ssa,
+    Fibre,
+    Usb,
+    Raid,
+    Iscsi,
+    /// Serial-Attached SCSI
+    Sas,
+    Sata,
+    /// Secure Digital
+    Sd,
+    /// Multimedia card
+    Mmc,
+    Virtual,
+    FileBackedVirtual,
+    Nvme,
+    Scm,
+    Ufs,
+    /// Bus reported by the platform that has no variant
+    Other(String),
+}
+
+impl BusType {
+    /// Name of the bus as reported by Windows, e.g. `USB` or `SDCARD`
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Unknown => "UNKNOWN",
+            Self::Scsi => "SCSI",
+            Self::Atapi => "ATAPI",
+            Self::Ata => "ATA",
+            Self::Ieee1394 => "1394",
+            Self::Ssa => "SSA",
+            Self::Fibre => "FIBRE",
+            Self::Usb => "USB",
+            Self::Raid => "RAID",
+            Self::Iscsi => "iSCSI",
+            Self::Sas => "SAS",
+            Self::Sata => "SATA",
+            Self::Sd => "SDCARD",
+            Self::Mmc => "MMC",
+            Self::Virtual => "VIRTUAL",
+            Self::FileBackedVirtual => "FILEBACKEDVIRTUAL",
+            Self::Nvme => "NVME",
+            Self::Scm => "SCM",
+            Self::Ufs => "UFS",
+            Self::Other(x) => x,
+        }
+    }
+}
+
+impl std::fmt::Display for BusType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for BusType {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_uppercase().as_str() {
+            "UNKNOWN" => Self::Unknown,
+            "SCSI" => Self::Scsi,
+            "ATAPI" => Self::Atapi,
+            "ATA" => Self::Ata,
+            "1394" => Self::Ieee1394,
+            "SSA" => Self::Ssa,
+            "FIBRE" => Self::Fibre,
+            "USB" => Self::Usb,
+            "RAID" => Self::Raid,
+            "ISCSI" => Self::Iscsi,
+            "SAS" => Self::Sas,
+            "SATA" => Self::Sata,
+            "SDCARD" => Self::Sd,
+            "MMC" => Self::Mmc,
+            "VIRTUAL" => Self::Virtual,
+            "FILEBACKEDVIRTUAL" => Self::FileBackedVirtual,
+            "NVME" => Self::Nvme,
+            "SCM" => Self::Scm,
+            "UFS" => Self::Ufs,
+            _ => Self::Other(s.to_string()),
+        })
+    }
+}
+
+impl From<String> for BusType {
+    fn from(value: String) -> Self {
+        let Ok(x) = value.parse();
+        x
+    }
+}
+
+impl From<BusType> for String {
+    fn from(value: BusType) -> Self {
+        value.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "String", from = "String")
+)]
+/// Partition table (scheme) of a drive
+pub enum PartitionTableType {
+    Mbr,
+    Gpt,
+    /// No partition table
+    Raw,
+    Unknown,
+}
+
+impl PartitionTableType {
+    /// Lowercase name of the scheme, e.g. `"mbr"` or `"gpt"`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Mbr => "mbr",
+            Self::Gpt => "gpt",
+            Self::Raw => "raw",
+            Self::Unknown => "unknown",
         }
     }
 }
 
+impl std::fmt::Display for PartitionTableType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for PartitionTableType {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            // lsblk reports MBR as "dos"
+            "mbr" | "dos" => Self::Mbr,
+            "gpt" => Self::Gpt,
+            "raw" => Self::Raw,
+            _ => Self::Unknown,
+        })
+    }
+}
+
+impl From<String> for PartitionTableType {
+    fn from(value: String) -> Self {
+        let Ok(x) = value.parse();
+        x
+    }
+}
+
+impl From<PartitionTableType> for String {
+    fn from(value: PartitionTableType) -> Self {
+        value.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// Physical category of a drive's media
+pub enum MediaType {
+    /// Non-removable media inside the machine
+    #[default]
+    FixedMedia,
+    /// Media which can be removed from the drive (SD cards, optical discs, most USB sticks)
+    RemovableMedia,
+    /// Non-removable media in an external enclosure (e.g. USB attached SSD)
+    ExternalFixed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// Overall SMART health status of a drive
+pub enum SmartHealth {
+    Passed,
+    Failing,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// Operating system whose backend listed a drive
+#[non_exhaustive]
+pub enum Platform {
+    Windows,
+    MacOs,
+    Linux,
+    /// Target without a backend, listing always fails
+    Unsupported,
+}
+
+impl Platform {
+    /// Platform this crate was compiled for
+    pub const fn current() -> Self {
+        #[cfg(target_os = "windows")]
+        return Self::Windows;
+        #[cfg(target_os = "macos")]
+        return Self::MacOs;
+        #[cfg(target_os = "linux")]
+        return Self::Linux;
+        #[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+        return Self::Unsupported;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// Category of a drive for UIs, see [`DeviceDescriptor::kind`]
+#[non_exhaustive]
+pub enum DriveKind {
+    SdCard,
+    Optical,
+    /// Memory backed disk, such as Linux `ram` and `zram` devices
+    RamDisk,
+    VirtualDisk,
+    Usb,
+    Nvme,
+    Removable,
+    Internal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Whether a drive can be offered as a flashing target, see
+/// [`DeviceDescriptor::flash_readiness`]
+pub enum FlashReadiness {
+    Ready,
+    /// Human readable reasons the drive shouldn't be flashed
+    NotReady(Vec<String>),
+}
+
+impl FlashReadiness {
+    pub fn is_ready(&self) -> bool {
+        *self == Self::Ready
+    }
+}
+
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "camelCase")
+)]
 /// Device Description
 pub struct DeviceDescriptor {
     pub enumerator: String,
-    pub bus_type: Option<String>,
+    pub bus_type: Option<BusType>,
     pub bus_version: Option<String>,
     pub device: String,
     pub device_path: Option<String>,
     pub raw: String,
     pub description: String,
     pub error: Option<String>,
-    pub partition_table_type: Option<String>,
+    /// Non-fatal problems found while checking the reported values
+    pub warnings: Vec<String>,
+    /// Partitions fit in the reported `size`. Faulty or counterfeit devices may report less.
+    pub size_consistent: bool,
+    pub partition_table_type: Option<PartitionTableType>,
     pub size: u64,
     pub block_size: u32,
     pub logical_block_size: u32,
+    /// Size of a cache line in bytes
+    pub bytes_per_cache_line: Option<u32>,
+    /// Offset in bytes of the first cache aligned block
+    pub bytes_offset_for_cache_alignment: Option<u32>,
+    /// Offset in bytes of the first physical sector aligned block
+    pub bytes_offset_for_sector_alignment: Option<u32>,
     pub mountpoints: Vec<MountPoint>,
     pub mountpoint_labels: Vec<String>,
+    pub partitions: Vec<PartitionInfo>,
     /// Device is read-only
+    #[cfg_attr(feature = "serde", serde(rename = "isReadOnly"))]
     pub is_readonly: bool,
     /// Device is a system drive
     pub is_system: bool,
     /// Device is an SD-card
     pub is_card: bool,
     /// Connected via the Small Computer System Interface (SCSI)
+    #[cfg_attr(feature = "serde", serde(rename = "isSCSI"))]
     pub is_scsi: bool,
     /// Connected via Universal Serial Bus (USB)
+    #[cfg_attr(feature = "serde", serde(rename = "isUSB"))]
     pub is_usb: bool,
     /// Device is a virtual storage device
     pub is_virtual: bool,
     /// Device is removable from the running system
     pub is_removable: bool,
+    /// Whether the media itself is removable or external
+    pub media_type: MediaType,
     /// Connected via the USB Attached SCSI (UAS)
+    #[cfg_attr(feature = "serde", serde(rename = "isUAS"))]
     pub is_uas: Option<bool>,
+    /// Physical form factor (e.g. `"eMMC"`, `"SD"`, `"M.2"`, `"2.5\""`), if known.
+    ///
+    /// Windows reports it for drives whose miniport fills in the physical topology (e.g. NVMe).
+    /// Elsewhere only cards are recognized: NVMe identify data has no form factor, and Linux and
+    /// macOS don't expose one for other drives.
+    pub form_factor: Option<String>,
+    /// Device is thin provisioned (e.g. sparse VM disks and thin LUNs), if known
+    pub thin_provisioned: Option<bool>,
+    /// Time spent querying this device, only reported when requested with
+    /// [`DriveListQuery::enumeration_durations`](crate::DriveListQuery::enumeration_durations)
+    pub enumeration_duration: Option<std::time::Duration>,
+    /// SMART health status, only gathered when requested with
+    /// [`DriveListQuery::smart_health`](crate::DriveListQuery::smart_health)
+    pub smart_health: Option<SmartHealth>,
+    /// Devices this one is built on, e.g. the paths of a multipath device
+    pub backing_devices: Vec<String>,
+    /// USB drive is connected through a hub rather than directly to a root port, if known
+    pub behind_hub: Option<bool>,
+    /// Media is inserted (e.g. a card in a card reader slot), if known
+    pub media_present: Option<bool>,
+    /// Serial number reported by the device, if any
+    pub serial: Option<String>,
+    /// PnP device instance ID on Windows, e.g. `USBSTOR\DISK&VEN_...`
+    pub instance_id: Option<String>,
+    /// Path of the parent hub or controller (PnP instance ID on Windows, sysfs path on Linux)
+    pub parent_path: Option<String>,
+    /// Drive supports the TCG security protocols used by self-encrypting drives (e.g. Opal), if
+    /// known. Only checked with
+    /// [`DriveListQuery::self_encryption`](crate::DriveListQuery::self_encryption).
+    pub self_encrypting: Option<bool>,
+    /// Volatile write cache is enabled, if known
+    pub write_cache_enabled: Option<bool>,
+    /// Backend which listed the drive
+    pub platform: Platform,
+    /// Number of hardware submission queues, if known
+    pub queue_count: Option<u32>,
+    /// Number of requests which can be queued per submission queue, if known
+    pub queue_depth: Option<u32>,
+    /// Largest single transfer the adapter supports in bytes, if known
+    pub max_transfer_bytes: Option<u32>,
+    /// Device is part of a RAID array which is being resynced, rebuilt or checked, if known.
+    ///
+    /// Removing such a drive is dangerous. Only Linux md arrays are detected.
+    pub maintenance_active: Option<bool>,
+    /// Hardware partition which isn't general purpose storage, such as the eMMC boot and RPMB
+    /// areas. Flashing tools should not offer these as targets.
+    pub special_partition: bool,
+    /// Memory backed disk, whose contents are lost on reboot. Always virtual.
+    pub ram_disk: bool,
 }
 
 impl Default for DeviceDescriptor {
@@ -58,6 +452,9 @@ impl Default for DeviceDescriptor {
         Self {
             block_size: 512,
             logical_block_size: 512,
+            bytes_per_cache_line: Default::default(),
+            bytes_offset_for_cache_alignment: Default::default(),
+            bytes_offset_for_sector_alignment: Default::default(),
             enumerator: Default::default(),
             bus_type: Default::default(),
             bus_version: Default::default(),
@@ -66,10 +463,13 @@ impl Default for DeviceDescriptor {
             raw: Default::default(),
             description: Default::default(),
             error: Default::default(),
+            warnings: Default::default(),
+            size_consistent: true,
             partition_table_type: Default::default(),
             size: Default::default(),
             mountpoints: Default::default(),
             mountpoint_labels: Default::default(),
+            partitions: Default::default(),
             is_readonly: Default::default(),
             is_system: Default::default(),
             is_card: Default::default(),
@@ -77,7 +477,931 @@ impl Default for DeviceDescriptor {
             is_usb: Default::default(),
             is_virtual: Default::default(),
             is_removable: Default::default(),
+            media_type: Default::default(),
             is_uas: Default::default(),
+            form_factor: Default::default(),
+            thin_provisioned: Default::default(),
+            enumeration_duration: Default::default(),
+            smart_health: Default::default(),
+            backing_devices: Default::default(),
+            behind_hub: Default::default(),
+            media_present: Default::default(),
+            serial: Default::default(),
+            instance_id: Default::default(),
+            parent_path: Default::default(),
+            self_encrypting: Default::default(),
+            write_cache_enabled: Default::default(),
+            platform: Platform::current(),
+            queue_count: Default::default(),
+            queue_depth: Default::default(),
+            max_transfer_bytes: Default::default(),
+            maintenance_active: Default::default(),
+            special_partition: Default::default(),
+            ram_disk: Default::default(),
+        }
+    }
+}
+
+/// Strip control characters (including NULs), collapse runs of whitespace and trim.
+///
+/// Strings which are already normalized, as most are, are borrowed.
+fn normalize_string(s: &str) -> Cow<'_, str> {
+    let normalized = !s.starts_with(' ')
+        && !s.ends_with(' ')
+        && !s.contains("  ")
+        && !s
+            .chars()
+            .any(|x| x != ' ' && (x.is_whitespace() || x.is_control()));
+
+    if normalized {
+        return Cow::Borrowed(s);
+    }
+
+    Cow::Owned(
+        s.split(|x: char| x.is_whitespace() || x.is_control())
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+impl DeviceDescriptor {
+    /// Logical sector size, falling back to 512 if the platform reported 0
+    pub fn sector_size(&self) -> u32 {
+        if self.logical_block_size == 0 {
+            512
+        } else {
+            self.logical_block_size
+        }
+    }
+
+    /// Size of the drive in logical sectors
+    pub fn size_in_sectors(&self) -> u64 {
+        safe_div(self.size, self.sector_size() as u64).unwrap_or_default()
+    }
+
+    /// Convert all size fields (drive, partitions and mountpoints) from bytes to logical sectors
+    pub(crate) fn convert_sizes_to_sectors(&mut self) {
+        let sector_size = self.sector_size() as u64;
+        let to_sectors = |x: &mut Option<u64>| *x = x.and_then(|x| safe_div(x, sector_size));
+
+        self.size = safe_div(self.size, sector_size).unwrap_or_default();
+
+        for partition in self.partitions.iter_mut() {
+            to_sectors(&mut partition.offset);
+            to_sectors(&mut partition.size);
+        }
+
+        for mountpoint in self.mountpoints.iter_mut() {
+            to_sectors(&mut mountpoint.total_bytes);
+            to_sectors(&mut mountpoint.available_bytes);
+        }
+    }
+
+    /// Sort mountpoints by path, dropping duplicates and unmounted (empty path) entries
+    pub(crate) fn normalize_mountpoints(&mut self) {
+        self.mountpoints.retain(|x| !x.path.is_empty());
+        self.mountpoints.sort_by(|a, b| a.path.cmp(&b.path));
+        self.mountpoints.dedup_by(|a, b| a.path == b.path);
+    }
+
+    /// Normalize the description, mountpoint labels and partition names reported by the platform.
+    ///
+    /// Labels and names that are empty afterwards are dropped.
+    pub(crate) fn normalize_strings(&mut self) {
+        if let Cow::Owned(x) = normalize_string(&self.description) {
+            self.description = x;
+        }
+
+        for label in self
+            .mountpoints
+            .iter_mut()
+            .map(|x| &mut x.label)
+            .chain(self.partitions.iter_mut().map(|x| &mut x.name))
+        {
+            if let Some(Cow::Owned(x)) = label.as_deref().map(normalize_string) {
+                *label = Some(x);
+            }
+
+            if label.as_deref() == Some("") {
+                *label = None;
+            }
+        }
+    }
+
+    /// Compute partition alignment from the partition offsets and physical block size
+    pub(crate) fn compute_partition_alignment(&mut self) {
+        let block_size = self.block_size as u64;
+
+        for partition in self.partitions.iter_mut() {
+            if let Some(offset) = partition.offset {
+                partition.aligned_physical =
+                    (block_size != 0).then(|| offset.is_multiple_of(block_size));
+                partition.aligned_mib = Some(offset.is_multiple_of(1024 * 1024));
+            }
+        }
+    }
+
+    /// Check that the partitions fit in the reported size.
+    ///
+    /// Skipped when the size is unknown (0), e.g. for a card reader without media.
+    pub(crate) fn check_size_consistency(&mut self) {
+        if self.size == 0 {
+            return;
+        }
+
+        let total = self
+            .partitions
+            .iter()
+            .filter_map(|x| x.size)
+            .fold(0_u64, u64::saturating_add);
+        let end = self
+            .partitions
+            .iter()
+            .filter_map(|x| x.offset?.checked_add(x.size?))
+            .max()
+            .unwrap_or_default();
+
+        self.size_consistent = total <= self.size && end <= self.size;
+
+        if !self.size_consistent {
+            self.warnings.push(format!(
+                "Partitions ({} bytes, ending at {}) exceed the reported size of {} bytes",
+                total, end, self.size
+            ));
+        }
+    }
+
+    /// The EFI System Partition of this drive, whether or not it is mounted
+    pub fn efi_system_partition(&self) -> Option<&PartitionInfo> {
+        self.partitions.iter().find(|x| x.is_efi_system_partition())
+    }
+
+    /// Check if the set of mounted paths differs from `old`.
+    ///
+    /// Only paths are compared, so changes in free space are ignored.
+    pub fn mountpoints_changed_since(&self, old: &DeviceDescriptor) -> bool {
+        let new: BTreeSet<&str> = self.mountpoints.iter().map(|x| x.path.as_str()).collect();
+        let old: BTreeSet<&str> = old.mountpoints.iter().map(|x| x.path.as_str()).collect();
+
+        new != old
+    }
+
+    /// Category of the drive, checked in the order of the [`DriveKind`] variants
+    pub fn kind(&self) -> DriveKind {
+        let bus_type = self.bus_type.as_ref();
+
+        if self.is_card || matches!(bus_type, Some(BusType::Sd | BusType::Mmc)) {
+            DriveKind::SdCard
+        } else if bus_type == Some(&BusType::Atapi) {
+            DriveKind::Optical
+        } else if self.ram_disk {
+            DriveKind::RamDisk
+        } else if self.is_virtual {
+            DriveKind::VirtualDisk
+        } else if self.is_usb || bus_type == Some(&BusType::Usb) {
+            DriveKind::Usb
+        } else if bus_type == Some(&BusType::Nvme) {
+            DriveKind::Nvme
+        } else if self.is_removable {
+            DriveKind::Removable
+        } else {
+            DriveKind::Internal
+        }
+    }
+
+    /// Short category label for UIs.
+    ///
+    /// The label is one of:
+    /// - `"SD Card"`
+    /// - `"Optical"`
+    /// - `"RAM Disk"`
+    /// - `"Virtual Disk"`
+    /// - `"USB Drive"`
+    /// - `"NVMe SSD"`
+    /// - `"Removable Drive"`
+    /// - `"Internal Drive"`
+    pub fn kind_label(&self) -> &'static str {
+        match self.kind() {
+            DriveKind::SdCard => "SD Card",
+            DriveKind::Optical => "Optical",
+            DriveKind::RamDisk => "RAM Disk",
+            DriveKind::VirtualDisk => "Virtual Disk",
+            DriveKind::Usb => "USB Drive",
+            DriveKind::Nvme => "NVMe SSD",
+            DriveKind::Removable => "Removable Drive",
+            DriveKind::Internal => "Internal Drive",
+        }
+    }
+
+    /// Combined check of everything which makes a drive unsuitable as a flashing target.
+    ///
+    /// The drive must be removable, not a system, virtual or RAM disk or special hardware partition,
+    /// writable, not busy (mounted or in RAID maintenance) and have media inserted. Unknown values
+    /// don't count against the drive.
+    pub fn flash_readiness(&self) -> FlashReadiness {
+        let mut reasons = Vec::new();
+
+        if !self.is_removable && !self.is_card && self.media_type == MediaType::FixedMedia {
+            reasons.push("Not removable".to_string());
+        }
+        if self.is_system {
+            reasons.push("System drive".to_string());
+        }
+        if self.ram_disk {
+            reasons.push("RAM disk".to_string());
+        } else if self.is_virtual {
+            reasons.push("Virtual drive".to_string());
+        }
+        if self.special_partition {
+            reasons.push("Boot or RPMB hardware partition".to_string());
+        }
+        if self.is_readonly {
+            reasons.push("Read-only".to_string());
+        }
+        if !self.mountpoints.is_empty() {
+            let paths: Vec<_> = self.mountpoints.iter().map(|x| x.path.as_str()).collect();
+            reasons.push(format!("Mounted at {}", paths.join(", ")));
+        }
+        if self.maintenance_active == Some(true) {
+            reasons.push("RAID maintenance in progress".to_string());
+        }
+        if self.media_present == Some(false) {
+            reasons.push("No media".to_string());
+        }
+
+        if reasons.is_empty() {
+            FlashReadiness::Ready
+        } else {
+            FlashReadiness::NotReady(reasons)
+        }
+    }
+
+    /// Multi-line report of all known fields, including mountpoints and partitions.
+    ///
+    /// Unknown (`None`) and empty fields are skipped. Meant for bug reports and verbose output, the
+    /// format is not stable.
+    pub fn human_report(&self) -> String {
+        use std::fmt::{Debug, Write};
+
+        fn field(out: &mut String, indent: &str, name: &str, value: Option<impl Debug>) {
+            if let Some(value) = value {
+                let _ = writeln!(out, "{}{}: {:?}", indent, name, value);
+            }
+        }
+
+        let non_empty = |x: &str| Some(x.to_string()).filter(|x| !x.is_empty());
+        let mut out = String::new();
+
+        let _ = writeln!(out, "{} ({})", self.device, self.kind_label());
+        field(&mut out, "  ", "raw", non_empty(&self.raw));
+        field(&mut out, "  ", "description", non_empty(&self.description));
+        field(&mut out, "  ", "enumerator", non_empty(&self.enumerator));
+        field(&mut out, "  ", "platform", Some(self.platform));
+        field(&mut out, "  ", "bus type", self.bus_type.as_ref());
+        field(&mut out, "  ", "bus version", self.bus_version.as_ref());
+        field(&mut out, "  ", "device path", self.device_path.as_ref());
+        field(&mut out, "  ", "instance id", self.instance_id.as_ref());
+        field(&mut out, "  ", "parent path", self.parent_path.as_ref());
+        field(&mut out, "  ", "serial", self.serial.as_ref());
+        field(&mut out, "  ", "size", Some(self.size));
+        field(&mut out, "  ", "block size", Some(self.block_size));
+        field(
+            &mut out,
+            "  ",
+            "logical block size",
+            Some(self.logical_block_size),
+        );
+        field(
+            &mut out,
+            "  ",
+            "partition table",
+            self.partition_table_type.as_ref(),
+        );
+        field(&mut out, "  ", "media type", Some(self.media_type));
+        field(&mut out, "  ", "media present", self.media_present);
+        field(&mut out, "  ", "form factor", self.form_factor.as_ref());
+        field(&mut out, "  ", "read-only", Some(self.is_readonly));
+        field(&mut out, "  ", "system", Some(self.is_system));
+        field(&mut out, "  ", "removable", Some(self.is_removable));
+        field(&mut out, "  ", "virtual", Some(self.is_virtual));
+        field(&mut out, "  ", "card", Some(self.is_card));
+        field(&mut out, "  ", "usb", Some(self.is_usb));
+        field(&mut out, "  ", "scsi", Some(self.is_scsi));
+        field(&mut out, "  ", "uas", self.is_uas);
+        field(&mut out, "  ", "behind hub", self.behind_hub);
+        field(
+            &mut out,
+            "  ",
+            "special partition",
+            Some(self.special_partition),
+        );
+        field(&mut out, "  ", "ram disk", Some(self.ram_disk));
+        field(&mut out, "  ", "thin provisioned", self.thin_provisioned);
+        field(&mut out, "  ", "self-encrypting", self.self_encrypting);
+        field(&mut out, "  ", "write cache", self.write_cache_enabled);
+        field(&mut out, "  ", "queue count", self.queue_count);
+        field(&mut out, "  ", "queue depth", self.queue_depth);
+        field(
+            &mut out,
+            "  ",
+            "max transfer bytes",
+            self.max_transfer_bytes,
+        );
+        field(
+            &mut out,
+            "  ",
+            "maintenance active",
+            self.maintenance_active,
+        );
+        field(&mut out, "  ", "smart health", self.smart_health);
+        field(
+            &mut out,
+            "  ",
+            "size consistent",
+            Some(self.size_consistent),
+        );
+        field(
+            &mut out,
+            "  ",
+            "enumeration duration",
+            self.enumeration_duration,
+        );
+        field(&mut out, "  ", "error", self.error.as_ref());
+
+        for warning in &self.warnings {
+            let _ = writeln!(out, "  warning: {}", warning);
+        }
+        if !self.backing_devices.is_empty() {
+            let _ = writeln!(
+                out,
+                "  backing devices: {}",
+                self.backing_devices.join(", ")
+            );
+        }
+
+        for mp in &self.mountpoints {
+            let _ = writeln!(out, "  mountpoint {}", mp.path);
+            field(&mut out, "    ", "label", mp.label.as_ref());
+            field(&mut out, "    ", "total bytes", mp.total_bytes);
+            field(&mut out, "    ", "available bytes", mp.available_bytes);
+            field(&mut out, "    ", "case sensitive", mp.case_sensitive);
+            field(&mut out, "    ", "compressed", mp.compressed);
+            field(&mut out, "    ", "user home", mp.is_user_home);
+            for warning in &mp.warnings {
+                let _ = writeln!(out, "    warning: {}", warning);
+            }
+        }
+
+        for partition in &self.partitions {
+            let _ = writeln!(out, "  partition {}", partition.path);
+            field(&mut out, "    ", "name", partition.name.as_ref());
+            field(&mut out, "    ", "label", partition.label.as_ref());
+            field(&mut out, "    ", "type", partition.partition_type.as_ref());
+            field(&mut out, "    ", "offset", partition.offset);
+            field(&mut out, "    ", "size", partition.size);
+            field(&mut out, "    ", "bootable", partition.bootable);
+            field(
+                &mut out,
+                "    ",
+                "aligned physical",
+                partition.aligned_physical,
+            );
+            field(&mut out, "    ", "aligned MiB", partition.aligned_mib);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partition_table_type_round_trip() {
+        for x in [
+            PartitionTableType::Mbr,
+            PartitionTableType::Gpt,
+            PartitionTableType::Raw,
+            PartitionTableType::Unknown,
+        ] {
+            assert_eq!(x.to_string().parse::<PartitionTableType>(), Ok(x));
+        }
+
+        assert_eq!(PartitionTableType::Mbr.to_string(), "mbr");
+        assert_eq!(PartitionTableType::Gpt.to_string(), "gpt");
+        assert_eq!("mbr".parse(), Ok(PartitionTableType::Mbr));
+        assert_eq!("dos".parse(), Ok(PartitionTableType::Mbr));
+        assert_eq!("atari".parse(), Ok(PartitionTableType::Unknown));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn bus_type_serde_round_trip() {
+        let all = [
+            BusType::Unknown,
+            BusType::Scsi,
+            BusType::Atapi,
+            BusType::Ata,
+            BusType::Ieee1394,
+            BusType::Ssa,
+            BusType::Fibre,
+            BusType::Usb,
+            BusType::Raid,
+            BusType::Iscsi,
+            BusType::Sas,
+            BusType::Sata,
+            BusType::Sd,
+            BusType::Mmc,
+            BusType::Virtual,
+            BusType::FileBackedVirtual,
+            BusType::Nvme,
+            BusType::Scm,
+            BusType::Ufs,
+            BusType::Other("THUNDERBOLT".to_string()),
+        ];
+
+        for bus_type in all {
+            let json = serde_json::to_string(&bus_type).unwrap();
+            assert_eq!(json, format!("\"{}\"", bus_type));
+            assert_eq!(serde_json::from_str::<BusType>(&json).unwrap(), bus_type);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn partition_table_type_serde_round_trip() {
+        for (x, json) in [
+            (PartitionTableType::Mbr, "\"mbr\""),
+            (PartitionTableType::Gpt, "\"gpt\""),
+            (PartitionTableType::Raw, "\"raw\""),
+            (PartitionTableType::Unknown, "\"unknown\""),
+        ] {
+            assert_eq!(serde_json::to_string(&x).unwrap(), json);
+            assert_eq!(serde_json::from_str::<PartitionTableType>(json).unwrap(), x);
+        }
+
+        // lsblk's spelling, and values stored before the enum
+        assert_eq!(
+            serde_json::from_str::<PartitionTableType>("\"dos\"").unwrap(),
+            PartitionTableType::Mbr
+        );
+    }
+
+    #[test]
+    fn constructors() {
+        let mountpoint = MountPoint::new("/media/stick");
+        assert_eq!(mountpoint.path, "/media/stick");
+        assert_eq!(mountpoint.label, None);
+        assert!(mountpoint.warnings.is_empty());
+
+        let partition = PartitionInfo::new("/dev/sda1");
+        assert_eq!(partition.path, "/dev/sda1");
+        assert_eq!(partition.size, None);
+        assert!(!partition.is_efi_system_partition());
+
+        let esp = PartitionInfo {
+            partition_type: Some(ESP_PARTITION_TYPE.to_uppercase()),
+            ..PartitionInfo::new("/dev/sda1")
+        };
+        assert!(esp.is_efi_system_partition());
+    }
+
+    #[test]
+    fn normalize_string_borrows_clean_input() {
+        assert!(matches!(
+            normalize_string("SanDisk Ultra"),
+            Cow::Borrowed("SanDisk Ultra")
+        ));
+        assert!(matches!(normalize_string(""), Cow::Borrowed("")));
+    }
+
+    #[test]
+    fn normalize_string_cleans_noisy_input() {
+        assert_eq!(normalize_string(" SanDisk\0  Ultra\t"), "SanDisk Ultra");
+        assert_eq!(normalize_string("USB\nDisk"), "USB Disk");
+        assert_eq!(normalize_string("  "), "");
+    }
+
+    /// Vendor and model as padded by SCSI INQUIRY, joined the way the Linux backend does
+    #[test]
+    fn normalize_strings_cleans_noisy_vendor() {
+        let mut drive = DeviceDescriptor {
+            description: [" ", "ATA     \0\0", "Samsung SSD 860 \n"].join(" "),
+            mountpoints: vec![MountPoint {
+                label: Some("\tEFI  ".to_string()),
+                ..MountPoint::new("/boot/efi")
+            }],
+            partitions: vec![PartitionInfo {
+                name: Some(" \0 ".to_string()),
+                ..PartitionInfo::new("/dev/sda1")
+            }],
+            ..Default::default()
+        };
+        drive.normalize_strings();
+
+        assert_eq!(drive.description, "ATA Samsung SSD 860");
+        assert_eq!(drive.mountpoints[0].label.as_deref(), Some("EFI"));
+        assert_eq!(drive.partitions[0].name, None);
+    }
+
+    #[test]
+    fn ready_usb_stick() {
+        let stick = DeviceDescriptor {
+            device: "/dev/sdb".to_string(),
+            bus_type: Some(BusType::Usb),
+            is_usb: true,
+            is_removable: true,
+            media_type: MediaType::RemovableMedia,
+            media_present: Some(true),
+            ..Default::default()
+        };
+
+        assert_eq!(stick.flash_readiness(), FlashReadiness::Ready);
+        assert!(stick.flash_readiness().is_ready());
+    }
+
+    #[test]
+    fn system_disk_not_ready() {
+        let disk = DeviceDescriptor {
+            device: "/dev/nvme0n1".to_string(),
+            bus_type: Some(BusType::Nvme),
+            is_system: true,
+            media_type: MediaType::FixedMedia,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            disk.flash_readiness(),
+            FlashReadiness::NotReady(vec![
+                "Not removable".to_string(),
+                "System drive".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn virtual_and_ram_disks_not_ready() {
+        let loop_device = DeviceDescriptor {
+            device: "/dev/loop0".to_string(),
+            is_virtual: true,
+            is_removable: true,
+            ..Default::default()
+        };
+        let zram = DeviceDescriptor {
+            device: "/dev/zram0".to_string(),
+            is_virtual: true,
+            is_system: true,
+            ram_disk: true,
+            ..Default::default()
+        };
+
+        assert_eq!(zram.kind(), DriveKind::RamDisk);
+        assert_eq!(
+            loop_device.flash_readiness(),
+            FlashReadiness::NotReady(vec!["Virtual drive".to_string()])
+        );
+        assert_eq!(
+            zram.flash_readiness(),
+            FlashReadiness::NotReady(vec![
+                "Not removable".to_string(),
+                "System drive".to_string(),
+                "RAM disk".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn mounted_not_ready() {
+        let stick = DeviceDescriptor {
+            device: "/dev/sdb".to_string(),
+            is_usb: true,
+            is_removable: true,
+            media_present: Some(true),
+            mountpoints: vec![
+                MountPoint::new("/media/stick"),
+                MountPoint::new("/media/stick2"),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            stick.flash_readiness(),
+            FlashReadiness::NotReady(vec!["Mounted at /media/stick, /media/stick2".to_string()])
+        );
+    }
+
+    #[test]
+    fn card_without_media_not_ready() {
+        let reader = DeviceDescriptor {
+            is_card: true,
+            is_readonly: true,
+            media_present: Some(false),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            reader.flash_readiness(),
+            FlashReadiness::NotReady(vec!["Read-only".to_string(), "No media".to_string()])
+        );
+    }
+
+    fn partition(offset: u64, size: u64) -> PartitionInfo {
+        PartitionInfo {
+            offset: Some(offset),
+            size: Some(size),
+            ..Default::default()
         }
     }
+
+    #[test]
+    fn partitions_exceed_size() {
+        let mut drive = DeviceDescriptor {
+            size: 8 * 1024 * 1024,
+            partitions: vec![
+                partition(1024 * 1024, 4 * 1024 * 1024),
+                partition(5 * 1024 * 1024, 16 * 1024 * 1024),
+            ],
+            ..Default::default()
+        };
+        drive.check_size_consistency();
+
+        assert!(!drive.size_consistent);
+        assert_eq!(drive.warnings.len(), 1);
+    }
+
+    #[test]
+    fn partitions_fit_size() {
+        let mut drive = DeviceDescriptor {
+            size: 8 * 1024 * 1024,
+            partitions: vec![partition(1024 * 1024, 7 * 1024 * 1024)],
+            ..Default::default()
+        };
+        drive.check_size_consistency();
+
+        assert!(drive.size_consistent);
+        assert!(drive.warnings.is_empty());
+    }
+
+    #[test]
+    fn unknown_size_is_not_checked() {
+        let mut drive = DeviceDescriptor {
+            partitions: vec![partition(0, 512)],
+            ..Default::default()
+        };
+        drive.check_size_consistency();
+
+        assert!(drive.size_consistent);
+        assert!(drive.warnings.is_empty());
+    }
+
+    #[test]
+    fn mountpoint_changes() {
+        let old = DeviceDescriptor {
+            mountpoints: vec![MountPoint {
+                available_bytes: Some(1024),
+                ..MountPoint::new("/media/stick")
+            }],
+            ..Default::default()
+        };
+        let written = DeviceDescriptor {
+            mountpoints: vec![MountPoint {
+                available_bytes: Some(512),
+                ..MountPoint::new("/media/stick")
+            }],
+            ..Default::default()
+        };
+        let remounted = DeviceDescriptor {
+            mountpoints: vec![
+                MountPoint::new("/media/stick"),
+                MountPoint::new("/media/stick2"),
+            ],
+            ..Default::default()
+        };
+
+        assert!(!written.mountpoints_changed_since(&old));
+        assert!(remounted.mountpoints_changed_since(&old));
+        assert!(DeviceDescriptor::default().mountpoints_changed_since(&old));
+    }
+
+    #[test]
+    fn partition_alignment() {
+        let mut drive = DeviceDescriptor {
+            block_size: 4096,
+            partitions: vec![
+                partition(1024 * 1024, 1024 * 1024),
+                // Old DOS layout starting at sector 63
+                partition(63 * 512, 1024 * 1024),
+                PartitionInfo::new("/dev/sda3"),
+            ],
+            ..Default::default()
+        };
+        drive.compute_partition_alignment();
+
+        assert_eq!(drive.partitions[0].aligned_physical, Some(true));
+        assert_eq!(drive.partitions[0].aligned_mib, Some(true));
+        assert_eq!(drive.partitions[1].aligned_physical, Some(false));
+        assert_eq!(drive.partitions[1].aligned_mib, Some(false));
+        assert_eq!(drive.partitions[2].aligned_physical, None);
+        assert_eq!(drive.partitions[2].aligned_mib, None);
+    }
+
+    #[test]
+    fn partition_alignment_without_block_size() {
+        let mut drive = DeviceDescriptor {
+            block_size: 0,
+            partitions: vec![partition(1024 * 1024, 1024 * 1024)],
+            ..Default::default()
+        };
+        drive.compute_partition_alignment();
+
+        assert_eq!(drive.partitions[0].aligned_physical, None);
+        assert_eq!(drive.partitions[0].aligned_mib, Some(true));
+    }
+
+    #[test]
+    fn sizes_in_sectors() {
+        let drive = DeviceDescriptor {
+            size: 16 * 1024 * 1024,
+            logical_block_size: 4096,
+            ..Default::default()
+        };
+
+        assert_eq!(drive.sector_size(), 4096);
+        assert_eq!(drive.size_in_sectors(), 4096);
+    }
+
+    #[test]
+    fn sector_size_falls_back_to_512() {
+        let drive = DeviceDescriptor {
+            size: 16 * 1024 * 1024,
+            logical_block_size: 0,
+            ..Default::default()
+        };
+
+        assert_eq!(drive.sector_size(), 512);
+        assert_eq!(drive.size_in_sectors(), 32768);
+    }
+
+    #[test]
+    fn convert_sizes_to_sectors() {
+        let mut drive = DeviceDescriptor {
+            size: 16 * 1024 * 1024,
+            logical_block_size: 0,
+            partitions: vec![partition(1024 * 1024, 4 * 1024 * 1024)],
+            mountpoints: vec![MountPoint {
+                total_bytes: Some(4 * 1024 * 1024),
+                available_bytes: Some(1024),
+                ..MountPoint::new("/media/stick")
+            }],
+            ..Default::default()
+        };
+        drive.convert_sizes_to_sectors();
+
+        assert_eq!(drive.size, 32768);
+        assert_eq!(drive.partitions[0].offset, Some(2048));
+        assert_eq!(drive.partitions[0].size, Some(8192));
+        assert_eq!(drive.mountpoints[0].total_bytes, Some(8192));
+        assert_eq!(drive.mountpoints[0].available_bytes, Some(2));
+    }
+
+    #[test]
+    fn kind_labels() {
+        let label = |drive: DeviceDescriptor| drive.kind_label();
+
+        assert_eq!(
+            label(DeviceDescriptor {
+                bus_type: Some(BusType::Sd),
+                is_removable: true,
+                ..Default::default()
+            }),
+            "SD Card"
+        );
+        assert_eq!(
+            label(DeviceDescriptor {
+                bus_type: Some(BusType::Atapi),
+                ..Default::default()
+            }),
+            "Optical"
+        );
+        assert_eq!(
+            label(DeviceDescriptor {
+                is_virtual: true,
+                ram_disk: true,
+                ..Default::default()
+            }),
+            "RAM Disk"
+        );
+        assert_eq!(
+            label(DeviceDescriptor {
+                bus_type: Some(BusType::Virtual),
+                is_virtual: true,
+                ..Default::default()
+            }),
+            "Virtual Disk"
+        );
+        assert_eq!(
+            label(DeviceDescriptor {
+                bus_type: Some(BusType::Usb),
+                is_removable: true,
+                ..Default::default()
+            }),
+            "USB Drive"
+        );
+        assert_eq!(
+            label(DeviceDescriptor {
+                bus_type: Some(BusType::Nvme),
+                ..Default::default()
+            }),
+            "NVMe SSD"
+        );
+        assert_eq!(
+            label(DeviceDescriptor {
+                bus_type: Some(BusType::Sata),
+                is_removable: true,
+                ..Default::default()
+            }),
+            "Removable Drive"
+        );
+        assert_eq!(
+            label(DeviceDescriptor {
+                bus_type: Some(BusType::Sata),
+                ..Default::default()
+            }),
+            "Internal Drive"
+        );
+    }
+
+    #[test]
+    fn usb_card_reader_is_sd_card() {
+        let reader = DeviceDescriptor {
+            bus_type: Some(BusType::Usb),
+            is_usb: true,
+            is_card: true,
+            ..Default::default()
+        };
+
+        assert_eq!(reader.kind(), DriveKind::SdCard);
+    }
+
+    #[test]
+    fn human_report_contents() {
+        let drive = DeviceDescriptor {
+            device: "/dev/sdb".to_string(),
+            size: 31914983424,
+            bus_type: Some(BusType::Usb),
+            mountpoints: vec![
+                MountPoint {
+                    label: Some("BOOT".to_string()),
+                    ..MountPoint::new("/media/boot")
+                },
+                MountPoint::new("/media/root"),
+            ],
+            partitions: vec![PartitionInfo::new("/dev/sdb1")],
+            ..Default::default()
+        };
+        let report = drive.human_report();
+
+        assert!(report.starts_with("/dev/sdb (USB Drive)\n"));
+        assert!(report.contains("  size: 31914983424\n"));
+        assert!(report.contains("  mountpoint /media/boot\n    label: \"BOOT\"\n"));
+        assert!(report.contains("  mountpoint /media/root\n"));
+        assert!(report.contains("  partition /dev/sdb1\n"));
+        // Unknown fields are skipped
+        assert!(!report.contains("serial"));
+    }
+
+    #[test]
+    fn normalize_mountpoints() {
+        let mut drive = DeviceDescriptor {
+            mountpoints: vec![
+                MountPoint::new("/media/b"),
+                MountPoint::new(""),
+                MountPoint::new("/media/a"),
+                MountPoint::new("/media/b"),
+            ],
+            ..Default::default()
+        };
+        drive.normalize_mountpoints();
+
+        let paths: Vec<_> = drive.mountpoints.iter().map(|x| x.path.as_str()).collect();
+        assert_eq!(paths, ["/media/a", "/media/b"]);
+    }
+
+    #[test]
+    fn platform_of_target() {
+        let expected = if cfg!(target_os = "windows") {
+            Platform::Windows
+        } else if cfg!(target_os = "macos") {
+            Platform::MacOs
+        } else if cfg!(target_os = "linux") {
+            Platform::Linux
+        } else {
+            Platform::Unsupported
+        };
+
+        assert_eq!(Platform::current(), expected);
+        assert_eq!(DeviceDescriptor::default().platform, expected);
+    }
 }
@@ -0,0 +1,111 @@
+use std::{fmt, io, process::ExitStatus};
+
+#[derive(Debug)]
+#[non_exhaustive]
+/// Errors returned while listing drives.
+///
+/// Public functions return `anyhow::Error`, which can be downcast to this type.
+pub enum DriveListError {
+    /// System call or spawning an external tool failed
+    Io(io::Error),
+    /// External tool (e.g. `lsblk` or `diskutil`) exited unsuccessfully
+    Command {
+        program: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+    /// Property list output (macOS `diskutil`) is malformed, e.g. cut off because the tool was
+    /// killed
+    PlistSyntax(String),
+    /// Property list output (macOS `diskutil`) is valid, but doesn't have the expected structure,
+    /// e.g. because a macOS update changed the format
+    PlistFormat(String),
+    /// Partition layout returned by Windows is malformed, e.g. truncated
+    PartitionLayout(String),
+    /// Listing was cancelled through [`DriveListQuery::cancel_token`](crate::DriveListQuery::cancel_token)
+    Cancelled,
+    /// There is no backend for the target platform
+    Unsupported,
+}
+
+impl DriveListError {
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub(crate) fn command(program: impl ToString, output: &std::process::Output) -> Self {
+        Self::Command {
+            program: program.to_string(),
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for DriveListError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+            Self::Command {
+                program,
+                status,
+                stderr,
+            } if stderr.is_empty() => write!(f, "{} failed: {}", program, status),
+            Self::Command {
+                program,
+                status,
+                stderr,
+            } => write!(f, "{} failed: {}: {}", program, status, stderr),
+            Self::PlistSyntax(err) => write!(f, "Malformed property list: {}", err),
+            Self::PlistFormat(err) => write!(f, "Unexpected property list structure: {}", err),
+            Self::PartitionLayout(err) => write!(f, "Malformed partition layout: {}", err),
+            Self::Cancelled => write!(f, "Listing cancelled"),
+            Self::Unsupported => write!(f, "Listing drives is not supported on this platform"),
+        }
+    }
+}
+
+impl std::error::Error for DriveListError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Command { .. }
+            | Self::PlistSyntax(_)
+            | Self::PlistFormat(_)
+            | Self::PartitionLayout(_)
+            | Self::Cancelled
+            | Self::Unsupported => None,
+        }
+    }
+}
+
+impl From<io::Error> for DriveListError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn io_source() {
+        let err = DriveListError::from(io::Error::new(io::ErrorKind::NotFound, "lsblk"));
+        let source = err.source().unwrap().downcast_ref::<io::Error>().unwrap();
+
+        assert_eq!(source.kind(), io::ErrorKind::NotFound);
+        assert_eq!(source.to_string(), "lsblk");
+        assert!(DriveListError::Cancelled.source().is_none());
+    }
+
+    #[test]
+    fn source_through_anyhow() {
+        let err = anyhow::Error::from(DriveListError::Io(io::ErrorKind::PermissionDenied.into()))
+            .context("Couldn't run lsblk");
+        let io = err.chain().find_map(|x| x.downcast_ref::<io::Error>());
+
+        assert_eq!(
+            io.map(io::Error::kind),
+            Some(io::ErrorKind::PermissionDenied)
+        );
+    }
+}
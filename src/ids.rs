@@ -0,0 +1,156 @@
+//! Vendor and product names from the USB and PCI ID databases
+
+use std::fs;
+
+/// Locations of the system USB ID database
+const USB_IDS_PATHS: &[&str] = &[
+    "/usr/share/hwdata/usb.ids",
+    "/usr/share/misc/usb.ids",
+    "/usr/share/usb.ids",
+    "/var/lib/usbutils/usb.ids",
+];
+
+/// Locations of the system PCI ID database
+const PCI_IDS_PATHS: &[&str] = &[
+    "/usr/share/hwdata/pci.ids",
+    "/usr/share/misc/pci.ids",
+    "/usr/share/pci.ids",
+];
+
+/// Common USB storage vendors, used when no database is installed
+const USB_VENDORS: &[(u16, &str)] = &[
+    (0x058f, "Alcor Micro Corp."),
+    (0x05e3, "Genesys Logic, Inc."),
+    (0x0781, "SanDisk Corp."),
+    (0x090c, "Silicon Motion, Inc."),
+    (0x0951, "Kingston Technology"),
+    (0x0bda, "Realtek Semiconductor Corp."),
+    (0x13fe, "Phison Electronics Corp."),
+    (0x152d, "JMicron Technology Corp."),
+    (0x174c, "ASMedia Technology Inc."),
+    (0x8564, "Transcend Information, Inc."),
+];
+
+/// Common PCI storage vendors, used when no database is installed
+const PCI_VENDORS: &[(u16, &str)] = &[
+    (0x126f, "Silicon Motion, Inc."),
+    (0x1344, "Micron Technology Inc"),
+    (0x144d, "Samsung Electronics Co Ltd"),
+    (0x15b7, "Sandisk Corp"),
+    (0x1987, "Phison Electronics Corporation"),
+    (0x1c5c, "SK hynix"),
+    (0x1e0f, "KIOXIA Corporation"),
+    (0x8086, "Intel Corporation"),
+];
+
+pub(crate) fn usb_name(vendor: u16, product: u16) -> Option<String> {
+    lookup(USB_IDS_PATHS, USB_VENDORS, vendor, product)
+}
+
+pub(crate) fn pci_name(vendor: u16, device: u16) -> Option<String> {
+    lookup(PCI_IDS_PATHS, PCI_VENDORS, vendor, device)
+}
+
+fn lookup(paths: &[&str], fallback: &[(u16, &str)], vendor: u16, product: u16) -> Option<String> {
+    paths
+        .iter()
+        .find_map(|x| fs::read_to_string(x).ok())
+        .and_then(|x| parse_ids(&x, vendor, product))
+        .or_else(|| {
+            fallback
+                .iter()
+                .find(|(id, _)| *id == vendor)
+                .map(|(_, name)| name.to_string())
+        })
+}
+
+/// Find `vendor` and `product` in the `usb.ids`/`pci.ids` format.
+///
+/// Vendors are lines starting with a 4 digit hex ID, followed by their products indented with a
+/// single tab. Returns `"<vendor> <product>"`, or just the vendor if the product is unknown.
+fn parse_ids(contents: &str, vendor: u16, product: u16) -> Option<String> {
+    let mut vendor_name: Option<&str> = None;
+
+    for line in contents.lines() {
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+
+        if let Some(line) = line.strip_prefix('\t') {
+            // Interfaces and subsystems are indented twice
+            if vendor_name.is_none() || line.starts_with('\t') {
+                continue;
+            }
+
+            if let Some((id, name)) = parse_id_line(line)
+                && id == product
+            {
+                return vendor_name.map(|x| format!("{} {}", x, name));
+            }
+        } else if vendor_name.is_some() {
+            // Next vendor, so the product is unknown
+            break;
+        } else if let Some((id, name)) = parse_id_line(line)
+            && id == vendor
+        {
+            vendor_name = Some(name);
+        }
+    }
+
+    vendor_name.map(str::to_string)
+}
+
+fn parse_id_line(line: &str) -> Option<(u16, &str)> {
+    let (id, name) = line.split_once("  ")?;
+
+    Some((u16::from_str_radix(id, 16).ok()?, name.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Excerpt of `usb.ids`
+    const USB_IDS: &str = "\
+# List of USB ID's
+0781  SanDisk Corp.
+\t5567  Cruzer Blade
+\t5583  Ultra Fit
+\t\t00  Mass Storage
+0951  Kingston Technology
+\t1666  DataTraveler 100 G3/G4/SE9 G2/50
+";
+
+    #[test]
+    fn vendor_and_product() {
+        assert_eq!(
+            parse_ids(USB_IDS, 0x0781, 0x5583).as_deref(),
+            Some("SanDisk Corp. Ultra Fit")
+        );
+        assert_eq!(
+            parse_ids(USB_IDS, 0x0951, 0x1666).as_deref(),
+            Some("Kingston Technology DataTraveler 100 G3/G4/SE9 G2/50")
+        );
+    }
+
+    #[test]
+    fn unknown_product_is_vendor() {
+        // The interface line of the previous product doesn't match
+        assert_eq!(
+            parse_ids(USB_IDS, 0x0781, 0x0000).as_deref(),
+            Some("SanDisk Corp.")
+        );
+        assert_eq!(parse_ids(USB_IDS, 0x1234, 0x5583), None);
+    }
+
+    #[test]
+    fn lookup_without_database() {
+        let paths = &["/nonexistent/usb.ids"];
+
+        assert_eq!(
+            lookup(paths, USB_VENDORS, 0x0781, 0x5583).as_deref(),
+            Some("SanDisk Corp.")
+        );
+        assert_eq!(lookup(paths, USB_VENDORS, 0x1234, 0x5583), None);
+    }
+}
@@ -4,13 +4,667 @@
 //! - Linux
 //! - Macos
 
+mod balena;
+mod cache;
+mod capacity;
+mod checked;
 mod device;
+mod error;
+#[cfg(all(feature = "ids", target_os = "linux"))]
+mod ids;
+mod list;
 
 mod pal;
+mod probe;
+mod query;
+#[cfg(feature = "table")]
+mod table;
+mod token;
+mod vm;
+mod watch;
 
-pub use device::{DeviceDescriptor, MountPoint};
+pub use cache::CachedEnumerator;
+pub use capacity::CapacityCheck;
+pub use device::{
+    BusType, DeviceDescriptor, DriveKind, FlashReadiness, MediaType, MountPoint, PartitionInfo,
+    PartitionTableType, Platform, SmartHealth,
+};
+pub use error::DriveListError;
+pub use list::DriveList;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub use pal::fuzzing;
+pub use pal::{reset_pal, set_pal, Pal};
+pub use probe::{DeviceProbe, ProbeResult};
+pub use query::DriveListQuery;
+#[cfg(feature = "table")]
+pub use table::render_table;
+pub use token::ChangeToken;
+pub use watch::{changed_fields, ChangedField, ChangedFields, DriveEvent, DriveWatcher};
+
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    time::{Duration, Instant},
+};
 
 /// Get a list of all drives
 pub fn drive_list() -> anyhow::Result<Vec<DeviceDescriptor>> {
-    pal::drive_list()
+    DriveListQuery::new().list()
+}
+
+/// Windows device path of physical drive `n`, e.g. `\\.\PhysicalDrive1`
+pub fn physical_drive_to_device_path(n: u32) -> String {
+    dos_device_path("PhysicalDrive", n)
+}
+
+/// Number of the physical drive at a Windows device path.
+///
+/// Accepts both the `\\.\PhysicalDriveN` and `\\?\PhysicalDriveN` forms, ignoring case.
+pub fn device_path_to_physical_drive(path: &str) -> Option<u32> {
+    let name = path
+        .strip_prefix(r"\\.\")
+        .or_else(|| path.strip_prefix(r"\\?\"))?;
+    let (prefix, n) = name.split_at_checked("PhysicalDrive".len())?;
+
+    if !prefix.eq_ignore_ascii_case("PhysicalDrive") || !n.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+
+    n.parse().ok()
+}
+
+/// Path of a device in the Win32 device namespace, e.g. `\\.\CdRom0`
+pub(crate) fn dos_device_path(prefix: &str, n: u32) -> String {
+    format!(r"\\.\{}{}", prefix, n)
+}
+
+/// Get all mountpoints of all drives, each paired with the `device` it belongs to
+pub fn list_mountpoints() -> anyhow::Result<Vec<(String, MountPoint)>> {
+    Ok(drive_list()?
+        .into_iter()
+        .flat_map(|drive| {
+            let device = drive.device;
+            drive
+                .mountpoints
+                .into_iter()
+                .map(move |mp| (device.clone(), mp))
+        })
+        .collect())
+}
+
+/// Find the drive with the given serial number.
+///
+/// Serials are compared ignoring case and surrounding whitespace. Drives without a serial never
+/// match.
+pub fn find_by_serial(serial: &str) -> anyhow::Result<Option<DeviceDescriptor>> {
+    let serial = serial.trim();
+
+    Ok(drive_list()?.into_iter().find(|x| {
+        x.serial
+            .as_deref()
+            .is_some_and(|x| x.trim().eq_ignore_ascii_case(serial))
+    }))
+}
+
+/// Get the removable drives which are not in `baseline`, matching drives by `device`.
+///
+/// Meant for "insert the card now" flows: take a baseline with [`drive_list`] before asking the
+/// user to insert the medium, then call this until it returns something.
+pub fn new_removable_since(baseline: &[DeviceDescriptor]) -> anyhow::Result<Vec<DeviceDescriptor>> {
+    Ok(DriveEvent::diff(baseline, &drive_list()?)
+        .into_iter()
+        .filter_map(|x| match x {
+            DriveEvent::Added(drive) if drive.is_removable => Some(drive),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Get the USB drives, never including a system drive.
+///
+/// A drive counts as USB if `is_usb` is set or its bus type is [`BusType::Usb`]. The macOS
+/// backend currently sets neither, so this returns no drives there.
+pub fn drive_list_usb() -> anyhow::Result<Vec<DeviceDescriptor>> {
+    let mut drives = DriveListQuery::new().list()?;
+
+    drives.retain(|x| (x.is_usb || x.bus_type == Some(BusType::Usb)) && !x.is_system);
+
+    Ok(drives)
+}
+
+/// Installed capacity in bytes, counting each physical drive once.
+///
+/// Virtual drives are skipped, since their bytes are stored on another drive. This includes the
+/// synthesized APFS container disks on macOS, loop devices and disk images.
+pub fn total_physical_capacity(devices: &[DeviceDescriptor]) -> u64 {
+    devices
+        .iter()
+        .filter(|x| !x.is_virtual)
+        .map(|x| x.size)
+        .fold(0, u64::saturating_add)
+}
+
+/// Group drives by bus type.
+///
+/// Drives without a bus type are grouped under [`BusType::Unknown`].
+pub fn group_by_bus(devices: &[DeviceDescriptor]) -> BTreeMap<BusType, Vec<&DeviceDescriptor>> {
+    let mut groups: BTreeMap<BusType, Vec<&DeviceDescriptor>> = BTreeMap::new();
+
+    for device in devices {
+        groups
+            .entry(device.bus_type.clone().unwrap_or(BusType::Unknown))
+            .or_default()
+            .push(device);
+    }
+
+    groups
+}
+
+/// Serialize `drives` to the JSON emitted by Balena's Node `drivelist`, with the same field names,
+/// order and types
+pub fn to_balena_json(drives: &[DeviceDescriptor]) -> String {
+    balena::to_json(drives)
+}
+
+/// Get a list of all drives along with backend diagnostics.
+///
+/// Diagnostics cover problems that are not tied to a single device's `error`, such as devices
+/// that were skipped, handles that failed to open or missing privileges.
+pub fn list_with_warnings() -> anyhow::Result<(Vec<DeviceDescriptor>, Vec<String>)> {
+    DriveListQuery::new().list_with_warnings()
+}
+
+/// Get a list of all drives in an existing device information set.
+///
+/// Useful when the caller already holds a device information set from a broader enumeration.
+/// The set must be created with `DIGCF_DEVICEINTERFACE` for `GUID_DEVINTERFACE_DISK`.
+///
+/// # Safety
+///
+/// `h_device_info` must be a valid device information set. It is not destroyed, so the caller is
+/// still responsible for calling `SetupDiDestroyDeviceInfoList`.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::ptr::{null, null_mut};
+/// use winapi::um::{
+///     handleapi::INVALID_HANDLE_VALUE,
+///     setupapi::{
+///         SetupDiDestroyDeviceInfoList, SetupDiGetClassDevsW, DIGCF_DEVICEINTERFACE, DIGCF_PRESENT,
+///     },
+///     winioctl::GUID_DEVINTERFACE_DISK,
+/// };
+///
+/// unsafe {
+///     let h_device_info = SetupDiGetClassDevsW(
+///         &GUID_DEVINTERFACE_DISK,
+///         null(),
+///         null_mut(),
+///         DIGCF_PRESENT | DIGCF_DEVICEINTERFACE,
+///     );
+///     assert_ne!(h_device_info, INVALID_HANDLE_VALUE);
+///
+///     for drive in bb_drivelist::drive_list_from_device_info(h_device_info) {
+///         println!("{}: {}", drive.device, drive.description);
+///     }
+///
+///     SetupDiDestroyDeviceInfoList(h_device_info);
+/// }
+/// ```
+#[cfg(target_os = "windows")]
+pub unsafe fn drive_list_from_device_info(
+    h_device_info: winapi::um::setupapi::HDEVINFO,
+) -> Vec<DeviceDescriptor> {
+    let mut drives = unsafe {
+        pal::drive_list_from_device_info(
+            h_device_info,
+            &winapi::um::winioctl::GUID_DEVINTERFACE_DISK,
+            Default::default(),
+            false,
+            None,
+            &mut Vec::new(),
+        )
+    };
+    pal::finish(&mut drives);
+
+    drives
+}
+
+/// Wait until a drive matching `predicate` appears.
+///
+/// Drives are polled every `poll` until one matches or `timeout` elapses, in which case `None` is
+/// returned.
+pub fn wait_for_device(
+    predicate: impl Fn(&DeviceDescriptor) -> bool,
+    timeout: Duration,
+    poll: Duration,
+) -> anyhow::Result<Option<DeviceDescriptor>> {
+    let start = Instant::now();
+
+    loop {
+        if let Some(drive) = drive_list()?.into_iter().find(|x| predicate(x)) {
+            return Ok(Some(drive));
+        }
+
+        let elapsed = start.elapsed();
+
+        if elapsed >= timeout {
+            return Ok(None);
+        }
+
+        std::thread::sleep(poll.min(timeout - elapsed));
+    }
+}
+
+/// Get the changes to the drive list since `token` was returned, along with a new token.
+///
+/// On Linux the kernel's uevent sequence number and the mount table are checked first, so polling
+/// an unchanged system doesn't list the drives again. Other platforms always list the drives and
+/// compare them with the token's.
+pub fn drive_list_since(token: ChangeToken) -> anyhow::Result<(Vec<DriveEvent>, ChangeToken)> {
+    token::drive_list_since(token)
+}
+
+/// Watch for drives being added, removed or changed.
+///
+/// Shorthand for [`DriveWatcher::new`] with the default query. The returned iterator blocks until
+/// the next event.
+pub fn watch() -> anyhow::Result<DriveWatcher> {
+    DriveWatcher::new(DriveListQuery::new())
+}
+
+/// Get the drive backing the running executable.
+///
+/// Tools running from a live USB can use this to avoid offering to overwrite their own medium.
+/// Returns `None` if the executable is not on any listed drive (e.g. network or overlay mounts).
+pub fn current_install_medium() -> anyhow::Result<Option<String>> {
+    let exe = std::env::current_exe()?.canonicalize()?;
+    let drives = drive_list()?;
+
+    Ok(mountpoint_for_path(&drives, &exe).map(|(drive, _)| drive.device.clone()))
+}
+
+/// Find the drive and mountpoint containing `path`, preferring the most specific mountpoint
+fn mountpoint_for_path<'a>(
+    drives: &'a [DeviceDescriptor],
+    path: &Path,
+) -> Option<(&'a DeviceDescriptor, &'a MountPoint)> {
+    // Canonical paths on Windows use the verbatim `\\?\` prefix, mountpoints don't
+    let path = path.to_string_lossy();
+    let path = Path::new(path.strip_prefix(r"\\?\").unwrap_or(&path));
+
+    drives
+        .iter()
+        .flat_map(|drive| drive.mountpoints.iter().map(move |mp| (drive, mp)))
+        .filter(|(_, mp)| !mp.path.is_empty() && path.starts_with(&mp.path))
+        .max_by_key(|(_, mp)| mp.path.len())
+}
+
+/// Flag the mountpoint containing the current user's home directory
+pub(crate) fn mark_user_home(drives: &mut [DeviceDescriptor]) {
+    #[cfg(target_os = "windows")]
+    const HOME: &str = "USERPROFILE";
+    #[cfg(not(target_os = "windows"))]
+    const HOME: &str = "HOME";
+
+    if let Some(home) = std::env::var_os(HOME).and_then(|x| Path::new(&x).canonicalize().ok()) {
+        mark_home(drives, &home);
+    }
+}
+
+/// Flag the mountpoint containing `home`, clearing the flag of all others
+fn mark_home(drives: &mut [DeviceDescriptor], home: &Path) {
+    let home_mount = mountpoint_for_path(drives, home)
+        .map(|(drive, mp)| (drive.device.clone(), mp.path.clone()));
+
+    for drive in drives.iter_mut() {
+        for mp in drive.mountpoints.iter_mut() {
+            mp.is_user_home = Some(
+                home_mount
+                    .as_ref()
+                    .is_some_and(|(device, path)| *device == drive.device && *path == mp.path),
+            );
+        }
+    }
+}
+
+/// Report which platform queries succeed for `device` (e.g. `\\.\PhysicalDrive1` or `/dev/sdb`).
+///
+/// Meant for diagnosing missing fields, such as an empty serial. Only fails if the device can't be
+/// opened at all.
+pub fn probe_capabilities(device: &str) -> anyhow::Result<DeviceProbe> {
+    Ok(DeviceProbe {
+        device: device.to_string(),
+        results: pal::probe_capabilities(device)?,
+    })
+}
+
+/// Check whether `device` (e.g. `/dev/sdb`) really has the capacity it reports, by reading its
+/// last claimed sector.
+///
+/// Meant for spotting fake capacity SD cards and USB sticks. Only reads from the device, but
+/// needs the same privileges as opening the raw device for reading. This is never done during
+/// normal enumeration.
+pub fn verify_capacity_readonly(device: &str) -> anyhow::Result<CapacityCheck> {
+    let drive = drive_list()?
+        .into_iter()
+        .find(|x| x.device == device)
+        .ok_or_else(|| anyhow::Error::msg(format!("No such drive: {}", device)))?;
+
+    capacity::verify(&drive)
+}
+
+/// Check whether the drive at `device` (e.g. `/dev/sda`) is spun down, without waking it up.
+///
+/// Uses `GetDevicePowerState` on Windows and ATA CHECK POWER MODE on Linux, which needs the
+/// privileges to send ATA commands. Not supported on macOS.
+pub fn is_spun_down(device: &str) -> anyhow::Result<bool> {
+    pal::is_spun_down(device)
+}
+
+/// Check if the process has enough privileges to query raw devices.
+///
+/// On Windows this means an elevated token. On Linux and macOS the process must either be root
+/// or a member of the group owning the raw block devices (`disk` and `operator` respectively).
+/// Without these privileges, devices are still listed but some fields will be missing.
+pub fn has_required_privileges() -> bool {
+    pal::has_required_privileges()
+}
+
+/// Check if the host is running inside a virtual machine.
+///
+/// Uses the CPUID hypervisor bit on x86 and the DMI system vendor on Linux.
+pub fn running_in_vm() -> bool {
+    vm::running_in_vm()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pal::{with_pal, Scripted};
+
+    fn device(name: &str, bus_type: Option<BusType>) -> DeviceDescriptor {
+        DeviceDescriptor {
+            device: name.to_string(),
+            bus_type,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn group_by_bus_buckets() {
+        let devices = [
+            device("sda", Some(BusType::Usb)),
+            device("nvme0n1", Some(BusType::Nvme)),
+            device("sdb", Some(BusType::Usb)),
+            device("sdc", Some(BusType::Sata)),
+            device("nvme1n1", Some(BusType::Nvme)),
+            device("sdd", Some(BusType::Usb)),
+            device("loop0", None),
+            device("sde", Some(BusType::Unknown)),
+        ];
+
+        let groups = group_by_bus(&devices);
+        let sizes: Vec<_> = groups.iter().map(|(k, v)| (k.clone(), v.len())).collect();
+
+        assert_eq!(
+            sizes,
+            [
+                (BusType::Unknown, 2),
+                (BusType::Usb, 3),
+                (BusType::Sata, 1),
+                (BusType::Nvme, 2)
+            ]
+        );
+        assert_eq!(groups[&BusType::Sata][0].device, "sdc");
+    }
+
+    #[test]
+    fn physical_drive_round_trip() {
+        assert_eq!(physical_drive_to_device_path(1), r"\\.\PhysicalDrive1");
+        assert_eq!(
+            device_path_to_physical_drive(r"\\.\PhysicalDrive1"),
+            Some(1)
+        );
+
+        for n in [0, 1, 10, u32::MAX] {
+            assert_eq!(
+                device_path_to_physical_drive(&physical_drive_to_device_path(n)),
+                Some(n)
+            );
+        }
+    }
+
+    #[test]
+    fn physical_drive_forms() {
+        assert_eq!(
+            device_path_to_physical_drive(r"\\?\PhysicalDrive2"),
+            Some(2)
+        );
+        assert_eq!(
+            device_path_to_physical_drive(r"\\.\physicaldrive3"),
+            Some(3)
+        );
+        assert_eq!(device_path_to_physical_drive(r"\\.\PhysicalDrive"), None);
+        assert_eq!(device_path_to_physical_drive(r"\\.\PhysicalDrive+1"), None);
+        assert_eq!(device_path_to_physical_drive(r"\\.\CdRom0"), None);
+        assert_eq!(device_path_to_physical_drive("PhysicalDrive1"), None);
+    }
+
+    #[test]
+    fn wait_for_inserted_device() {
+        let stick = DeviceDescriptor {
+            is_removable: true,
+            ..device("/dev/sdb", Some(BusType::Usb))
+        };
+        // Inserted after the second poll
+        let pal = Scripted::new(vec![vec![], vec![], vec![stick]]);
+
+        let start = Instant::now();
+        let found = with_pal(pal, || {
+            wait_for_device(
+                |x| x.is_removable,
+                Duration::from_secs(60),
+                Duration::from_millis(10),
+            )
+        })
+        .unwrap();
+
+        assert_eq!(found.map(|x| x.device).as_deref(), Some("/dev/sdb"));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn wait_for_device_timeout() {
+        let pal = Scripted::new(vec![vec![device("/dev/sda", Some(BusType::Sata))]]);
+
+        let found = with_pal(pal, || {
+            wait_for_device(
+                |x| x.is_removable,
+                Duration::from_millis(30),
+                Duration::from_millis(10),
+            )
+        })
+        .unwrap();
+
+        assert!(found.is_none());
+    }
+
+    fn with_serial(name: &str, serial: Option<&str>) -> DeviceDescriptor {
+        DeviceDescriptor {
+            serial: serial.map(str::to_string),
+            ..device(name, None)
+        }
+    }
+
+    #[test]
+    fn find_known_serial() {
+        let pal = Scripted::new(vec![vec![
+            with_serial("/dev/sda", None),
+            with_serial("/dev/sdb", Some(" 4C530001230817115375 ")),
+            with_serial("/dev/sdc", Some("S4EWNX0N123456")),
+        ]]);
+
+        let found = with_pal(pal, || find_by_serial("4c530001230817115375")).unwrap();
+
+        assert_eq!(found.map(|x| x.device).as_deref(), Some("/dev/sdb"));
+    }
+
+    #[test]
+    fn find_unknown_serial() {
+        let pal = Scripted::new(vec![vec![
+            with_serial("/dev/sda", None),
+            with_serial("/dev/sdb", Some("4C530001230817115375")),
+        ]]);
+
+        assert!(with_pal(pal, || find_by_serial("unknown"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn new_usb_stick() {
+        let internal = device("/dev/sda", Some(BusType::Sata));
+        let reader = DeviceDescriptor {
+            is_removable: true,
+            ..device("/dev/sdb", Some(BusType::Usb))
+        };
+        let stick = DeviceDescriptor {
+            is_removable: true,
+            ..device("/dev/sdc", Some(BusType::Usb))
+        };
+        let baseline = [internal.clone(), reader.clone()];
+        // A new internal drive isn't removable, so it isn't reported
+        let pal = Scripted::new(vec![vec![
+            internal,
+            reader,
+            stick,
+            device("/dev/nvme0n1", Some(BusType::Nvme)),
+        ]]);
+
+        let new = with_pal(pal, || new_removable_since(&baseline)).unwrap();
+
+        assert_eq!(
+            new.iter().map(|x| x.device.as_str()).collect::<Vec<_>>(),
+            ["/dev/sdc"]
+        );
+    }
+
+    #[test]
+    fn flattened_mountpoints() {
+        let drives = vec![
+            DeviceDescriptor {
+                mountpoints: vec![MountPoint::new("/"), MountPoint::new("/home")],
+                ..device("/dev/sda", Some(BusType::Sata))
+            },
+            device("/dev/sdb", Some(BusType::Usb)),
+            DeviceDescriptor {
+                mountpoints: vec![MountPoint::new("/media/stick")],
+                ..device("/dev/sdc", Some(BusType::Usb))
+            },
+        ];
+        let expected: usize = drives.iter().map(|x| x.mountpoints.len()).sum();
+
+        let mountpoints = with_pal(Scripted::new(vec![drives]), list_mountpoints).unwrap();
+
+        assert_eq!(mountpoints.len(), expected);
+        assert_eq!(
+            mountpoints
+                .iter()
+                .map(|(device, mp)| (device.as_str(), mp.path.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                ("/dev/sda", "/"),
+                ("/dev/sda", "/home"),
+                ("/dev/sdc", "/media/stick")
+            ]
+        );
+    }
+
+    fn mounted(name: &str, paths: &[&str]) -> DeviceDescriptor {
+        DeviceDescriptor {
+            mountpoints: paths.iter().map(MountPoint::new).collect(),
+            ..device(name, None)
+        }
+    }
+
+    #[test]
+    fn home_mountpoint() {
+        let mut drives = [
+            mounted("/dev/sda", &["/", "/home"]),
+            mounted("/dev/sdb", &["/media/stick"]),
+        ];
+
+        mark_home(&mut drives, Path::new("/home/user"));
+
+        let flags: Vec<_> = drives
+            .iter()
+            .flat_map(|x| &x.mountpoints)
+            .map(|x| (x.path.as_str(), x.is_user_home))
+            .collect();
+        assert_eq!(
+            flags,
+            [
+                ("/", Some(false)),
+                ("/home", Some(true)),
+                ("/media/stick", Some(false))
+            ]
+        );
+    }
+
+    #[test]
+    fn mountpoint_for_nested_path() {
+        let drives = [
+            mounted("/dev/sda", &["/", "/home"]),
+            mounted("/dev/sdb", &["/home/user/stick", ""]),
+        ];
+        let find = |path: &str| {
+            mountpoint_for_path(&drives, Path::new(path))
+                .map(|(drive, mp)| (drive.device.as_str(), mp.path.as_str()))
+        };
+
+        assert_eq!(find("/etc/fstab"), Some(("/dev/sda", "/")));
+        assert_eq!(find("/home/user/.bashrc"), Some(("/dev/sda", "/home")));
+        assert_eq!(
+            find("/home/user/stick/a"),
+            Some(("/dev/sdb", "/home/user/stick"))
+        );
+        // Not a path component boundary
+        assert_eq!(find("/homework"), Some(("/dev/sda", "/")));
+    }
+
+    #[test]
+    fn install_medium() {
+        let exe = std::env::current_exe().unwrap().canonicalize().unwrap();
+        let dir = exe.parent().unwrap().to_string_lossy();
+        let dir = dir.strip_prefix(r"\\?\").unwrap_or(&dir).to_string();
+        // `/` or the drive letter root
+        let root: String = if cfg!(target_os = "windows") {
+            dir.chars().take(3).collect()
+        } else {
+            "/".to_string()
+        };
+        let pal = Scripted::new(vec![
+            vec![
+                mounted("/dev/sda", &[&root]),
+                mounted("/dev/sdb", &["/media/stick"]),
+            ],
+            vec![mounted("/dev/sda", &[&root]), mounted("/dev/sdb", &[&dir])],
+            vec![mounted("/dev/sdb", &["/media/stick"])],
+        ]);
+
+        let (system, live_usb, elsewhere) = with_pal(pal, || {
+            (
+                current_install_medium().unwrap(),
+                current_install_medium().unwrap(),
+                current_install_medium().unwrap(),
+            )
+        });
+
+        assert_eq!(system.as_deref(), Some("/dev/sda"));
+        assert_eq!(live_usb.as_deref(), Some("/dev/sdb"));
+        assert_eq!(elsewhere, None);
+    }
 }
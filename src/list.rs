@@ -0,0 +1,250 @@
+use std::path::Path;
+
+use crate::DeviceDescriptor;
+
+#[derive(Default, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+/// List of drives which behaves like the `Vec` it wraps, e.g. `DriveList::from(drive_list()?)`
+pub struct DriveList(Vec<DeviceDescriptor>);
+
+impl DriveList {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, DeviceDescriptor> {
+        self.0.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, DeviceDescriptor> {
+        self.0.iter_mut()
+    }
+
+    /// Serialize to the JSON emitted by Balena's Node `drivelist`, with the same field names,
+    /// order and types
+    pub fn to_balena_json(&self) -> String {
+        crate::balena::to_json(&self.0)
+    }
+
+    /// Add a warning to each mountpoint nested within a mountpoint of another drive.
+    ///
+    /// Listing already does this, it is only needed for lists assembled by hand.
+    pub fn flag_nested_mountpoints(&mut self) {
+        flag_nested_mountpoints(&mut self.0);
+    }
+}
+
+/// Warn about mountpoints nested within a mountpoint of another drive (nested or bind mounts), so
+/// their capacity isn't counted twice.
+///
+/// Everything is nested within the root filesystem (`/` or `C:\`), so mountpoints at a root are
+/// not considered parents.
+pub(crate) fn flag_nested_mountpoints(drives: &mut [DeviceDescriptor]) {
+    let mounts: Vec<(usize, String)> = drives
+        .iter()
+        .enumerate()
+        .flat_map(|(i, drive)| drive.mountpoints.iter().map(move |mp| (i, mp.path.clone())))
+        .filter(|(_, path)| Path::new(path).parent().is_some())
+        .collect();
+
+    for (i, drive) in drives.iter_mut().enumerate() {
+        for mp in drive.mountpoints.iter_mut() {
+            let parent = mounts
+                .iter()
+                .filter(|(j, path)| {
+                    *j != i && *path != mp.path && Path::new(&mp.path).starts_with(path)
+                })
+                .max_by_key(|(_, path)| path.len());
+
+            if let Some((_, path)) = parent {
+                let warning = format!("nested in {}", path);
+
+                if !mp.warnings.contains(&warning) {
+                    mp.warnings.push(warning);
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for DriveList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<Vec<DeviceDescriptor>> for DriveList {
+    fn from(value: Vec<DeviceDescriptor>) -> Self {
+        Self(value)
+    }
+}
+
+impl From<DriveList> for Vec<DeviceDescriptor> {
+    fn from(value: DriveList) -> Self {
+        value.0
+    }
+}
+
+impl std::ops::Deref for DriveList {
+    type Target = [DeviceDescriptor];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for DriveList {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl AsRef<[DeviceDescriptor]> for DriveList {
+    fn as_ref(&self) -> &[DeviceDescriptor] {
+        &self.0
+    }
+}
+
+impl FromIterator<DeviceDescriptor> for DriveList {
+    fn from_iter<T: IntoIterator<Item = DeviceDescriptor>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for DriveList {
+    type Item = DeviceDescriptor;
+    type IntoIter = std::vec::IntoIter<DeviceDescriptor>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a DriveList {
+    type Item = &'a DeviceDescriptor;
+    type IntoIter = std::slice::Iter<'a, DeviceDescriptor>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut DriveList {
+    type Item = &'a mut DeviceDescriptor;
+    type IntoIter = std::slice::IterMut<'a, DeviceDescriptor>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MountPoint;
+
+    fn list(devices: &[&str]) -> DriveList {
+        devices
+            .iter()
+            .map(|x| DeviceDescriptor {
+                device: x.to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn iterate() {
+        let mut drives = list(&["/dev/sda", "/dev/sdb"]);
+        assert_eq!(drives.len(), 2);
+        assert!(!drives.is_empty());
+
+        for drive in &mut drives {
+            drive.device.push('1');
+        }
+
+        let names: Vec<_> = (&drives).into_iter().map(|x| x.device.as_str()).collect();
+        assert_eq!(names, ["/dev/sda1", "/dev/sdb1"]);
+
+        let owned: Vec<DeviceDescriptor> = drives.into_iter().collect();
+        assert_eq!(owned.len(), 2);
+    }
+
+    #[test]
+    fn convert() {
+        let drives = list(&["/dev/sda"]);
+        assert_eq!(format!("{:?}", drives), format!("{:?}", drives.as_ref()));
+
+        let vec = Vec::from(drives.clone());
+        assert_eq!(DriveList::from(vec).as_ref()[0].device, "/dev/sda");
+        assert!(DriveList::default().is_empty());
+    }
+
+    #[test]
+    fn slice_methods() {
+        let mut drives = list(&["/dev/sdb", "/dev/sda"]);
+        drives.sort_by(|a, b| a.device.cmp(&b.device));
+
+        assert_eq!(drives[0].device, "/dev/sda");
+        assert_eq!(drives.first().map(|x| x.device.as_str()), Some("/dev/sda"));
+        assert_eq!(drives.last().map(|x| x.device.as_str()), Some("/dev/sdb"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_transparent() {
+        let drives = list(&["/dev/sda"]);
+        let json = serde_json::to_string(&drives).unwrap();
+
+        assert_eq!(json, serde_json::to_string(drives.as_ref()).unwrap());
+        assert!(json.starts_with("[{"));
+        assert_eq!(
+            serde_json::from_str::<DriveList>(&json).unwrap()[0].device,
+            "/dev/sda"
+        );
+    }
+
+    fn mounted(device: &str, paths: &[&str]) -> DeviceDescriptor {
+        DeviceDescriptor {
+            device: device.to_string(),
+            mountpoints: paths.iter().map(MountPoint::new).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn nested_mount() {
+        let mut drives = DriveList::from(vec![
+            mounted("/dev/sda", &["/", "/srv"]),
+            mounted("/dev/sdb", &["/srv/backup"]),
+            mounted("/dev/sdc", &["/media/stick"]),
+        ]);
+        drives.flag_nested_mountpoints();
+        // Flagging again doesn't repeat the warning
+        drives.flag_nested_mountpoints();
+
+        let warnings: Vec<_> = drives
+            .iter()
+            .flat_map(|x| &x.mountpoints)
+            .map(|x| (x.path.as_str(), x.warnings.clone()))
+            .collect();
+
+        assert_eq!(
+            warnings,
+            [
+                ("/", vec![]),
+                ("/srv", vec![]),
+                ("/srv/backup", vec!["nested in /srv".to_string()]),
+                ("/media/stick", vec![]),
+            ]
+        );
+    }
+}
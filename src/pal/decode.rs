@@ -0,0 +1,575 @@
+//! Decoding of the strings, flags and property descriptors returned by the Windows APIs.
+//!
+//! Like the drive layout and topology, everything here works on plain bytes and values, so it can
+//! be tested on any platform.
+
+use std::str::from_utf8;
+
+use crate::{BusType, DeviceDescriptor, MediaType, MountPoint};
+
+/// Size of `STORAGE_DEVICE_NUMBER`
+pub(crate) const DEVICE_NUMBER_SIZE: usize = 12;
+/// Size of `VOLUME_DISK_EXTENTS`, with room for one extent
+pub(crate) const DISK_EXTENTS_SIZE: usize = 32;
+/// Offset of `Extents[0].DiskNumber` in `VOLUME_DISK_EXTENTS`
+pub(crate) const DISK_EXTENTS_NUMBER_OFFSET: usize = 8;
+/// `FILE_DEVICE_DISK` device type
+pub(crate) const FILE_DEVICE_DISK: u32 = 7;
+
+/// Size of `DISK_GEOMETRY_EX`
+pub(crate) const GEOMETRY_SIZE: usize = 40;
+/// Offset of `Geometry.MediaType` in `DISK_GEOMETRY_EX`
+pub(crate) const GEOMETRY_MEDIA_TYPE_OFFSET: usize = 8;
+/// Offset of `Geometry.BytesPerSector` in `DISK_GEOMETRY_EX`
+pub(crate) const GEOMETRY_SECTOR_OFFSET: usize = 20;
+/// Offset of `DiskSize` in `DISK_GEOMETRY_EX`
+pub(crate) const GEOMETRY_SIZE_OFFSET: usize = 24;
+/// `RemovableMedia` of `MEDIA_TYPE`
+pub(crate) const REMOVABLE_MEDIA: u32 = 11;
+
+/// Offset of `SerialNumberOffset` in `STORAGE_DEVICE_DESCRIPTOR`
+pub(crate) const SERIAL_NUMBER_OFFSET: usize = 24;
+
+/// Size of `STORAGE_ADAPTER_DESCRIPTOR`
+pub(crate) const ADAPTER_SIZE: usize = 32;
+/// Offset of `MaximumTransferLength` in `STORAGE_ADAPTER_DESCRIPTOR`
+pub(crate) const MAX_TRANSFER_OFFSET: usize = 8;
+/// Offset of `CommandQueueing` in `STORAGE_ADAPTER_DESCRIPTOR`
+pub(crate) const COMMAND_QUEUEING_OFFSET: usize = 22;
+/// Offset of `BusType` in `STORAGE_ADAPTER_DESCRIPTOR`
+pub(crate) const BUS_TYPE_OFFSET: usize = 24;
+/// Offset of `BusMajorVersion` in `STORAGE_ADAPTER_DESCRIPTOR`, followed by `BusMinorVersion`
+pub(crate) const BUS_VERSION_OFFSET: usize = 26;
+
+type StorageBusType = u32;
+const BUS_TYPE_UNKNOWN: StorageBusType = 0;
+const BUS_TYPE_SCSI: StorageBusType = 1;
+const BUS_TYPE_ATAPI: StorageBusType = 2;
+const BUS_TYPE_ATA: StorageBusType = 3;
+const BUS_TYPE1394: StorageBusType = 4;
+const BUS_TYPE_SSA: StorageBusType = 5;
+const BUS_TYPE_FIBRE: StorageBusType = 6;
+const BUS_TYPE_USB: StorageBusType = 7;
+const BUS_TYPE_RAID: StorageBusType = 8;
+const BUS_TYPEI_SCSI: StorageBusType = 9;
+const BUS_TYPE_SAS: StorageBusType = 10;
+const BUS_TYPE_SATA: StorageBusType = 11;
+const BUS_TYPE_SD: StorageBusType = 12;
+const BUS_TYPE_MMC: StorageBusType = 13;
+const BUS_TYPE_VIRTUAL: StorageBusType = 14;
+const BUS_TYPE_FILE_BACKED_VIRTUAL: StorageBusType = 15;
+//const BusTypeSpaces:STORAGE_BUS_TYPE=16;
+const BUS_TYPE_NVME: StorageBusType = 17;
+const BUS_TYPE_SCM: StorageBusType = 18;
+const BUS_TYPE_UFS: StorageBusType = 19;
+//const BusTypeMax:STORAGE_BUS_TYPE=20;
+//const BusTypeMaxReserved:STORAGE_BUS_TYPE=0x7F;
+
+/// Offset of `BytesPerCacheLine` in `STORAGE_ACCESS_ALIGNMENT_DESCRIPTOR`, followed by
+/// `BytesOffsetForCacheAlignment`
+pub(crate) const CACHE_LINE_OFFSET: usize = 8;
+/// Offset of `BytesPerLogicalSector` in `STORAGE_ACCESS_ALIGNMENT_DESCRIPTOR`, followed by
+/// `BytesPerPhysicalSector` and `BytesOffsetForSectorAlignment`
+pub(crate) const LOGICAL_SECTOR_OFFSET: usize = 16;
+/// Size of `STORAGE_ACCESS_ALIGNMENT_DESCRIPTOR`
+pub(crate) const ACCESS_ALIGNMENT_SIZE: usize = 28;
+
+pub(crate) fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+pub(crate) fn ansi_to_string(unsafe_utf8: &[u8]) -> String {
+    match from_utf8(
+        &unsafe_utf8
+            .iter()
+            .filter(|c| **c != 0)
+            .copied()
+            .collect::<Vec<u8>>() as _,
+    ) {
+        Err(err) => {
+            println!("Error {}", err);
+            "".to_string()
+        }
+        Ok(res) => res.trim().to_string(),
+    }
+}
+
+/// Decode a NUL terminated UTF-16 buffer, as written by the `W` APIs.
+pub(crate) fn wide_to_string(wide: &[u16]) -> String {
+    let len = wide.iter().position(|c| *c == 0).unwrap_or(wide.len());
+
+    String::from_utf16_lossy(&wide[..len]).trim().to_string()
+}
+
+/// Storage device a disk, CD-ROM, tape or volume handle belongs to.
+///
+/// Device numbers are only unique per device type, e.g. `\\.\CdRom0` and `\\.\PhysicalDrive0` are
+/// both number 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DeviceNumber {
+    pub(crate) device_type: u32,
+    pub(crate) number: u32,
+}
+
+/// Device type and number of a `STORAGE_DEVICE_NUMBER`
+pub(crate) fn device_number(bytes: &[u8]) -> Option<DeviceNumber> {
+    Some(DeviceNumber {
+        device_type: u32_at(bytes, 0)?,
+        number: u32_at(bytes, 4)?,
+    })
+}
+
+/// Disk of a volume from its `VOLUME_DISK_EXTENTS`, `None` if it spans several disks
+pub(crate) fn volume_disk(bytes: &[u8]) -> Option<DeviceNumber> {
+    if u32_at(bytes, 0)? != 1 {
+        return None;
+    }
+
+    Some(DeviceNumber {
+        device_type: FILE_DEVICE_DISK,
+        number: u32_at(bytes, DISK_EXTENTS_NUMBER_OFFSET)?,
+    })
+}
+
+/// Fill the size, sector size and media type of `device` from a `DISK_GEOMETRY_EX`.
+///
+/// Nothing is set if `bytes` is cut off before the disk size.
+pub(crate) fn geometry(device: &mut DeviceDescriptor, bytes: &[u8]) -> Option<()> {
+    let media_type = u32_at(bytes, GEOMETRY_MEDIA_TYPE_OFFSET)?;
+    let sector = u32_at(bytes, GEOMETRY_SECTOR_OFFSET)?;
+    let size = u64_at(bytes, GEOMETRY_SIZE_OFFSET)?;
+
+    device.size = size;
+    device.block_size = sector;
+
+    if media_type == REMOVABLE_MEDIA {
+        device.media_type = MediaType::RemovableMedia;
+    }
+
+    Some(())
+}
+
+/// Serial number of a `STORAGE_DEVICE_DESCRIPTOR`, `None` if the device has none
+pub(crate) fn serial_number(bytes: &[u8]) -> Option<String> {
+    let offset = u32_at(bytes, SERIAL_NUMBER_OFFSET)?;
+
+    // 0 and -1 both mean no serial number
+    if offset == 0 || offset == u32::MAX {
+        return None;
+    }
+
+    let serial = bytes.get(offset as usize..)?;
+    let len = serial.iter().position(|x| *x == 0).unwrap_or(serial.len());
+    let serial = String::from_utf8_lossy(&serial[..len]).trim().to_string();
+
+    Some(serial).filter(|x| !x.is_empty())
+}
+
+/// Bus of a `STORAGE_BUS_TYPE`
+fn bus_type(code: StorageBusType) -> BusType {
+    match code {
+        BUS_TYPE_UNKNOWN => BusType::Unknown,
+        BUS_TYPE_SCSI => BusType::Scsi,
+        BUS_TYPE_ATAPI => BusType::Atapi,
+        BUS_TYPE_ATA => BusType::Ata,
+        BUS_TYPE1394 => BusType::Ieee1394,
+        BUS_TYPE_SSA => BusType::Ssa,
+        BUS_TYPE_FIBRE => BusType::Fibre,
+        BUS_TYPE_USB => BusType::Usb,
+        BUS_TYPE_RAID => BusType::Raid,
+        BUS_TYPEI_SCSI => BusType::Iscsi,
+        BUS_TYPE_SAS => BusType::Sas,
+        BUS_TYPE_SATA => BusType::Sata,
+        BUS_TYPE_SD => BusType::Sd,
+        BUS_TYPE_MMC => BusType::Mmc,
+        BUS_TYPE_VIRTUAL => BusType::Virtual,
+        BUS_TYPE_FILE_BACKED_VIRTUAL => BusType::FileBackedVirtual,
+        BUS_TYPE_NVME => BusType::Nvme,
+        BUS_TYPE_UFS => BusType::Ufs,
+        BUS_TYPE_SCM => BusType::Scm,
+        _ => BusType::Unknown,
+    }
+}
+
+/// Fill the bus and queueing of `device` from a `STORAGE_ADAPTER_DESCRIPTOR`.
+///
+/// Nothing is set if `bytes` is cut off before the bus version.
+pub(crate) fn adapter(device: &mut DeviceDescriptor, bytes: &[u8]) -> Option<()> {
+    let max_transfer = u32_at(bytes, MAX_TRANSFER_OFFSET)?;
+    let command_queueing = *bytes.get(COMMAND_QUEUEING_OFFSET)?;
+    let bus = *bytes.get(BUS_TYPE_OFFSET)?;
+    let major = u16_at(bytes, BUS_VERSION_OFFSET)?;
+    let minor = u16_at(bytes, BUS_VERSION_OFFSET + 2)?;
+
+    device.bus_type = Some(bus_type(bus as StorageBusType));
+    device.bus_version = Some(format!("{}.{}", major, minor));
+    device.max_transfer_bytes = Some(max_transfer).filter(|x| *x != 0);
+
+    // Without command queueing only a single command is outstanding at a time, the queue
+    // sizes of queueing adapters aren't reported
+    if command_queueing == 0 {
+        device.queue_count = Some(1);
+        device.queue_depth = Some(1);
+    }
+
+    Some(())
+}
+
+/// Fill the block sizes and alignment of `device` from a `STORAGE_ACCESS_ALIGNMENT_DESCRIPTOR`.
+///
+/// Nothing is set if `bytes` is cut off before the last field.
+pub(crate) fn access_alignment(device: &mut DeviceDescriptor, bytes: &[u8]) -> Option<()> {
+    let cache_line = u32_at(bytes, CACHE_LINE_OFFSET)?;
+    let cache_offset = u32_at(bytes, CACHE_LINE_OFFSET + 4)?;
+    let logical = u32_at(bytes, LOGICAL_SECTOR_OFFSET)?;
+    let physical = u32_at(bytes, LOGICAL_SECTOR_OFFSET + 4)?;
+    let sector_offset = u32_at(bytes, LOGICAL_SECTOR_OFFSET + 8)?;
+
+    device.block_size = physical;
+    device.logical_block_size = logical;
+    device.bytes_per_cache_line = Some(cache_line);
+    device.bytes_offset_for_cache_alignment = Some(cache_offset);
+    device.bytes_offset_for_sector_alignment = Some(sector_offset);
+
+    Some(())
+}
+
+/// Size of `STORAGE_WRITE_CACHE_PROPERTY`
+pub(crate) const WRITE_CACHE_SIZE: usize = 28;
+/// Offset of `WriteCacheEnabled` in `STORAGE_WRITE_CACHE_PROPERTY`
+pub(crate) const WRITE_CACHE_ENABLED_OFFSET: usize = 12;
+/// `WriteCacheDisabled` of `WRITE_CACHE_ENABLE`
+const WRITE_CACHE_DISABLED: u32 = 1;
+/// `WriteCacheEnabled` of `WRITE_CACHE_ENABLE`
+const WRITE_CACHE_ENABLED: u32 = 2;
+
+/// Write cache state of a `STORAGE_WRITE_CACHE_PROPERTY`, `None` for `WriteCacheEnableUnknown` or
+/// if `bytes` is cut off before it
+pub(crate) fn write_cache_enabled(bytes: &[u8]) -> Option<bool> {
+    match u32_at(bytes, WRITE_CACHE_ENABLED_OFFSET)? {
+        WRITE_CACHE_ENABLED => Some(true),
+        WRITE_CACHE_DISABLED => Some(false),
+        _ => None,
+    }
+}
+
+/// Size of `DEVICE_LB_PROVISIONING_DESCRIPTOR`
+pub(crate) const PROVISIONING_SIZE: usize = 40;
+/// Offset of the flags byte in `DEVICE_LB_PROVISIONING_DESCRIPTOR`
+pub(crate) const PROVISIONING_FLAGS_OFFSET: usize = 8;
+
+/// `ThinProvisioningEnabled` (bit 0) of a `DEVICE_LB_PROVISIONING_DESCRIPTOR`, `None` if `bytes` is
+/// cut off before the flags
+pub(crate) fn thin_provisioning(bytes: &[u8]) -> Option<bool> {
+    Some(bytes.get(PROVISIONING_FLAGS_OFFSET)? & 1 != 0)
+}
+
+/// `ERROR_WRITE_PROTECT` system error code
+pub(crate) const ERROR_WRITE_PROTECT: u32 = 19;
+
+/// Read-only state from the result of `IOCTL_DISK_IS_WRITABLE`.
+///
+/// The ioctl also fails for reasons unrelated to write protection (e.g. access denied), so only a
+/// write protect error means read-only.
+pub(crate) fn is_write_protected(writable: Result<(), u32>) -> bool {
+    writable == Err(ERROR_WRITE_PROTECT)
+}
+
+/// File system flag of `GetVolumeInformationW`
+pub(crate) const FILE_CASE_SENSITIVE_SEARCH: u32 = 0x1;
+/// File system flag of `GetVolumeInformationW`
+pub(crate) const FILE_VOLUME_IS_COMPRESSED: u32 = 0x8000;
+
+/// Set the flags of `mountpoint` from the file system flags of `GetVolumeInformationW`
+pub(crate) fn volume_flags(mountpoint: &mut MountPoint, file_system_flags: u32) {
+    mountpoint.case_sensitive = Some(file_system_flags & FILE_CASE_SENSITIVE_SEARCH != 0);
+    mountpoint.compressed = Some(file_system_flags & FILE_VOLUME_IS_COMPRESSED != 0);
+}
+
+/// Flag returned by `FSCTL_IS_VOLUME_DIRTY`
+const VOLUME_IS_DIRTY: u32 = 0x1;
+
+/// Warn about a dirty volume, from the flags returned by `FSCTL_IS_VOLUME_DIRTY`.
+///
+/// Free space of a dirty volume (unclean dismount, pending chkdsk) may be stale.
+pub(crate) fn volume_dirty(mountpoint: &mut MountPoint, flags: u32) {
+    if flags & VOLUME_IS_DIRTY != 0 {
+        mountpoint.warnings.push("volume dirty".to_string());
+    }
+}
+
+/// GPT partition type of the Microsoft Reserved Partition
+const MSR_PARTITION_TYPE: &str = "e3c9e316-0b5c-4db8-817d-f92df00215ae";
+/// GPT partition type of the Windows Recovery Environment partition
+const RECOVERY_PARTITION_TYPE: &str = "de94bba4-06d1-4d40-a16a-bfd50179d6ac";
+
+/// The drive has the partition layout Windows setup creates on the boot disk.
+///
+/// The ESP, MSR and recovery partitions have no drive letter, so this catches a system disk
+/// whose only lettered partition is hidden from mountpoint matching.
+pub(crate) fn has_windows_boot_layout(device: &DeviceDescriptor) -> bool {
+    let has_type = |guid: &str| {
+        device
+            .partitions
+            .iter()
+            .any(|x| x.partition_type.as_deref() == Some(guid))
+    };
+
+    device.efi_system_partition().is_some()
+        && (has_type(MSR_PARTITION_TYPE) || has_type(RECOVERY_PARTITION_TYPE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `s` as a NUL terminated buffer of `len` UTF-16 units, like the `W` APIs fill it
+    fn wide(s: &str, len: usize) -> Vec<u16> {
+        let mut buffer: Vec<u16> = s.encode_utf16().collect();
+        buffer.resize(len, 0);
+        buffer
+    }
+
+    #[test]
+    fn multi_byte_names() {
+        // 'ü' takes two bytes in UTF-8, the emoji a surrogate pair in UTF-16
+        assert_eq!(
+            wide_to_string(&wide("Intenso Ultra Line Füße 💾", 260)),
+            "Intenso Ultra Line Füße 💾"
+        );
+        assert_eq!(
+            wide_to_string(&wide("外付けドライブ", 260)),
+            "外付けドライブ"
+        );
+    }
+
+    #[test]
+    fn wide_buffer_bounds() {
+        // Garbage after the terminator is ignored, a full buffer needs no terminator
+        let mut buffer = wide("USBSTOR", 16);
+        buffer[10] = 'X' as u16;
+        assert_eq!(wide_to_string(&buffer), "USBSTOR");
+        assert_eq!(wide_to_string(&wide("SCSI", 4)), "SCSI");
+        assert_eq!(wide_to_string(&[]), "");
+    }
+
+    #[test]
+    fn ansi() {
+        assert_eq!(ansi_to_string(b"C:\\Windows\0\0\0"), "C:\\Windows");
+        assert_eq!(ansi_to_string(b"\xff\xfe"), "");
+    }
+
+    /// Volume label as `GetVolumeInformationW` returns it, matching the macOS fixture
+    #[test]
+    fn non_ascii_label() {
+        let label = wide("Fotós", 261);
+
+        assert_eq!(label[3], 0xf3);
+        assert_eq!(wide_to_string(&label), "Fotós");
+    }
+
+    fn gpt_partition(path: &str, partition_type: &str) -> crate::PartitionInfo {
+        crate::PartitionInfo {
+            partition_type: Some(partition_type.to_string()),
+            ..crate::PartitionInfo::new(path)
+        }
+    }
+
+    /// Boot disk whose only lettered partition isn't a system directory: the ESP and recovery
+    /// partition have no letter, so only the layout gives it away
+    #[test]
+    fn boot_layout_without_letters() {
+        let esp = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b";
+        let data = "ebd0a0a2-b9e5-4433-87c0-68b6b72699c7";
+        let mut device = DeviceDescriptor {
+            partitions: vec![
+                gpt_partition(r"\\.\Harddisk0Partition1", esp),
+                gpt_partition(r"\\.\Harddisk0Partition2", data),
+                gpt_partition(r"\\.\Harddisk0Partition3", RECOVERY_PARTITION_TYPE),
+            ],
+            ..Default::default()
+        };
+        assert!(has_windows_boot_layout(&device));
+
+        device.partitions[2].partition_type = Some(MSR_PARTITION_TYPE.to_string());
+        assert!(has_windows_boot_layout(&device));
+
+        // An ESP alone is any GPT boot stick
+        device.partitions.remove(2);
+        assert!(!has_windows_boot_layout(&device));
+
+        device.partitions[0].partition_type = Some(data.to_string());
+        device.partitions[1].partition_type = Some(RECOVERY_PARTITION_TYPE.to_string());
+        assert!(!has_windows_boot_layout(&device));
+    }
+
+    /// `DWORD` fields of a descriptor, in order
+    fn descriptor(fields: &[u32]) -> Vec<u8> {
+        fields.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+
+    /// 512e drive: 4K physical sectors emulating 512 byte logical sectors
+    #[test]
+    fn access_alignment_fields() {
+        let bytes = descriptor(&[1, 28, 64, 0, 512, 4096, 0]);
+        assert_eq!(bytes.len(), ACCESS_ALIGNMENT_SIZE);
+
+        let mut device = DeviceDescriptor::default();
+        assert!(access_alignment(&mut device, &bytes).is_some());
+
+        assert_eq!(device.logical_block_size, 512);
+        assert_eq!(device.block_size, 4096);
+        assert_eq!(device.bytes_per_cache_line, Some(64));
+        assert_eq!(device.bytes_offset_for_cache_alignment, Some(0));
+        assert_eq!(device.bytes_offset_for_sector_alignment, Some(0));
+    }
+
+    #[test]
+    fn access_alignment_truncated() {
+        let bytes = descriptor(&[1, 28, 64, 0, 512, 4096, 0]);
+        let mut device = DeviceDescriptor::default();
+
+        assert!(access_alignment(&mut device, &bytes[..ACCESS_ALIGNMENT_SIZE - 1]).is_none());
+        assert_eq!(device.block_size, DeviceDescriptor::default().block_size);
+        assert_eq!(device.bytes_per_cache_line, None);
+    }
+
+    #[test]
+    fn write_cache() {
+        // Version, Size, WriteCacheType (write back), WriteCacheEnabled, ...
+        let property = |enabled| descriptor(&[1, 28, 2, enabled, 1, 1, 0]);
+
+        assert_eq!(write_cache_enabled(&property(2)), Some(true));
+        assert_eq!(write_cache_enabled(&property(1)), Some(false));
+        assert_eq!(write_cache_enabled(&property(0)), None);
+        assert_eq!(
+            write_cache_enabled(&property(2)[..WRITE_CACHE_ENABLED_OFFSET + 4]),
+            Some(true)
+        );
+        assert_eq!(
+            write_cache_enabled(&property(2)[..WRITE_CACHE_ENABLED_OFFSET + 3]),
+            None
+        );
+    }
+
+    #[test]
+    fn thin_provisioned() {
+        let mut bytes = descriptor(&[1, 40, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(thin_provisioning(&bytes), Some(false));
+
+        // ThinProvisioningReadZeros (bit 1) alone isn't thin provisioning
+        bytes[PROVISIONING_FLAGS_OFFSET] = 0b10;
+        assert_eq!(thin_provisioning(&bytes), Some(false));
+
+        bytes[PROVISIONING_FLAGS_OFFSET] = 0b11;
+        assert_eq!(thin_provisioning(&bytes), Some(true));
+        assert_eq!(
+            thin_provisioning(&bytes[..PROVISIONING_FLAGS_OFFSET + 1]),
+            Some(true)
+        );
+        assert_eq!(thin_provisioning(&bytes[..PROVISIONING_FLAGS_OFFSET]), None);
+    }
+
+    /// `STORAGE_ADAPTER_DESCRIPTOR` of a USB bridge
+    fn usb_adapter(max_transfer: u32, command_queueing: u8) -> Vec<u8> {
+        let mut bytes = descriptor(&[1, 32, max_transfer, 33, 0, 0, 0, 0]);
+        bytes[COMMAND_QUEUEING_OFFSET] = command_queueing;
+        bytes[BUS_TYPE_OFFSET] = BUS_TYPE_USB as u8;
+        bytes[BUS_VERSION_OFFSET..BUS_VERSION_OFFSET + 4].copy_from_slice(&[2, 0, 0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn adapter_fields() {
+        let mut device = DeviceDescriptor::default();
+        assert!(adapter(&mut device, &usb_adapter(128 * 1024, 0)).is_some());
+
+        assert_eq!(device.bus_type, Some(BusType::Usb));
+        assert_eq!(device.bus_version.as_deref(), Some("2.0"));
+        assert_eq!(device.max_transfer_bytes, Some(128 * 1024));
+        assert_eq!(device.queue_count, Some(1));
+        assert_eq!(device.queue_depth, Some(1));
+    }
+
+    #[test]
+    fn adapter_without_limits() {
+        let mut bytes = usb_adapter(0, 1);
+        bytes[BUS_TYPE_OFFSET] = BUS_TYPE_NVME as u8;
+
+        let mut device = DeviceDescriptor::default();
+        assert!(adapter(&mut device, &bytes).is_some());
+
+        // 0 is no reported limit, and queueing adapters don't report their queues
+        assert_eq!(device.bus_type, Some(BusType::Nvme));
+        assert_eq!(device.max_transfer_bytes, None);
+        assert_eq!(device.queue_count, None);
+        assert_eq!(device.queue_depth, None);
+
+        bytes[BUS_TYPE_OFFSET] = 0x7f;
+        assert!(adapter(&mut device, &bytes).is_some());
+        assert_eq!(device.bus_type, Some(BusType::Unknown));
+    }
+
+    #[test]
+    fn adapter_truncated() {
+        let bytes = usb_adapter(128 * 1024, 0);
+        let mut device = DeviceDescriptor::default();
+
+        assert!(adapter(&mut device, &bytes[..BUS_VERSION_OFFSET + 3]).is_none());
+        assert_eq!(device.bus_type, None);
+        assert_eq!(device.max_transfer_bytes, None);
+    }
+
+    #[test]
+    fn file_system_flags() {
+        // Flags of an NTFS volume, which supports case sensitive search
+        let ntfs = 0x03e7_00ff;
+        let mut mountpoint = MountPoint::new(r"C:\");
+        volume_flags(&mut mountpoint, ntfs);
+        assert_eq!(mountpoint.case_sensitive, Some(true));
+        assert_eq!(mountpoint.compressed, Some(false));
+
+        // Case preserving only, on a compressed volume
+        let mut mountpoint = MountPoint::new(r"E:\");
+        volume_flags(&mut mountpoint, 0x0002 | FILE_VOLUME_IS_COMPRESSED);
+        assert_eq!(mountpoint.case_sensitive, Some(false));
+        assert_eq!(mountpoint.compressed, Some(true));
+    }
+
+    #[test]
+    fn dirty_volume() {
+        let mut mountpoint = MountPoint::new(r"E:\");
+        volume_dirty(&mut mountpoint, 0);
+        assert!(mountpoint.warnings.is_empty());
+
+        // VOLUME_UPGRADE_SCHEDULED alone isn't dirty
+        volume_dirty(&mut mountpoint, 0x2);
+        assert!(mountpoint.warnings.is_empty());
+
+        volume_dirty(&mut mountpoint, VOLUME_IS_DIRTY | 0x2);
+        assert_eq!(mountpoint.warnings, ["volume dirty"]);
+    }
+
+    #[test]
+    fn write_protect() {
+        assert!(!is_write_protected(Ok(())));
+        // ERROR_ACCESS_DENIED and ERROR_NOT_READY
+        assert!(!is_write_protected(Err(5)));
+        assert!(!is_write_protected(Err(21)));
+        assert!(is_write_protected(Err(ERROR_WRITE_PROTECT)));
+    }
+}
@@ -0,0 +1,426 @@
+//! Parsing of `diskutil` plist output.
+//!
+//! Kept apart from the macOS backend, which only runs the command, so the parsing can be tested
+//! with recorded output on any platform.
+
+use std::{
+    io::Cursor,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use super::Watchdog;
+use crate::{
+    device::{DeviceDescriptor, MediaType, MountPoint, PartitionInfo, PartitionTableType},
+    DriveListError,
+};
+
+#[derive(Deserialize, Debug)]
+struct Disks {
+    #[serde(rename = "AllDisksAndPartitions")]
+    all_disks_and_partitions: Vec<Disk>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Disk {
+    #[serde(rename = "DeviceIdentifier")]
+    device_identifier: String,
+    #[serde(rename = "OSInternal")]
+    os_internal: bool,
+    #[serde(rename = "Size")]
+    size: u64,
+    #[serde(rename = "Content")]
+    content: String,
+    #[serde(rename = "Partitions", default)]
+    partitions: Vec<Partition>,
+    #[serde(rename = "APFSVolumes", default)]
+    apfs_volumes: Vec<ApfsVolume>,
+    /// Partitions holding a synthesized APFS container
+    #[serde(rename = "APFSPhysicalStores", default)]
+    apfs_physical_stores: Vec<ApfsPhysicalStore>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApfsPhysicalStore {
+    #[serde(rename = "DeviceIdentifier")]
+    device_identifier: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Partition {
+    #[serde(rename = "DeviceIdentifier")]
+    device_identifier: String,
+    #[serde(rename = "MountPoint")]
+    mount_point: Option<String>,
+    #[serde(rename = "VolumeName")]
+    volume_name: Option<String>,
+    #[serde(rename = "Content")]
+    content: String,
+    #[serde(rename = "Size")]
+    size: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApfsVolume {
+    #[serde(rename = "DeviceIdentifier")]
+    device_identifier: String,
+    #[serde(rename = "MountPoint")]
+    mount_point: Option<String>,
+    #[serde(rename = "VolumeName")]
+    volume_name: Option<String>,
+    #[serde(rename = "Size")]
+    size: u64,
+    /// Filled from `diskutil info`
+    #[serde(skip)]
+    available_bytes: Option<u64>,
+    /// Filled from `diskutil info`
+    #[serde(skip)]
+    case_sensitive: Option<bool>,
+}
+
+/// Subset of `diskutil info -plist` output
+#[derive(Deserialize, Debug)]
+pub(crate) struct VolumeInfo {
+    #[serde(rename = "APFSContainerFree")]
+    apfs_container_free: Option<u64>,
+    #[serde(rename = "VolumeAvailableSpace")]
+    volume_available_space: Option<u64>,
+    #[serde(rename = "FilesystemName")]
+    filesystem_name: Option<String>,
+    /// Only read by the macOS backend, not by the fuzz targets
+    #[serde(rename = "SMARTStatus")]
+    #[cfg_attr(all(feature = "fuzzing", not(target_os = "macos")), allow(dead_code))]
+    pub(crate) smart_status: Option<String>,
+}
+
+/// Content of a synthesized APFS container disk
+const APFS_CONTAINER_SCHEME: &str = "EF57347C-0000-11AA-AA11-00306543ECAC";
+
+impl From<Disk> for DeviceDescriptor {
+    fn from(value: Disk) -> Self {
+        let partition_table_type = match value.content.as_str() {
+            "GUID_partition_scheme" => Some(PartitionTableType::Gpt),
+            "FDisk_partition_scheme" => Some(PartitionTableType::Mbr),
+            _ => None,
+        };
+        // Synthesized APFS container disks repeat the bytes of their physical store
+        let is_synthesized =
+            value.content == APFS_CONTAINER_SCHEME || !value.apfs_volumes.is_empty();
+
+        DeviceDescriptor {
+            enumerator: "diskutil".to_string(),
+            is_virtual: is_synthesized,
+            backing_devices: value
+                .apfs_physical_stores
+                .iter()
+                .map(|x| format!("/dev/{}", x.device_identifier))
+                .collect(),
+            description: value.content,
+            size: value.size,
+            partition_table_type,
+            partitions: value.partitions.iter().map(PartitionInfo::from).collect(),
+            mountpoints: value
+                .partitions
+                .into_iter()
+                .map(MountPoint::from)
+                .chain(value.apfs_volumes.into_iter().map(MountPoint::from))
+                .collect(),
+            device: format!("/dev/{}", value.device_identifier),
+            raw: format!("/dev/r{}", value.device_identifier),
+            is_system: value.os_internal,
+            is_removable: !value.os_internal,
+            media_type: if value.os_internal {
+                MediaType::FixedMedia
+            } else {
+                MediaType::ExternalFixed
+            },
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Partition> for MountPoint {
+    fn from(value: Partition) -> Self {
+        MountPoint {
+            path: value.mount_point.unwrap_or_default(),
+            label: value.volume_name.or(Some(value.content)),
+            total_bytes: Some(value.size),
+            available_bytes: None,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&Partition> for PartitionInfo {
+    fn from(value: &Partition) -> Self {
+        PartitionInfo {
+            path: format!("/dev/{}", value.device_identifier),
+            partition_type: Some(value.content.clone()),
+            size: Some(value.size),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<ApfsVolume> for MountPoint {
+    fn from(value: ApfsVolume) -> Self {
+        MountPoint {
+            path: value.mount_point.unwrap_or_default(),
+            label: value.volume_name,
+            total_bytes: Some(value.size),
+            available_bytes: value.available_bytes,
+            // APFS volumes share the free space of their container
+            available_bytes_shared: true,
+            case_sensitive: value.case_sensitive,
+            ..Default::default()
+        }
+    }
+}
+
+/// Output of a successful `diskutil` run
+pub(crate) struct DiskUtilOutput {
+    pub(crate) stdout: Vec<u8>,
+    /// Warnings printed next to the plist, if any
+    pub(crate) stderr: String,
+}
+
+impl DiskUtilOutput {
+    /// Parse the plist on stdout, mentioning `command` and stderr on failure
+    fn parse<T: DeserializeOwned>(&self, command: &str) -> anyhow::Result<T> {
+        parse_plist(&self.stdout).with_context(|| {
+            if self.stderr.is_empty() {
+                format!("Couldn't parse output of diskutil {}", command)
+            } else {
+                format!(
+                    "Couldn't parse output of diskutil {}: {}",
+                    command, self.stderr
+                )
+            }
+        })
+    }
+}
+
+/// Source of `diskutil` plist output, so the parsing can be fed recorded output
+pub(crate) trait DiskUtilSource {
+    /// Output of `diskutil list -plist`
+    fn list(&self) -> anyhow::Result<DiskUtilOutput>;
+
+    /// Output of `diskutil info -plist <device_identifier>`
+    fn info(&self, device_identifier: &str) -> anyhow::Result<DiskUtilOutput>;
+}
+
+/// Parse a property list, telling malformed output apart from an unexpected structure
+fn parse_plist<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, DriveListError> {
+    let value = plist::Value::from_reader(Cursor::new(bytes))
+        .map_err(|x| DriveListError::PlistSyntax(x.to_string()))?;
+
+    plist::from_value(&value).map_err(|x| DriveListError::PlistFormat(x.to_string()))
+}
+
+pub(crate) fn diskutil_info(
+    source: &impl DiskUtilSource,
+    device_identifier: &str,
+) -> anyhow::Result<VolumeInfo> {
+    source.info(device_identifier)?.parse("info")
+}
+
+pub(crate) fn diskutil(
+    source: &impl DiskUtilSource,
+    watchdog: Watchdog,
+    cancel: Option<&AtomicBool>,
+    diagnostics: &mut Vec<String>,
+) -> anyhow::Result<Vec<DeviceDescriptor>> {
+    let start = Instant::now();
+    let output = source.list();
+    watchdog.check(start, "diskutil", "list", diagnostics);
+
+    let parsed: Disks = output?.parse("list")?;
+
+    let mut drives = Vec::new();
+
+    for mut disk in parsed.all_disks_and_partitions {
+        if cancel.is_some_and(|x| x.load(Ordering::Relaxed)) {
+            return Err(DriveListError::Cancelled.into());
+        }
+
+        let disk_start = Instant::now();
+
+        for volume in disk.apfs_volumes.iter_mut() {
+            let start = Instant::now();
+            let info = diskutil_info(source, &volume.device_identifier);
+            watchdog.check(start, &volume.device_identifier, "info", diagnostics);
+
+            match info {
+                Ok(info) => {
+                    volume.available_bytes =
+                        info.apfs_container_free.or(info.volume_available_space);
+                    volume.case_sensitive = info
+                        .filesystem_name
+                        .map(|x| x.to_lowercase().contains("case-sensitive"));
+                }
+                Err(err) => diagnostics.push(format!("{}: {}", volume.device_identifier, err)),
+            }
+        }
+
+        let mut drive = DeviceDescriptor::from(disk);
+        drive.enumeration_duration = Some(disk_start.elapsed());
+        drives.push(drive);
+    }
+
+    Ok(drives)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Replays output recorded from `diskutil`, using the same `info` output for every volume
+    struct Recorded;
+
+    impl DiskUtilSource for Recorded {
+        fn list(&self) -> anyhow::Result<DiskUtilOutput> {
+            Ok(DiskUtilOutput {
+                stdout: include_bytes!("../../fixtures/diskutil-list.plist").to_vec(),
+                stderr: String::new(),
+            })
+        }
+
+        fn info(&self, _device_identifier: &str) -> anyhow::Result<DiskUtilOutput> {
+            Ok(DiskUtilOutput {
+                stdout: include_bytes!("../../fixtures/diskutil-info-apfs.plist").to_vec(),
+                stderr: String::new(),
+            })
+        }
+    }
+
+    #[test]
+    fn recorded_list() {
+        let mut diagnostics = Vec::new();
+        let drives = diskutil(&Recorded, Watchdog::default(), None, &mut diagnostics).unwrap();
+
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            drives.iter().map(|x| x.device.as_str()).collect::<Vec<_>>(),
+            ["/dev/disk0", "/dev/disk3", "/dev/disk4"]
+        );
+
+        let disk = &drives[0];
+        assert_eq!(disk.raw, "/dev/rdisk0");
+        assert_eq!(disk.partition_table_type, Some(PartitionTableType::Gpt));
+        assert_eq!(disk.partitions.len(), 3);
+        assert!(!disk.is_virtual);
+
+        let container = &drives[1];
+        assert!(container.is_virtual);
+        assert_eq!(container.backing_devices, ["/dev/disk0s2"]);
+        let data = container
+            .mountpoints
+            .iter()
+            .find(|x| x.path == "/System/Volumes/Data")
+            .unwrap();
+        assert_eq!(data.label.as_deref(), Some("Data"));
+        assert_eq!(data.available_bytes, Some(282823454720));
+        assert!(data.available_bytes_shared);
+        assert_eq!(data.case_sensitive, Some(false));
+
+        let stick = &drives[2];
+        assert_eq!(stick.partition_table_type, Some(PartitionTableType::Mbr));
+        assert_eq!(stick.mountpoints[0].path, "/Volumes/UNTITLED");
+        assert_eq!(stick.mountpoints[0].label.as_deref(), Some("UNTITLED"));
+    }
+
+    #[test]
+    fn recorded_info() {
+        let info = diskutil_info(&Recorded, "disk3s5").unwrap();
+
+        assert_eq!(info.apfs_container_free, Some(282823454720));
+        assert_eq!(info.smart_status.as_deref(), Some("Verified"));
+    }
+
+    #[test]
+    fn truncated_plist() {
+        let bytes = include_bytes!("../../fixtures/diskutil-list.plist");
+
+        assert!(matches!(
+            parse_plist::<Disks>(&bytes[..bytes.len() / 2]),
+            Err(DriveListError::PlistSyntax(_))
+        ));
+    }
+
+    #[test]
+    fn unexpected_plist() {
+        let bytes = br#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>AllDisks</key>
+	<array>
+		<string>disk0</string>
+	</array>
+</dict>
+</plist>
+"#;
+
+        assert!(matches!(
+            parse_plist::<Disks>(bytes),
+            Err(DriveListError::PlistFormat(_))
+        ));
+    }
+
+    /// Output cut off anywhere inside the root dictionary is an error, not a partial list
+    #[test]
+    fn every_truncation() {
+        let bytes = include_bytes!("../../fixtures/diskutil-list.plist");
+        let end = bytes.windows(7).rposition(|x| x == b"</dict>").unwrap();
+
+        for len in 0..=end {
+            assert!(parse_plist::<Disks>(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn enumeration_durations() {
+        let drives = diskutil(&Recorded, Watchdog::default(), None, &mut Vec::new()).unwrap();
+
+        // The container's volumes are each queried with `diskutil info`
+        assert!(drives[1]
+            .enumeration_duration
+            .is_some_and(|x| x > Duration::ZERO));
+        assert!(drives.iter().all(|x| x.enumeration_duration.is_some()));
+    }
+
+    /// The synthesized APFS container repeats the bytes of its physical store
+    #[test]
+    fn physical_capacity() {
+        let drives = diskutil(&Recorded, Watchdog::default(), None, &mut Vec::new()).unwrap();
+        let sizes: Vec<_> = drives.iter().map(|x| x.size).collect();
+
+        assert_eq!(sizes, [500277790720, 494384795648, 15931539456]);
+        assert_eq!(
+            crate::total_physical_capacity(&drives),
+            500277790720 + 15931539456
+        );
+    }
+
+    #[test]
+    fn non_ascii_label() {
+        let disks: Disks =
+            parse_plist(include_bytes!("../../fixtures/diskutil-list-exfat.plist")).unwrap();
+        let drives: Vec<_> = disks
+            .all_disks_and_partitions
+            .into_iter()
+            .map(DeviceDescriptor::from)
+            .collect();
+        let [drive] = &drives[..] else {
+            panic!("expected one disk, got {:?}", drives);
+        };
+
+        assert_eq!(drive.mountpoints[0].path, "/Volumes/Fotós");
+        assert_eq!(drive.mountpoints[0].label.as_deref(), Some("Fotós"));
+    }
+}
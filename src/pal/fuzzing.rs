@@ -0,0 +1,52 @@
+//! Entry points for the fuzz targets in `fuzz/`, feeding arbitrary bytes to the parsers of
+//! platform tool and ioctl output. Not a stable API.
+
+use super::{
+    diskutil::{self, DiskUtilOutput, DiskUtilSource},
+    layout, Watchdog,
+};
+
+/// Serves fixed bytes in place of running `diskutil`
+struct Fuzzed<'a> {
+    list: &'a [u8],
+    info: &'a [u8],
+}
+
+impl DiskUtilSource for Fuzzed<'_> {
+    fn list(&self) -> anyhow::Result<DiskUtilOutput> {
+        Ok(DiskUtilOutput {
+            stdout: self.list.to_vec(),
+            stderr: String::new(),
+        })
+    }
+
+    fn info(&self, _device_identifier: &str) -> anyhow::Result<DiskUtilOutput> {
+        Ok(DiskUtilOutput {
+            stdout: self.info.to_vec(),
+            stderr: String::new(),
+        })
+    }
+}
+
+/// Parse `bytes` as the output of `diskutil list -plist`
+pub fn diskutil_list(bytes: &[u8]) {
+    let source = Fuzzed {
+        list: bytes,
+        info: &[],
+    };
+    let _ = diskutil::diskutil(&source, Watchdog::default(), None, &mut Vec::new());
+}
+
+/// Parse `bytes` as the output of `diskutil info -plist`
+pub fn diskutil_info(bytes: &[u8]) {
+    let source = Fuzzed {
+        list: &[],
+        info: bytes,
+    };
+    let _ = diskutil::diskutil_info(&source, "disk0");
+}
+
+/// Parse `bytes` as the buffer filled by `IOCTL_DISK_GET_DRIVE_LAYOUT_EX`
+pub fn drive_layout(bytes: &[u8]) {
+    let _ = layout::parse(bytes, "0");
+}
@@ -0,0 +1,262 @@
+//! Parsing of the `DRIVE_LAYOUT_INFORMATION_EX` buffer filled by `IOCTL_DISK_GET_DRIVE_LAYOUT_EX`.
+//!
+//! Fields are read from the bytes at their fixed offsets instead of casting the buffer, so
+//! truncated or garbage output can't be misread, and the parsing can be tested and fuzzed on any
+//! platform.
+
+use crate::{DriveListError, PartitionInfo, PartitionTableType};
+
+const PARTITION_STYLE_MBR: u32 = 0;
+const PARTITION_STYLE_GPT: u32 = 1;
+const PARTITION_STYLE_RAW: u32 = 2;
+
+/// Offset of `PartitionEntry` in `DRIVE_LAYOUT_INFORMATION_EX`
+pub(crate) const ENTRIES_OFFSET: usize = 48;
+/// Size of `PARTITION_INFORMATION_EX`
+pub(crate) const ENTRY_SIZE: usize = 144;
+
+/// Offset of the MBR / GPT union in `PARTITION_INFORMATION_EX`
+const ENTRY_UNION: usize = 32;
+/// Length of `PARTITION_INFORMATION_GPT::Name` in UTF-16 units
+const GPT_NAME_LEN: usize = 36;
+
+/// Only read by the Windows backend, not by the fuzz targets
+#[derive(Debug, Default)]
+#[cfg_attr(all(feature = "fuzzing", not(target_os = "windows")), allow(dead_code))]
+pub(crate) struct DriveLayout {
+    pub(crate) partition_table_type: Option<PartitionTableType>,
+    pub(crate) partitions: Vec<PartitionInfo>,
+}
+
+/// `N` bytes at `offset`, which the caller has checked to be in bounds
+fn bytes_at<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N].try_into().unwrap()
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes_at(bytes, offset))
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes_at(bytes, offset))
+}
+
+/// Format a GUID the same way as Linux (lowercase, no braces)
+fn guid_at(bytes: &[u8], offset: usize) -> String {
+    let data4: [u8; 8] = bytes_at(bytes, offset + 8);
+
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+        u32_at(bytes, offset),
+        u16::from_le_bytes(bytes_at(bytes, offset + 4)),
+        u16::from_le_bytes(bytes_at(bytes, offset + 6)),
+        data4[0],
+        data4[1],
+        data4[2],
+        data4[3],
+        data4[4],
+        data4[5],
+        data4[6],
+        data4[7]
+    )
+}
+
+fn name_at(bytes: &[u8], offset: usize) -> String {
+    let wide: Vec<u16> = bytes[offset..offset + GPT_NAME_LEN * 2]
+        .chunks_exact(2)
+        .map(|x| u16::from_le_bytes([x[0], x[1]]))
+        .take_while(|x| *x != 0)
+        .collect();
+
+    String::from_utf16_lossy(&wide).trim().to_string()
+}
+
+fn partition(entry: &[u8], disk_number: &str) -> Option<PartitionInfo> {
+    let style = u32_at(entry, 0);
+    let number = u32_at(entry, 24);
+
+    // Unused MBR slots and extended partition containers
+    if number == 0 {
+        return None;
+    }
+
+    let (partition_type, name, bootable) = match style {
+        PARTITION_STYLE_GPT => (
+            Some(guid_at(entry, ENTRY_UNION)),
+            Some(name_at(entry, ENTRY_UNION + 40)),
+            None,
+        ),
+        PARTITION_STYLE_MBR => (
+            Some(format!("0x{:02x}", entry[ENTRY_UNION])),
+            None,
+            Some(entry[ENTRY_UNION + 1] != 0),
+        ),
+        _ => (None, None, None),
+    };
+
+    Some(PartitionInfo {
+        path: format!(r"\\.\Harddisk{}Partition{}", disk_number, number),
+        name: name.filter(|x| !x.is_empty()),
+        partition_type,
+        offset: Some(u64_at(entry, 8)),
+        size: Some(u64_at(entry, 16)),
+        bootable,
+        ..Default::default()
+    })
+}
+
+/// Parse the part of the layout buffer written by the ioctl.
+///
+/// Entries beyond the end of `bytes` are ignored, even if the partition count includes them.
+pub(crate) fn parse(bytes: &[u8], disk_number: &str) -> Result<DriveLayout, DriveListError> {
+    if bytes.len() < ENTRIES_OFFSET {
+        return Err(DriveListError::PartitionLayout(format!(
+            "{} bytes is shorter than the header",
+            bytes.len()
+        )));
+    }
+
+    let style = u32_at(bytes, 0);
+    let count = u32_at(bytes, 4) as usize;
+
+    let partition_table_type = match style {
+        PARTITION_STYLE_MBR if count.is_multiple_of(4) => Some(PartitionTableType::Mbr),
+        PARTITION_STYLE_GPT => Some(PartitionTableType::Gpt),
+        PARTITION_STYLE_RAW => Some(PartitionTableType::Raw),
+        _ => None,
+    };
+
+    let partitions = bytes[ENTRIES_OFFSET..]
+        .chunks_exact(ENTRY_SIZE)
+        .take(count)
+        .filter_map(|x| partition(x, disk_number))
+        .collect();
+
+    Ok(DriveLayout {
+        partition_table_type,
+        partitions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// EFI System Partition type GUID in its on-disk (mixed endian) byte order
+    const ESP: [u8; 16] = [
+        0x28, 0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9,
+        0x3b,
+    ];
+
+    fn header(style: u32, count: u32) -> Vec<u8> {
+        let mut bytes = vec![0; ENTRIES_OFFSET];
+        bytes[0..4].copy_from_slice(&style.to_le_bytes());
+        bytes[4..8].copy_from_slice(&count.to_le_bytes());
+        bytes
+    }
+
+    fn entry(style: u32, number: u32, offset: u64, size: u64) -> [u8; ENTRY_SIZE] {
+        let mut entry = [0; ENTRY_SIZE];
+        entry[0..4].copy_from_slice(&style.to_le_bytes());
+        entry[8..16].copy_from_slice(&offset.to_le_bytes());
+        entry[16..24].copy_from_slice(&size.to_le_bytes());
+        entry[24..28].copy_from_slice(&number.to_le_bytes());
+        entry
+    }
+
+    fn gpt_entry(number: u32, offset: u64, size: u64, name: &str) -> [u8; ENTRY_SIZE] {
+        let mut entry = entry(PARTITION_STYLE_GPT, number, offset, size);
+        entry[ENTRY_UNION..ENTRY_UNION + 16].copy_from_slice(&ESP);
+
+        for (i, unit) in name.encode_utf16().enumerate() {
+            let at = ENTRY_UNION + 40 + i * 2;
+            entry[at..at + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+
+        entry
+    }
+
+    #[test]
+    fn gpt() {
+        let mut bytes = header(PARTITION_STYLE_GPT, 1);
+        bytes.extend(gpt_entry(
+            1,
+            1024 * 1024,
+            100 * 1024 * 1024,
+            "EFI system partition",
+        ));
+
+        let layout = parse(&bytes, "1").unwrap();
+        assert_eq!(layout.partition_table_type, Some(PartitionTableType::Gpt));
+
+        let [partition] = &layout.partitions[..] else {
+            panic!("expected one partition, got {:?}", layout.partitions);
+        };
+        assert_eq!(partition.path, r"\\.\Harddisk1Partition1");
+        assert_eq!(
+            partition.partition_type.as_deref(),
+            Some("c12a7328-f81f-11d2-ba4b-00a0c93ec93b")
+        );
+        assert!(partition.is_efi_system_partition());
+        assert_eq!(partition.name.as_deref(), Some("EFI system partition"));
+        assert_eq!(partition.offset, Some(1024 * 1024));
+        assert_eq!(partition.size, Some(100 * 1024 * 1024));
+        assert_eq!(partition.bootable, None);
+    }
+
+    #[test]
+    fn mbr_skips_unused_slots() {
+        let mut bytes = header(PARTITION_STYLE_MBR, 4);
+        let mut active = entry(PARTITION_STYLE_MBR, 1, 8192 * 512, 1 << 30);
+        active[ENTRY_UNION] = 0x0c;
+        active[ENTRY_UNION + 1] = 1;
+        bytes.extend(active);
+
+        for _ in 0..3 {
+            bytes.extend(entry(PARTITION_STYLE_MBR, 0, 0, 0));
+        }
+
+        let layout = parse(&bytes, "2").unwrap();
+        assert_eq!(layout.partition_table_type, Some(PartitionTableType::Mbr));
+
+        let [partition] = &layout.partitions[..] else {
+            panic!("expected one partition, got {:?}", layout.partitions);
+        };
+        assert_eq!(partition.partition_type.as_deref(), Some("0x0c"));
+        assert_eq!(partition.bootable, Some(true));
+        assert_eq!(partition.name, None);
+    }
+
+    #[test]
+    fn truncated_header() {
+        let bytes = header(PARTITION_STYLE_GPT, 1);
+
+        assert!(matches!(
+            parse(&bytes[..ENTRIES_OFFSET - 1], "0"),
+            Err(DriveListError::PartitionLayout(_))
+        ));
+    }
+
+    /// The count is not trusted beyond the bytes which were written
+    #[test]
+    fn count_exceeds_buffer() {
+        let mut bytes = header(PARTITION_STYLE_GPT, u32::MAX);
+        bytes.extend(gpt_entry(1, 0, 512, "a"));
+        bytes.extend(&gpt_entry(2, 512, 512, "b")[..ENTRY_SIZE - 1]);
+
+        let layout = parse(&bytes, "0").unwrap();
+        assert_eq!(layout.partitions.len(), 1);
+    }
+
+    #[test]
+    fn every_truncation() {
+        let mut bytes = header(PARTITION_STYLE_GPT, 2);
+        bytes.extend(gpt_entry(1, 0, 512, "a"));
+        bytes.extend(gpt_entry(2, 512, 512, "b"));
+
+        for len in 0..bytes.len() {
+            let partitions = parse(&bytes[..len], "0").map_or(0, |x| x.partitions.len());
+            assert_eq!(partitions, len.saturating_sub(ENTRIES_OFFSET) / ENTRY_SIZE);
+        }
+    }
+}
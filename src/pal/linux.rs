@@ -1,6 +1,19 @@
-use std::process::Command;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    path::{Path, PathBuf},
+    process::Command,
+    time::Instant,
+};
 
-use crate::device::{DeviceDescriptor, MountPoint};
+use crate::{
+    checked::checked_mul_u64,
+    device::{BusType, DeviceDescriptor, MediaType, MountPoint, PartitionInfo, SmartHealth},
+    probe::ProbeResult,
+    vm::is_virtual_disk_name,
+    DriveListError,
+};
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug)]
@@ -23,12 +36,13 @@ struct Device {
     #[serde(rename = "log-sec")]
     log_sec: u32,
     rm: bool,
-    ptype: Option<String>,
+    pttype: Option<String>,
     #[serde(default)]
     children: Vec<Child>,
     label: Option<String>,
     vendor: Option<String>,
     model: Option<String>,
+    serial: Option<String>,
     hotplug: bool,
 }
 
@@ -54,30 +68,223 @@ impl Device {
         .join(" ")
     }
 
-    fn is_virtual(&self) -> bool {
+    /// Not attached below a `block` subsystem, which has always been listed as removable
+    fn is_detached(&self) -> bool {
         !self.subsystems.contains("block")
     }
 
+    fn is_virtual(&self) -> bool {
+        self.is_detached() || self.is_zvol() || self.is_ram_disk()
+    }
+
+    fn is_ram_disk(&self) -> bool {
+        is_ram_disk(self.kname.rsplit('/').next().unwrap_or(&self.kname))
+    }
+
+    fn is_zvol(&self) -> bool {
+        is_zvol(self.kname.rsplit('/').next().unwrap_or(&self.kname))
+    }
+
+    /// Description, or the `pool/name` of a ZFS volume from its `zvol` link in `dev`
+    fn description_in(&self, dev: &Path) -> String {
+        if self.is_zvol() {
+            let name = self.kname.rsplit('/').next().unwrap_or(&self.kname);
+
+            if let Some(x) = zvol_name(dev, name) {
+                return x;
+            }
+        }
+
+        self.description()
+    }
+
+    /// lsblk has no transport for virtio disks on older util-linux and calls it `virtio` on newer
+    fn bus_type(&self) -> BusType {
+        match self.tran.as_deref() {
+            Some("virtio") => BusType::Virtual,
+            Some(x) => x.parse().unwrap_or(BusType::Unknown),
+            None if self.subsystems.contains("virtio") => BusType::Virtual,
+            None => BusType::Unknown,
+        }
+    }
+
+    /// Disk emulated by a hypervisor (virtio, Xen, QEMU, VMware, Hyper-V)
+    fn is_emulated(&self) -> bool {
+        self.subsystems.contains("virtio")
+            || self.name.starts_with("/dev/vd")
+            || self.name.starts_with("/dev/xvd")
+            || [&self.vendor, &self.model]
+                .iter()
+                .any(|x| x.as_deref().is_some_and(is_virtual_disk_name))
+    }
+
+    /// Name from the USB or PCI ID database, for devices without a vendor and model
+    #[cfg(feature = "ids")]
+    fn id_database_name(&self) -> Option<String> {
+        let parse = |dir: &std::path::Path, attr: &str| {
+            let x = fs::read_to_string(dir.join(attr)).ok()?;
+            u16::from_str_radix(x.trim().trim_start_matches("0x"), 16).ok()
+        };
+        let device = self.sysfs_device()?;
+
+        device.ancestors().find_map(|dir| {
+            if let (Some(vendor), Some(product)) = (parse(dir, "idVendor"), parse(dir, "idProduct"))
+            {
+                return crate::ids::usb_name(vendor, product);
+            }
+
+            // SCSI devices also have a textual `vendor` attribute
+            if fs::read_to_string(dir.join("vendor")).is_ok_and(|x| x.starts_with("0x"))
+                && let (Some(vendor), Some(device)) = (parse(dir, "vendor"), parse(dir, "device"))
+            {
+                return crate::ids::pci_name(vendor, device);
+            }
+
+            None
+        })
+    }
+
+    fn form_factor(&self) -> Option<String> {
+        match sysfs_attr(&self.kname, "device/type")?.as_str() {
+            "MMC" => Some("eMMC".to_string()),
+            "SD" => Some("SD".to_string()),
+            _ => None,
+        }
+    }
+
+    /// Sysfs path of the controller or hub the device is attached to
+    fn parent_path(&self) -> Option<String> {
+        let device = self.sysfs_device()?;
+
+        Some(device.parent()?.to_string_lossy().into_owned())
+    }
+
+    /// USB devices are named `<bus>-<port>[.<port>...]`, one port per hub level
+    fn behind_hub(&self) -> Option<bool> {
+        let device = self.sysfs_device()?;
+        let usb = device
+            .ancestors()
+            .find(|x| x.join("idVendor").exists() && x.join("busnum").exists())?;
+        let (_, ports) = usb.file_name()?.to_str()?.split_once('-')?;
+
+        Some(ports.contains('.'))
+    }
+
+    /// Canonical sysfs path of the underlying device, e.g. the SCSI or NVMe device
+    fn sysfs_device(&self) -> Option<std::path::PathBuf> {
+        fs::canonicalize(format!(
+            "/sys/class/block/{}/device",
+            self.kname.rsplit('/').next()?
+        ))
+        .ok()
+    }
+
+    /// sysfs directory of the block device
+    fn sysfs_dir(&self) -> Option<PathBuf> {
+        Some(Path::new("/sys/class/block").join(self.kname.rsplit('/').next()?))
+    }
+
+    /// Largest request size the hardware accepts
+    fn max_transfer_bytes(&self) -> Option<u32> {
+        let kb: u32 = fs::read_to_string(format!(
+            "/sys/class/block/{}/queue/max_hw_sectors_kb",
+            self.kname.rsplit('/').next()?
+        ))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+
+        kb.checked_mul(1024)
+    }
+
+    /// Volatile write cache state from the block queue
+    fn write_cache_enabled(&self) -> Option<bool> {
+        let mode = fs::read_to_string(format!(
+            "/sys/class/block/{}/queue/write_cache",
+            self.kname.rsplit('/').next()?
+        ))
+        .ok()?;
+
+        match mode.trim() {
+            "write back" => Some(true),
+            "write through" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Thin provisioning from the SCSI disk's provisioning (discard) mode
+    fn thin_provisioned(&self) -> Option<bool> {
+        let name = self.kname.rsplit('/').next()?;
+        let dir = fs::read_dir(format!("/sys/class/block/{}/device/scsi_disk", name))
+            .ok()?
+            .flatten()
+            .next()?;
+        let mode = fs::read_to_string(dir.path().join("provisioning_mode")).ok()?;
+
+        match mode.trim() {
+            "unmap" | "writesame_16" | "writesame_10" | "writesame_zero" => Some(true),
+            "full" | "disabled" => Some(false),
+            _ => None,
+        }
+    }
+
+    fn media_type(&self) -> MediaType {
+        if self.rm {
+            MediaType::RemovableMedia
+        } else if self.hotplug || self.subsystems.contains("usb") {
+            MediaType::ExternalFixed
+        } else {
+            MediaType::FixedMedia
+        }
+    }
+
+    fn is_special_partition(&self) -> bool {
+        is_special_partition(self.kname.rsplit('/').next().unwrap_or(&self.kname))
+    }
+
+    /// RAM disks and ZFS volumes are virtual but hold system data (e.g. zram swap or VM disks), so
+    /// they don't count as removable
     fn is_removable(&self) -> bool {
-        self.rm || self.hotplug || self.is_virtual()
+        self.rm || self.hotplug || self.is_detached()
     }
 
     fn is_system(&self) -> bool {
-        !(self.is_removable() || self.is_virtual())
+        !self.is_removable()
     }
 }
 
 impl From<Device> for DeviceDescriptor {
     fn from(value: Device) -> Self {
         let is_scsi = value.is_scsi();
-        let description = value.description();
-        let is_virtual = value.is_virtual();
+        let description = value.description_in(Path::new("/dev"));
+        #[cfg(feature = "ids")]
+        let description = if description.trim().is_empty() {
+            value.id_database_name().unwrap_or(description)
+        } else {
+            description
+        };
+        let is_virtual = value.is_virtual() || value.is_emulated();
         let is_removable = value.is_removable();
         let is_system = value.is_system();
+        let form_factor = value.form_factor();
+        let parent_path = value.parent_path();
+        let thin_provisioned = value.thin_provisioned();
+        let write_cache_enabled = value.write_cache_enabled();
+        let sysfs_dir = value.sysfs_dir();
+        let queue_count = sysfs_dir.as_deref().and_then(queue_count);
+        let queue_depth = sysfs_dir.as_deref().and_then(queue_depth);
+        let max_transfer_bytes = value.max_transfer_bytes();
+        let behind_hub = value.behind_hub();
+        let media_type = value.media_type();
+        let special_partition = value.is_special_partition();
+        let ram_disk = value.is_ram_disk();
 
         Self {
+            special_partition,
+            ram_disk,
             enumerator: "lsblk:json".to_string(),
-            bus_type: Some(value.tran.as_deref().unwrap_or("UNKNOWN").to_uppercase()),
+            bus_type: Some(value.bus_type()),
             device: value.name,
             raw: value.kname,
             is_virtual,
@@ -86,11 +293,34 @@ impl From<Device> for DeviceDescriptor {
             is_readonly: value.ro,
             description,
             size: value.size,
+            // Card readers without a card report a size of 0
+            media_present: Some(value.size != 0),
             block_size: value.phy_sec,
             logical_block_size: value.log_sec,
             is_removable,
             is_system,
-            partition_table_type: value.ptype,
+            form_factor,
+            parent_path,
+            thin_provisioned,
+            write_cache_enabled,
+            queue_count,
+            queue_depth,
+            max_transfer_bytes,
+            serial: value.serial.map(|x| x.trim().to_string()),
+            behind_hub,
+            media_type,
+            partition_table_type: value.pttype.as_deref().and_then(|x| x.parse().ok()),
+            mountpoint_labels: value
+                .children
+                .iter()
+                .filter_map(|x| x.label.clone().or_else(|| x.partlabel.clone()))
+                .collect(),
+            partitions: value
+                .children
+                .iter()
+                .filter(|x| x.kind.as_deref() == Some("part"))
+                .map(Into::into)
+                .collect(),
             mountpoints: value.children.into_iter().map(Into::into).collect(),
             ..Default::default()
         }
@@ -99,11 +329,34 @@ impl From<Device> for DeviceDescriptor {
 
 #[derive(Deserialize, Debug)]
 struct Child {
+    name: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    size: Option<u64>,
+    /// Offset in 512 byte sectors
+    start: Option<u64>,
+    parttype: Option<String>,
+    /// Partition table type of the parent drive
+    pttype: Option<String>,
+    partflags: Option<String>,
     mountpoint: Option<String>,
     fssize: Option<u64>,
     fsavail: Option<u64>,
     label: Option<String>,
     partlabel: Option<String>,
+    fstype: Option<String>,
+    /// Mount options from `/proc/self/mounts`
+    #[serde(skip)]
+    options: Option<String>,
+}
+
+impl Child {
+    /// Fall back to the label from `labels`, as returned by [`labels_by_device`]
+    fn fill_label(&mut self, labels: &HashMap<String, String>) {
+        if self.label.is_none() {
+            self.label = self.name.as_ref().and_then(|x| labels.get(x)).cloned();
+        }
+    }
 }
 
 impl From<Child> for MountPoint {
@@ -117,20 +370,1262 @@ impl From<Child> for MountPoint {
             },
             total_bytes: value.fssize,
             available_bytes: value.fsavail,
+            case_sensitive: match value.fstype.as_deref() {
+                Some("vfat" | "msdos" | "exfat") => Some(false),
+                Some(
+                    "ext2" | "ext3" | "ext4" | "btrfs" | "xfs" | "f2fs" | "zfs" | "ntfs" | "ntfs3",
+                ) => Some(true),
+                _ => None,
+            },
+            compressed: value
+                .options
+                .map(|x| x.split(',').any(|x| x.starts_with("compress"))),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&Child> for PartitionInfo {
+    fn from(value: &Child) -> Self {
+        Self {
+            path: value.name.clone().unwrap_or_default(),
+            name: value.partlabel.clone(),
+            label: value.label.clone(),
+            partition_type: value.parttype.clone(),
+            offset: value.start.and_then(|x| checked_mul_u64(x, 512)),
+            size: value.size,
+            bootable: (value.pttype.as_deref() == Some("dos")).then(|| {
+                value
+                    .partflags
+                    .as_deref()
+                    .and_then(|x| u8::from_str_radix(x.trim_start_matches("0x"), 16).ok())
+                    .is_some_and(|x| x & 0x80 != 0)
+            }),
+            ..Default::default()
         }
     }
 }
 
+/// Kernel name of an eMMC boot (`mmcblkNbootM`) or RPMB (`mmcblkNrpmb`) hardware partition
+fn is_special_partition(name: &str) -> bool {
+    name.strip_prefix("mmcblk").is_some_and(|x| {
+        let area = x.trim_start_matches(|c: char| c.is_ascii_digit());
+        area.len() != x.len() && (area.starts_with("boot") || area == "rpmb")
+    })
+}
+
+/// Kernel name of a RAM disk, `ramN` (brd, which lsblk hides by default) or `zramN`
+/// ZFS volume (`zdN`), which is only a plain block device as far as sysfs is concerned
+fn is_zvol(name: &str) -> bool {
+    name.strip_prefix("zd")
+        .is_some_and(|x| !x.is_empty() && x.bytes().all(|x| x.is_ascii_digit()))
+}
+
+/// `pool/name` of the ZFS volume `name`, from its symlink below `dev`/zvol
+fn zvol_name(dev: &Path, name: &str) -> Option<String> {
+    fn find(dir: &Path, target: &Path) -> Option<PathBuf> {
+        for entry in fs::read_dir(dir).ok()?.flatten() {
+            let path = entry.path();
+            let file_type = entry.file_type().ok()?;
+
+            if file_type.is_dir() {
+                if let Some(x) = find(&path, target) {
+                    return Some(x);
+                }
+            } else if file_type.is_symlink() && fs::canonicalize(&path).is_ok_and(|x| x == target) {
+                return Some(path);
+            }
+        }
+
+        None
+    }
+
+    let root = dev.join("zvol");
+    let link = find(&root, &fs::canonicalize(dev.join(name)).ok()?)?;
+
+    Some(link.strip_prefix(&root).ok()?.to_string_lossy().to_string())
+}
+
+fn is_ram_disk(name: &str) -> bool {
+    name.strip_prefix("ram")
+        .or_else(|| name.strip_prefix("zram"))
+        .is_some_and(|x| !x.is_empty() && x.bytes().all(|x| x.is_ascii_digit()))
+}
+
 pub(crate) fn lsblk() -> anyhow::Result<Vec<DeviceDescriptor>> {
     let output = Command::new("lsblk")
         .args(["--bytes", "--all", "--json", "--paths", "--output-all"])
-        .output()?;
+        .output()
+        .map_err(DriveListError::Io)?;
 
     if !output.status.success() {
-        return Err(anyhow::Error::msg("lsblk fail"));
+        return Err(DriveListError::command("lsblk", &output).into());
+    }
+
+    let mut res: Devices = serde_json::from_slice(&output.stdout)?;
+
+    let maintenance = md_maintenance();
+
+    // lsblk only knows labels which are in the udev database
+    let labels = labels_by_device(Path::new("/dev/disk/by-label"));
+    let options = mount_options(&read_mounts());
+    for child in res
+        .blockdevices
+        .iter_mut()
+        .flat_map(|x| x.children.iter_mut())
+    {
+        child.fill_label(&labels);
+        child.options = child
+            .mountpoint
+            .as_ref()
+            .and_then(|x| options.get(x))
+            .cloned();
+    }
+
+    Ok(coalesce_multipath(
+        res.blockdevices
+            .into_iter()
+            .map(|x| {
+                // lsblk itself queries all devices at once, so this only covers sysfs
+                let start = Instant::now();
+                let mut drive = DeviceDescriptor::from(x);
+                drive.maintenance_active = maintenance
+                    .as_ref()
+                    .map(|names| in_maintenance(&drive, names));
+                drive.enumeration_duration = Some(start.elapsed());
+                drive
+            })
+            .collect(),
+        multipath_devices(Path::new("/sys")),
+    ))
+}
+
+/// List drives from `/sys/block` and `/proc/self/mounts`, for systems without `lsblk` (e.g.
+/// minimal initramfs or container images).
+///
+/// Only the basic fields are filled in.
+pub(crate) fn sysfs() -> anyhow::Result<Vec<DeviceDescriptor>> {
+    sysfs_at(Path::new("/sys"), &block_mounts(&read_mounts()))
+}
+
+/// List drives from the sysfs tree at `root`, with the mountpoints of each device node in `mounts`
+fn sysfs_at(
+    root: &Path,
+    mounts: &HashMap<String, Vec<String>>,
+) -> anyhow::Result<Vec<DeviceDescriptor>> {
+    let read = |path: &Path| fs::read_to_string(path).ok().map(|x| x.trim().to_string());
+    let read_u64 = |path: &Path| read(path).and_then(|x| x.parse::<u64>().ok());
+    let mut drives = Vec::new();
+
+    for entry in fs::read_dir(root.join("block"))
+        .map_err(DriveListError::Io)?
+        .flatten()
+    {
+        let dir = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        let size = read_u64(&dir.join("size"))
+            .and_then(|x| checked_mul_u64(x, 512))
+            .unwrap_or_default();
+
+        if size == 0 {
+            continue;
+        }
+
+        let ram_disk = is_ram_disk(&name);
+        let pinned = ram_disk || is_zvol(&name);
+        let in_virtual =
+            fs::canonicalize(&dir).is_ok_and(|x| x.starts_with(root.join("devices/virtual")));
+        let is_virtual = pinned || in_virtual;
+        // Same as lsblk: RAM disks and ZFS volumes are virtual, but not removable
+        let is_removable = read_u64(&dir.join("removable")) == Some(1) || (in_virtual && !pinned);
+        let description = [
+            read(&dir.join("device/vendor")),
+            read(&dir.join("device/model")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+        let mut partitions: Vec<PartitionInfo> = fs::read_dir(&dir)
+            .map_err(DriveListError::Io)?
+            .flatten()
+            .filter(|x| x.path().join("partition").exists())
+            .map(|x| PartitionInfo {
+                path: format!("/dev/{}", x.file_name().to_string_lossy()),
+                offset: read_u64(&x.path().join("start")).and_then(|x| checked_mul_u64(x, 512)),
+                size: read_u64(&x.path().join("size")).and_then(|x| checked_mul_u64(x, 512)),
+                ..Default::default()
+            })
+            .collect();
+        partitions.sort_by_key(|x| x.offset);
+
+        let device = format!("/dev/{}", name);
+        let mountpoints = std::iter::once(&device)
+            .chain(partitions.iter().map(|x| &x.path))
+            .flat_map(|x| mounts.get(x).into_iter().flatten())
+            .map(|path| {
+                let (total_bytes, available_bytes) = statvfs(path).unzip();
+
+                MountPoint {
+                    path: path.clone(),
+                    total_bytes,
+                    available_bytes,
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        drives.push(DeviceDescriptor {
+            enumerator: "sysfs".to_string(),
+            device: device.clone(),
+            raw: device,
+            description,
+            size,
+            block_size: read_u64(&dir.join("queue/physical_block_size")).unwrap_or(512) as u32,
+            logical_block_size: read_u64(&dir.join("queue/logical_block_size")).unwrap_or(512)
+                as u32,
+            is_readonly: read_u64(&dir.join("ro")) == Some(1),
+            is_removable,
+            is_virtual,
+            ram_disk,
+            special_partition: is_special_partition(&name),
+            is_system: !is_removable,
+            partitions,
+            mountpoints,
+            ..Default::default()
+        });
+    }
+
+    Ok(drives)
+}
+
+/// Line of `/proc/self/mounts`
+#[derive(Debug, PartialEq)]
+struct Mount {
+    source: String,
+    path: String,
+    fstype: String,
+    options: String,
+}
+
+impl Mount {
+    /// Not backed by a block device (`proc`, `tmpfs`, `cgroup`, ...)
+    fn is_pseudo(&self) -> bool {
+        !self.source.starts_with("/dev/")
+    }
+}
+
+fn read_mounts() -> Vec<Mount> {
+    parse_mounts(&fs::read_to_string("/proc/self/mounts").unwrap_or_default())
+}
+
+fn parse_mounts(mounts: &str) -> Vec<Mount> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+
+            Some(Mount {
+                source: fields.next()?.to_string(),
+                path: unescape_mount_path(fields.next()?),
+                fstype: fields.next()?.to_string(),
+                options: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Map device nodes to the paths they are mounted at
+fn block_mounts(mounts: &[Mount]) -> HashMap<String, Vec<String>> {
+    let mut res: HashMap<String, Vec<String>> = HashMap::new();
+
+    for mount in mounts.iter().filter(|x| !x.is_pseudo()) {
+        res.entry(mount.source.clone())
+            .or_default()
+            .push(mount.path.clone());
+    }
+
+    res
+}
+
+/// One virtual drive per pseudo filesystem source and type, with all its mountpoints
+pub(crate) fn pseudo_filesystems() -> Vec<DeviceDescriptor> {
+    pseudo_drives(&read_mounts())
+}
+
+fn pseudo_drives(mounts: &[Mount]) -> Vec<DeviceDescriptor> {
+    let mut drives: Vec<DeviceDescriptor> = Vec::new();
+
+    for mount in mounts.iter().filter(|x| x.is_pseudo()) {
+        let (total_bytes, available_bytes) = statvfs(&mount.path).unzip();
+        let mountpoint = MountPoint {
+            path: mount.path.clone(),
+            total_bytes,
+            available_bytes,
+            ..Default::default()
+        };
+
+        match drives
+            .iter_mut()
+            .find(|x| x.device == mount.source && x.description == mount.fstype)
+        {
+            Some(drive) => drive.mountpoints.push(mountpoint),
+            None => drives.push(DeviceDescriptor {
+                enumerator: "mounts".to_string(),
+                device: mount.source.clone(),
+                raw: mount.source.clone(),
+                description: mount.fstype.clone(),
+                is_virtual: true,
+                mountpoints: vec![mountpoint],
+                ..Default::default()
+            }),
+        }
+    }
+
+    drives
+}
+
+/// Total and available bytes of the filesystem mounted at `path`
+fn statvfs(path: &str) -> Option<(u64, u64)> {
+    let path = std::ffi::CString::new(path).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    let fragment = stat.f_frsize as u64;
+
+    Some((
+        checked_mul_u64(stat.f_blocks as u64, fragment)?,
+        checked_mul_u64(stat.f_bavail as u64, fragment)?,
+    ))
+}
+
+/// Number of hardware queues of the multi-queue block device with the sysfs directory `dir`
+fn queue_count(dir: &Path) -> Option<u32> {
+    let entries = fs::read_dir(dir.join("mq")).ok()?;
+
+    Some(entries.flatten().filter(|x| x.path().is_dir()).count() as u32).filter(|x| *x != 0)
+}
+
+/// Number of requests the block layer queues for the block device with the sysfs directory `dir`
+fn queue_depth(dir: &Path) -> Option<u32> {
+    fs::read_to_string(dir.join("queue/nr_requests"))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Multipath device
+struct Multipath {
+    /// `/dev/mapper` node
+    device: String,
+    /// `/dev/dm-N` node
+    raw: String,
+    /// Device nodes of the individual paths
+    paths: Vec<String>,
+}
+
+/// Find multipath devices using the device mapper entries in `block` of the sysfs tree at `root`
+fn multipath_devices(root: &Path) -> Vec<Multipath> {
+    let Ok(entries) = fs::read_dir(root.join("block")) else {
+        return Vec::new();
+    };
+
+    entries
+        .flatten()
+        .filter(|x| x.file_name().to_string_lossy().starts_with("dm-"))
+        .filter_map(|entry| {
+            let dir = entry.path();
+            let uuid = fs::read_to_string(dir.join("dm/uuid")).ok()?;
+
+            if !uuid.starts_with("mpath-") {
+                return None;
+            }
+
+            let name = fs::read_to_string(dir.join("dm/name")).ok()?;
+            let mut paths: Vec<String> = fs::read_dir(dir.join("slaves"))
+                .ok()?
+                .flatten()
+                .map(|x| format!("/dev/{}", x.file_name().to_string_lossy()))
+                .collect();
+            paths.sort();
+
+            Some(Multipath {
+                device: format!("/dev/mapper/{}", name.trim()),
+                raw: format!("/dev/{}", entry.file_name().to_string_lossy()),
+                paths,
+            })
+        })
+        .collect()
+}
+
+/// Replace the individual paths of each multipath device by a single drive for the mapper device
+fn coalesce_multipath(
+    mut drives: Vec<DeviceDescriptor>,
+    multipaths: Vec<Multipath>,
+) -> Vec<DeviceDescriptor> {
+    for multipath in multipaths {
+        let Some(first) = drives
+            .iter()
+            .find(|x| multipath.paths.contains(&x.raw))
+            .cloned()
+        else {
+            continue;
+        };
+
+        drives.retain(|x| !multipath.paths.contains(&x.raw));
+        drives.push(DeviceDescriptor {
+            device: multipath.device,
+            raw: multipath.raw,
+            backing_devices: multipath.paths,
+            ..first
+        });
+    }
+
+    drives
+}
+
+/// Read a sysfs attribute of a block device, e.g. `sysfs_attr("/dev/sda", "queue/rotational")`
+fn sysfs_attr(device: &str, attr: &str) -> Option<String> {
+    let name = device.rsplit('/').next()?;
+
+    fs::read_to_string(format!("/sys/class/block/{}/{}", name, attr))
+        .ok()
+        .map(|x| x.trim().to_string())
+}
+
+/// Map device nodes to their filesystem label using the udev symlinks in `dir`, usually
+/// `/dev/disk/by-label`
+fn labels_by_device(dir: &Path) -> HashMap<String, String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let target = fs::canonicalize(entry.path()).ok()?;
+            let label = unescape_udev(&entry.file_name().to_string_lossy());
+
+            Some((target.to_string_lossy().to_string(), label))
+        })
+        .collect()
+}
+
+/// The drive or one of its partitions is in `names`, as returned by [`md_maintenance`]
+fn in_maintenance(drive: &DeviceDescriptor, names: &HashSet<String>) -> bool {
+    std::iter::once(&drive.raw)
+        .chain(drive.partitions.iter().map(|x| &x.path))
+        .any(|x| names.contains(x.rsplit('/').next().unwrap_or(x)))
+}
+
+/// Kernel names of md arrays with a resync, recovery, check or reshape in progress, and of their
+/// members. `None` if `/proc/mdstat` is unavailable.
+fn md_maintenance() -> Option<HashSet<String>> {
+    fs::read_to_string("/proc/mdstat")
+        .ok()
+        .map(|x| parse_mdstat(&x))
+}
+
+fn parse_mdstat(mdstat: &str) -> HashSet<String> {
+    let mut names = HashSet::new();
+    let mut array: Vec<&str> = Vec::new();
+
+    for line in mdstat.lines() {
+        if let Some((name, status)) = line.split_once(" : ") {
+            // Members look like `sda1[0]`, possibly followed by flags such as `(F)`
+            array = std::iter::once(name.trim())
+                .chain(
+                    status
+                        .split_whitespace()
+                        .filter_map(|x| x.split_once('[').map(|(x, _)| x)),
+                )
+                .collect();
+        } else if ["resync", "recovery", "check", "reshape"]
+            .iter()
+            .any(|x| line.contains(&format!("{} =", x)))
+        {
+            names.extend(array.iter().map(|x| x.to_string()));
+        }
+    }
+
+    names
+}
+
+/// Map mount paths to their mount options.
+///
+/// Mountpoints themselves come from lsblk, so only block devices are listed. Pseudo filesystems
+/// are skipped here as well, so one mounted over a block device's mountpoint doesn't replace its
+/// options.
+fn mount_options(mounts: &[Mount]) -> HashMap<String, String> {
+    mounts
+        .iter()
+        .filter(|x| !x.is_pseudo())
+        .map(|x| (x.path.clone(), x.options.clone()))
+        .collect()
+}
+
+/// Decode the octal escapes (e.g. `\040` for space) used in `/proc/self/mounts`
+fn unescape_mount_path(path: &str) -> String {
+    path.replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
+
+/// Decode the `\xNN` escapes udev uses in `/dev/disk/by-*` link names
+fn unescape_udev(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'\\' && bytes.get(i + 1) == Some(&b'x') {
+            bytes
+                .get(i + 2..i + 4)
+                .and_then(|x| std::str::from_utf8(x).ok())
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+        } else {
+            None
+        };
+
+        if let Some(x) = escaped {
+            res.push(x);
+            i += 4;
+            continue;
+        }
+
+        res.push(bytes[i]);
+        i += 1;
     }
 
-    let res: Devices = serde_json::from_slice(&output.stdout).unwrap();
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+/// `_IOR(0x12, 114, size_t)`
+const BLKGETSIZE64: libc::c_ulong = 0x8008_1272;
+/// `_IO(0x12, 123)`
+const BLKPBSZGET: libc::c_ulong = 0x127B;
+
+pub(crate) fn probe_capabilities(device: &str) -> anyhow::Result<Vec<ProbeResult>> {
+    let file = fs::File::open(device).map_err(DriveListError::Io)?;
+    let ioctl = |request: libc::c_ulong| {
+        let mut value = 0_u64;
+
+        if unsafe { libc::ioctl(file.as_raw_fd(), request as _, &mut value) } == 0 {
+            Ok(())
+        } else {
+            Err(std::io::Error::last_os_error().raw_os_error().unwrap_or(-1))
+        }
+    };
+    let attr = |attrs: &[&str]| {
+        let name = device.rsplit('/').next().unwrap_or_default();
+        let mut error = libc::ENOENT;
+
+        for attr in attrs {
+            match fs::read_to_string(format!("/sys/class/block/{}/{}", name, attr)) {
+                Ok(_) => return Ok(()),
+                Err(err) => error = err.raw_os_error().unwrap_or(error),
+            }
+        }
+
+        Err(error)
+    };
+
+    Ok(vec![
+        ProbeResult::new("geometry", ioctl(BLKGETSIZE64)),
+        ProbeResult::new("alignment", ioctl(BLKPBSZGET)),
+        ProbeResult::new("serial", attr(&["device/serial", "device/wwid", "wwid"])),
+        ProbeResult::new("trim", attr(&["queue/discard_max_bytes"])),
+    ])
+}
+
+/// `struct nvme_admin_cmd` from `linux/nvme_ioctl.h`
+#[repr(C)]
+#[derive(Default)]
+struct NvmeAdminCmd {
+    opcode: u8,
+    flags: u8,
+    rsvd1: u16,
+    nsid: u32,
+    cdw2: u32,
+    cdw3: u32,
+    metadata: u64,
+    addr: u64,
+    metadata_len: u32,
+    data_len: u32,
+    cdw10: u32,
+    cdw11: u32,
+    cdw12: u32,
+    cdw13: u32,
+    cdw14: u32,
+    cdw15: u32,
+    timeout_ms: u32,
+    result: u32,
+}
+
+/// `_IOWR('N', 0x41, struct nvme_admin_cmd)`
+const NVME_IOCTL_ADMIN_CMD: libc::c_ulong = 0xC048_4E41;
+
+/// `struct sg_io_hdr` from `scsi/sg.h`
+#[repr(C)]
+struct SgIoHdr {
+    interface_id: libc::c_int,
+    dxfer_direction: libc::c_int,
+    cmd_len: u8,
+    mx_sb_len: u8,
+    iovec_count: u16,
+    dxfer_len: u32,
+    dxferp: *mut libc::c_void,
+    cmdp: *mut u8,
+    sbp: *mut u8,
+    timeout: u32,
+    flags: u32,
+    pack_id: libc::c_int,
+    usr_ptr: *mut libc::c_void,
+    status: u8,
+    masked_status: u8,
+    msg_status: u8,
+    sb_len_wr: u8,
+    host_status: u16,
+    driver_status: u16,
+    resid: libc::c_int,
+    duration: u32,
+    info: u32,
+}
+
+impl SgIoHdr {
+    fn new(cdb: &mut [u8], sense: &mut [u8], direction: libc::c_int, data: &mut [u8]) -> Self {
+        Self {
+            interface_id: b'S' as _,
+            dxfer_direction: direction,
+            cmd_len: cdb.len() as _,
+            mx_sb_len: sense.len() as _,
+            iovec_count: 0,
+            dxfer_len: data.len() as _,
+            dxferp: if data.is_empty() {
+                std::ptr::null_mut()
+            } else {
+                data.as_mut_ptr() as _
+            },
+            cmdp: cdb.as_mut_ptr(),
+            sbp: sense.as_mut_ptr(),
+            timeout: 3000,
+            flags: 0,
+            pack_id: 0,
+            usr_ptr: std::ptr::null_mut(),
+            status: 0,
+            masked_status: 0,
+            msg_status: 0,
+            sb_len_wr: 0,
+            host_status: 0,
+            driver_status: 0,
+            resid: 0,
+            duration: 0,
+            info: 0,
+        }
+    }
+}
+
+const SG_IO: libc::c_ulong = 0x2285;
+const SG_DXFER_NONE: libc::c_int = -1;
+const SG_DXFER_FROM_DEV: libc::c_int = -3;
+
+/// Overall SMART health of a drive.
+///
+/// NVMe drives are failing if any critical warning bit is set, ATA drives use SMART RETURN STATUS.
+/// Every error results in [`SmartHealth::Unknown`].
+pub(crate) fn smart_health(device: &DeviceDescriptor) -> SmartHealth {
+    let Ok(file) = fs::File::open(&device.raw) else {
+        return SmartHealth::Unknown;
+    };
+
+    let health = if device.raw.starts_with("/dev/nvme") {
+        nvme_critical_warning(&file).map(nvme_health)
+    } else {
+        ata_smart_status(&file)
+    };
+
+    health.unwrap_or(SmartHealth::Unknown)
+}
+
+fn nvme_health(critical_warning: u8) -> SmartHealth {
+    if critical_warning == 0 {
+        SmartHealth::Passed
+    } else {
+        SmartHealth::Failing
+    }
+}
+
+/// Read the critical warning byte of the SMART / Health Information log page
+fn nvme_critical_warning(file: &fs::File) -> Option<u8> {
+    let mut log = [0_u8; 512];
+    let mut cmd = NvmeAdminCmd {
+        // Get Log Page
+        opcode: 0x02,
+        nsid: 0xFFFF_FFFF,
+        addr: log.as_mut_ptr() as u64,
+        data_len: log.len() as u32,
+        // Number of dwords - 1, log identifier 0x02
+        cdw10: ((log.len() as u32 / 4 - 1) << 16) | 0x02,
+        ..Default::default()
+    };
+
+    let res = unsafe { libc::ioctl(file.as_raw_fd(), NVME_IOCTL_ADMIN_CMD as _, &mut cmd) };
+
+    (res == 0).then_some(log[0])
+}
+
+/// Issue SMART RETURN STATUS through ATA PASS-THROUGH (16)
+fn ata_smart_status(file: &fs::File) -> Option<SmartHealth> {
+    let mut cdb: [u8; 16] = [
+        0x85, // ATA PASS-THROUGH (16)
+        0x06, // Non-data protocol
+        0x20, // Return the ATA registers in the sense data
+        0x00, 0xDA, // Features: SMART RETURN STATUS
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x4F, 0x00, 0xC2, // LBA
+        0x00, 0xB0, // Command: SMART
+        0x00,
+    ];
+    let mut sense = [0_u8; 32];
+    let mut hdr = SgIoHdr::new(&mut cdb, &mut sense, SG_DXFER_NONE, &mut []);
+
+    if unsafe { libc::ioctl(file.as_raw_fd(), SG_IO as _, &mut hdr) } != 0 {
+        return None;
+    }
+
+    // Descriptor format sense data with an ATA Status Return descriptor
+    if sense[0] != 0x72 || sense[8] != 0x09 {
+        return None;
+    }
+
+    match (sense[8 + 9], sense[8 + 11]) {
+        (0x4F, 0xC2) => Some(SmartHealth::Passed),
+        (0xF4, 0x2C) => Some(SmartHealth::Failing),
+        _ => None,
+    }
+}
+
+/// Check for TCG security protocol support, which self-encrypting drives use.
+///
+/// NVMe drives report Security Send / Receive support in the OACS field of Identify Controller,
+/// ATA drives report the Trusted Computing feature set in IDENTIFY DEVICE word 48.
+pub(crate) fn self_encrypting(device: &DeviceDescriptor) -> Option<bool> {
+    // Non-blocking, so opening an optical drive doesn't close its tray
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&device.raw)
+        .ok()?;
+
+    if device.raw.starts_with("/dev/nvme") {
+        nvme_security_supported(&nvme_identify_controller(&file)?)
+    } else {
+        ata_trusted_computing(&ata_identify(&file)?)
+    }
+}
+
+/// Security Send / Receive support (OACS bit 0) in NVMe Identify Controller data
+fn nvme_security_supported(identify: &[u8]) -> Option<bool> {
+    let oacs = u16::from_le_bytes([*identify.get(256)?, *identify.get(257)?]);
+
+    Some(oacs & 0x1 != 0)
+}
+
+/// Trusted Computing feature set (word 48 bit 0) in ATA IDENTIFY DEVICE data
+fn ata_trusted_computing(identify: &[u8]) -> Option<bool> {
+    let word = u16::from_le_bytes([*identify.get(96)?, *identify.get(97)?]);
+
+    // Bits 15:14 must be 01 for the word to be valid
+    (word & 0xC000 == 0x4000).then_some(word & 0x1 != 0)
+}
+
+fn nvme_identify_controller(file: &fs::File) -> Option<[u8; 4096]> {
+    let mut identify = [0_u8; 4096];
+    let mut cmd = NvmeAdminCmd {
+        // Identify
+        opcode: 0x06,
+        addr: identify.as_mut_ptr() as u64,
+        data_len: identify.len() as u32,
+        // CNS 1: Identify Controller
+        cdw10: 0x01,
+        ..Default::default()
+    };
+
+    let res = unsafe { libc::ioctl(file.as_raw_fd(), NVME_IOCTL_ADMIN_CMD as _, &mut cmd) };
+
+    (res == 0).then_some(identify)
+}
+
+/// Issue IDENTIFY DEVICE through ATA PASS-THROUGH (16)
+fn ata_identify(file: &fs::File) -> Option<[u8; 512]> {
+    let mut cdb: [u8; 16] = [
+        0x85, // ATA PASS-THROUGH (16)
+        0x08, // PIO Data-In protocol
+        0x0E, // Transfer from the device, length in sectors given by the sector count
+        0x00, 0x00, // Features
+        0x00, 0x01, // Sector count
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // LBA
+        0x00, 0xEC, // Command: IDENTIFY DEVICE
+        0x00,
+    ];
+    let mut sense = [0_u8; 32];
+    let mut identify = [0_u8; 512];
+    let mut hdr = SgIoHdr::new(&mut cdb, &mut sense, SG_DXFER_FROM_DEV, &mut identify);
+
+    let res = unsafe { libc::ioctl(file.as_raw_fd(), SG_IO as _, &mut hdr) };
+
+    (res == 0 && hdr.status == 0 && hdr.host_status == 0 && hdr.driver_status == 0)
+        .then_some(identify)
+}
+
+/// Check whether a drive is in standby, using ATA CHECK POWER MODE, which doesn't spin it up.
+///
+/// NVMe drives have no spindle and are never spun down.
+pub(crate) fn is_spun_down(device: &str) -> anyhow::Result<bool> {
+    let file = fs::File::open(device).map_err(DriveListError::Io)?;
+
+    if device.starts_with("/dev/nvme") {
+        return Ok(false);
+    }
+
+    let mut cdb: [u8; 16] = [
+        0x85, // ATA PASS-THROUGH (16)
+        0x06, // Non-data protocol
+        0x20, // Return the ATA registers in the sense data
+        0x00, 0x00, // Features
+        0x00, 0x00, // Sector count
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // LBA
+        0x00, 0xE5, // Command: CHECK POWER MODE
+        0x00,
+    ];
+    let mut sense = [0_u8; 32];
+    let mut hdr = SgIoHdr::new(&mut cdb, &mut sense, SG_DXFER_NONE, &mut []);
+
+    if unsafe { libc::ioctl(file.as_raw_fd(), SG_IO as _, &mut hdr) } != 0 {
+        return Err(DriveListError::Io(std::io::Error::last_os_error()).into());
+    }
+
+    power_mode_from_sense(&sense)
+        .ok_or_else(|| anyhow::Error::msg(format!("{}: no ATA status returned", device)))
+}
+
+/// Standby state from the sense data of CHECK POWER MODE, if it is in descriptor format with an
+/// ATA Status Return descriptor
+fn power_mode_from_sense(sense: &[u8]) -> Option<bool> {
+    if sense.len() < 14 || sense[0] != 0x72 || sense[8] != 0x09 {
+        return None;
+    }
+
+    Some(power_mode_is_standby(sense[8 + 5]))
+}
+
+/// Sector count returned by CHECK POWER MODE: 0x00 is standby, 0x01 is standby entered by the
+/// extended power conditions feature, everything else (idle, active) means spinning
+fn power_mode_is_standby(count: u8) -> bool {
+    matches!(count, 0x00 | 0x01)
+}
 
-    Ok(res.blockdevices.into_iter().map(Into::into).collect())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DriveKind, FlashReadiness};
+
+    const MDSTAT: &str = "\
+Personalities : [raid1] [raid6] [raid5] [raid4]
+md0 : active raid1 sdb1[1] sda1[0]
+      976630464 blocks super 1.2 [2/2] [UU]
+      [=====>...............]  resync = 27.5% (268696576/976630464) finish=57.3min speed=205884K/sec
+      bitmap: 6/8 pages [24KB], 65536KB chunk
+
+md1 : active raid5 sde[2] sdd[1] sdc[0](F)
+      1953260544 blocks super 1.2 level 5, 512k chunk, algorithm 2 [3/2] [_UU]
+
+unused devices: <none>
+";
+
+    /// Drives in `fixtures/sysfs`, sorted by device
+    fn sysfs_fixture() -> Vec<DeviceDescriptor> {
+        let root =
+            fs::canonicalize(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/sysfs")).unwrap();
+        let mut drives = sysfs_at(&root, &HashMap::new()).unwrap();
+        drives.sort_by(|a, b| a.device.cmp(&b.device));
+
+        drives
+    }
+
+    #[test]
+    fn emmc_hardware_partitions() {
+        let drives = sysfs_fixture();
+
+        let special: Vec<_> = drives
+            .iter()
+            .filter(|x| x.special_partition)
+            .map(|x| x.device.as_str())
+            .collect();
+        assert_eq!(
+            special,
+            ["/dev/mmcblk0boot0", "/dev/mmcblk0boot1", "/dev/mmcblk0rpmb"]
+        );
+
+        let emmc = drives.iter().find(|x| x.device == "/dev/mmcblk0").unwrap();
+        assert!(!emmc.special_partition);
+        assert_eq!(emmc.kind(), DriveKind::Internal);
+        assert_eq!(emmc.partitions.len(), 2);
+        assert_eq!(emmc.partitions[0].path, "/dev/mmcblk0p1");
+        assert_eq!(emmc.partitions[0].offset, Some(8192 * 512));
+
+        assert!(!is_special_partition("mmcblk0p1"));
+        assert!(!is_special_partition("mmcblkboot0"));
+    }
+
+    #[test]
+    fn ram_disks() {
+        let drives = sysfs_fixture();
+
+        for device in ["/dev/ram0", "/dev/zram0"] {
+            let drive = drives.iter().find(|x| x.device == device).unwrap();
+
+            assert!(drive.ram_disk);
+            assert!(drive.is_virtual);
+            assert_eq!(drive.kind(), DriveKind::RamDisk);
+        }
+
+        for device in ["/dev/ram0", "/dev/zram0"] {
+            let drive = drives.iter().find(|x| x.device == device).unwrap();
+
+            assert!(!drive.is_removable);
+            assert!(drive.is_system);
+            assert!(matches!(
+                drive.flash_readiness(),
+                FlashReadiness::NotReady(_)
+            ));
+        }
+
+        let zram = DeviceDescriptor::from(lsblk_device("/dev/zram0", None, "block"));
+        assert!(zram.ram_disk);
+        assert!(zram.is_virtual);
+        assert!(!zram.is_removable);
+        assert!(zram.is_system);
+        assert!(matches!(
+            zram.flash_readiness(),
+            FlashReadiness::NotReady(_)
+        ));
+
+        assert!(!is_ram_disk("ram"));
+        assert!(!is_ram_disk("ramdisk"));
+    }
+
+    /// Sense data as returned by ATA PASS-THROUGH, with `count` in the sector count register
+    fn ata_status_sense(count: u8) -> [u8; 32] {
+        let mut sense = [0; 32];
+        sense[0] = 0x72;
+        sense[7] = 14;
+        sense[8] = 0x09;
+        sense[9] = 12;
+        sense[13] = count;
+        sense
+    }
+
+    #[test]
+    fn power_mode() {
+        assert_eq!(power_mode_from_sense(&ata_status_sense(0x00)), Some(true));
+        assert_eq!(power_mode_from_sense(&ata_status_sense(0x01)), Some(true));
+        assert_eq!(power_mode_from_sense(&ata_status_sense(0x80)), Some(false));
+        assert_eq!(power_mode_from_sense(&ata_status_sense(0xFF)), Some(false));
+
+        // Fixed format sense data, e.g. from a bridge without ATA pass-through
+        let mut fixed = [0; 32];
+        fixed[0] = 0x70;
+        assert_eq!(power_mode_from_sense(&fixed), None);
+        assert_eq!(power_mode_from_sense(&[]), None);
+    }
+
+    const MOUNTS: &str = "\
+/dev/sda2 / ext4 rw,relatime 0 0
+proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+sysfs /sys sysfs rw,nosuid,nodev,noexec,relatime 0 0
+tmpfs /run tmpfs rw,nosuid,nodev,size=1620232k,mode=755 0 0
+cgroup2 /sys/fs/cgroup cgroup2 rw,nosuid,nodev,noexec,relatime 0 0
+tmpfs /tmp tmpfs rw,nosuid,nodev 0 0
+/dev/sdb1 /media/my\\040stick vfat ro,relatime 0 0
+";
+
+    #[test]
+    fn pseudo_filesystems_excluded() {
+        let mounts = parse_mounts(MOUNTS);
+        assert_eq!(mounts.len(), 7);
+
+        let block = block_mounts(&mounts);
+        assert_eq!(block.len(), 2);
+        assert_eq!(block["/dev/sda2"], ["/"]);
+        assert_eq!(block["/dev/sdb1"], ["/media/my stick"]);
+
+        let options = mount_options(&mounts);
+        assert_eq!(options.len(), 2);
+        assert_eq!(options["/media/my stick"], "ro,relatime");
+        assert!(!options.contains_key("/proc"));
+    }
+
+    #[test]
+    fn pseudo_filesystems_included() {
+        let drives = pseudo_drives(&parse_mounts(MOUNTS));
+
+        let devices: Vec<_> = drives.iter().map(|x| x.device.as_str()).collect();
+        assert_eq!(devices, ["proc", "sysfs", "tmpfs", "cgroup2"]);
+        assert!(drives.iter().all(|x| x.is_virtual));
+
+        let tmpfs: Vec<_> = drives[2].mountpoints.iter().map(|x| &x.path).collect();
+        assert_eq!(tmpfs, ["/run", "/tmp"]);
+    }
+
+    #[test]
+    fn zfs_volumes() {
+        let dev = fs::canonicalize(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/dev")).unwrap();
+        let zvol = lsblk_device("/dev/zd0", None, "block");
+
+        assert!(zvol.is_virtual());
+        assert!(!zvol.is_removable());
+        assert!(zvol.is_system());
+        assert_eq!(zvol.description_in(&dev), "tank/vm-100-disk-0");
+        assert_eq!(zvol_name(&dev, "zd16"), None);
+
+        let drives = sysfs_fixture();
+        let zvol = drives.iter().find(|x| x.device == "/dev/zd0").unwrap();
+        assert!(zvol.is_virtual);
+        assert!(!zvol.is_removable);
+        assert!(zvol.is_system);
+        assert!(matches!(
+            zvol.flash_readiness(),
+            FlashReadiness::NotReady(_)
+        ));
+    }
+
+    #[test]
+    fn labels_of_unmounted_partitions() {
+        let dev = fs::canonicalize(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/dev")).unwrap();
+        let labels = labels_by_device(&dev.join("disk/by-label"));
+        let node = |name: &str| dev.join(name).to_string_lossy().to_string();
+
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels[&node("sda1")], "BOOT");
+        assert_eq!(labels[&node("sda2")], "Fotós");
+
+        let mut child: Child = serde_json::from_value(serde_json::json!({
+            "name": node("sda2"),
+            "type": "part",
+            "size": 1024,
+            "start": 2048,
+            "parttype": null,
+            "pttype": "gpt",
+            "partflags": null,
+            "mountpoint": null,
+            "fssize": null,
+            "fsavail": null,
+            "label": null,
+            "partlabel": "data",
+            "fstype": "ext4",
+        }))
+        .unwrap();
+        child.fill_label(&labels);
+
+        let partition = PartitionInfo::from(&child);
+        assert_eq!(partition.label.as_deref(), Some("Fotós"));
+        assert_eq!(partition.name.as_deref(), Some("data"));
+    }
+
+    fn lsblk_device(name: &str, tran: Option<&str>, subsystems: &str) -> Device {
+        serde_json::from_value(serde_json::json!({
+            "size": 0,
+            "kname": name,
+            "name": name,
+            "tran": tran,
+            "subsystems": subsystems,
+            "ro": false,
+            "phy-sec": 512,
+            "log-sec": 512,
+            "rm": false,
+            "pttype": null,
+            "label": null,
+            "vendor": null,
+            "model": null,
+            "serial": null,
+            "hotplug": false,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn virtio_bus() {
+        assert_eq!(
+            lsblk_device("/dev/vda", None, "block:virtio:pci").bus_type(),
+            BusType::Virtual
+        );
+        assert_eq!(
+            lsblk_device("/dev/vda", Some("virtio"), "block:virtio:pci").bus_type(),
+            BusType::Virtual
+        );
+        assert_eq!(
+            lsblk_device("/dev/nvme0n1", Some("nvme"), "block:nvme:pci").bus_type(),
+            BusType::Nvme
+        );
+        assert_eq!(
+            lsblk_device("/dev/sda", None, "block").bus_type(),
+            BusType::Unknown
+        );
+    }
+
+    #[test]
+    fn mdstat_resync() {
+        let names = parse_mdstat(MDSTAT);
+
+        let mut sorted: Vec<_> = names.iter().map(String::as_str).collect();
+        sorted.sort();
+        assert_eq!(sorted, ["md0", "sda1", "sdb1"]);
+
+        let member = DeviceDescriptor {
+            raw: "/dev/sda".to_string(),
+            partitions: vec![PartitionInfo::new("/dev/sda1")],
+            ..Default::default()
+        };
+        assert!(in_maintenance(&member, &names));
+
+        let degraded = DeviceDescriptor {
+            raw: "/dev/sdc".to_string(),
+            ..Default::default()
+        };
+        assert!(!in_maintenance(&degraded, &names));
+    }
+
+    #[test]
+    fn linux_platform() {
+        let drive =
+            DeviceDescriptor::from(lsblk_device("/dev/sda", Some("sata"), "block:scsi:pci"));
+        assert_eq!(drive.platform, crate::Platform::Linux);
+
+        assert!(sysfs_fixture()
+            .iter()
+            .all(|x| x.platform == crate::Platform::Linux));
+    }
+
+    #[test]
+    fn media_types() {
+        let internal_ssd = lsblk_device("/dev/sda", Some("sata"), "block:scsi:pci");
+        let mut usb_stick = lsblk_device("/dev/sdb", Some("usb"), "block:scsi:usb:pci");
+        usb_stick.rm = true;
+        let usb_ssd = lsblk_device("/dev/sdc", Some("usb"), "block:scsi:usb:pci");
+
+        assert_eq!(
+            DeviceDescriptor::from(internal_ssd).media_type,
+            MediaType::FixedMedia
+        );
+        assert_eq!(
+            DeviceDescriptor::from(usb_stick).media_type,
+            MediaType::RemovableMedia
+        );
+        assert_eq!(
+            DeviceDescriptor::from(usb_ssd).media_type,
+            MediaType::ExternalFixed
+        );
+    }
+
+    fn multipath_fixture() -> Vec<Multipath> {
+        let mut multipaths = multipath_devices(Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/multipath"
+        )));
+        multipaths.sort_by(|a, b| a.raw.cmp(&b.raw));
+        multipaths
+    }
+
+    #[test]
+    fn multipath_sysfs() {
+        // dm-1 is an LVM volume
+        let [multipath] = &multipath_fixture()[..] else {
+            panic!("expected only the multipath device");
+        };
+
+        assert_eq!(multipath.device, "/dev/mapper/mpatha");
+        assert_eq!(multipath.raw, "/dev/dm-0");
+        assert_eq!(multipath.paths, ["/dev/sda", "/dev/sdb"]);
+    }
+
+    #[test]
+    fn multipath_coalesced() {
+        let drive = |raw: &str| DeviceDescriptor {
+            device: raw.to_string(),
+            raw: raw.to_string(),
+            size: 1 << 40,
+            ..Default::default()
+        };
+        let drives = vec![drive("/dev/sda"), drive("/dev/sdb"), drive("/dev/sdc")];
+
+        let drives = coalesce_multipath(drives, multipath_fixture());
+
+        let [sdc, mpath] = &drives[..] else {
+            panic!("expected sdc and the multipath device, got {:?}", drives);
+        };
+        assert_eq!(sdc.device, "/dev/sdc");
+        assert_eq!(mpath.device, "/dev/mapper/mpatha");
+        assert_eq!(mpath.raw, "/dev/dm-0");
+        assert_eq!(mpath.size, 1 << 40);
+        assert_eq!(mpath.backing_devices, ["/dev/sda", "/dev/sdb"]);
+    }
+
+    #[test]
+    fn nvme_critical_warning_bits() {
+        assert_eq!(nvme_health(0), SmartHealth::Passed);
+        // Available spare below threshold
+        assert_eq!(nvme_health(0x01), SmartHealth::Failing);
+        // NVM subsystem reliability degraded
+        assert_eq!(nvme_health(0x04), SmartHealth::Failing);
+        // Volatile memory backup failed, with the media read-only
+        assert_eq!(nvme_health(0x18), SmartHealth::Failing);
+    }
+
+    /// Identify Controller data of an Opal drive: Security Send / Receive, Format NVM and
+    /// Firmware Download
+    #[test]
+    fn nvme_opal_identify() {
+        let mut identify = [0_u8; 4096];
+        identify[256..258].copy_from_slice(&0x0007_u16.to_le_bytes());
+        assert_eq!(nvme_security_supported(&identify), Some(true));
+
+        identify[256..258].copy_from_slice(&0x0006_u16.to_le_bytes());
+        assert_eq!(nvme_security_supported(&identify), Some(false));
+        assert_eq!(nvme_security_supported(&identify[..257]), None);
+    }
+
+    /// IDENTIFY DEVICE data of an Opal SATA drive, with word 48 valid and bit 0 set
+    #[test]
+    fn ata_opal_identify() {
+        let mut identify = [0_u8; 512];
+        identify[96..98].copy_from_slice(&0x4001_u16.to_le_bytes());
+        assert_eq!(ata_trusted_computing(&identify), Some(true));
+
+        identify[96..98].copy_from_slice(&0x4000_u16.to_le_bytes());
+        assert_eq!(ata_trusted_computing(&identify), Some(false));
+
+        // Older drives leave the word zeroed, which isn't valid
+        identify[96..98].copy_from_slice(&0x0001_u16.to_le_bytes());
+        assert_eq!(ata_trusted_computing(&identify), None);
+    }
+
+    #[test]
+    fn nvme_queues() {
+        let fixtures = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures"));
+        let nvme = fixtures.join("block/nvme0n1");
+
+        assert_eq!(queue_count(&nvme), Some(4));
+        assert_eq!(queue_depth(&nvme), Some(1023));
+
+        // Single queue devices have no `mq` directory
+        let mmc = fixtures.join("sysfs/block/mmcblk0");
+        assert_eq!(queue_count(&mmc), None);
+        assert_eq!(queue_depth(&mmc), None);
+    }
 }
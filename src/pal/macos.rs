@@ -1,78 +1,150 @@
-use std::process::Command;
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+};
 
-use serde::Deserialize;
+use super::diskutil::{diskutil_info, DiskUtilOutput, DiskUtilSource};
+use crate::{
+    device::{DeviceDescriptor, SmartHealth},
+    probe::ProbeResult,
+    DriveListError,
+};
 
-use crate::device::{DeviceDescriptor, MountPoint};
+/// Runs the real `diskutil`
+pub(crate) struct DiskUtilCommand;
 
-#[derive(Deserialize, Debug)]
-struct Disks {
-    #[serde(rename = "AllDisksAndPartitions")]
-    all_disks_and_partitions: Vec<Disk>,
+/// Absolute path of `diskutil`, resolved from `PATH` once per process
+fn diskutil_path() -> &'static Path {
+    static PATH: OnceLock<PathBuf> = OnceLock::new();
+
+    resolve_once(&PATH, || find_diskutil(std::env::var_os("PATH").as_deref()))
 }
 
-#[derive(Deserialize, Debug)]
-struct Disk {
-    #[serde(rename = "DeviceIdentifier")]
-    device_identifier: String,
-    #[serde(rename = "OSInternal")]
-    os_internal: bool,
-    #[serde(rename = "Size")]
-    size: u64,
-    #[serde(rename = "Content")]
-    content: String,
-    #[serde(rename = "Partitions")]
-    partitions: Vec<Partition>,
+/// Path cached in `cell`, calling `resolve` only for the first call
+fn resolve_once(cell: &OnceLock<PathBuf>, resolve: impl FnOnce() -> PathBuf) -> &Path {
+    cell.get_or_init(resolve)
 }
 
-#[derive(Deserialize, Debug)]
-struct Partition {
-    #[serde(rename = "MountPoint")]
-    mount_point: Option<String>,
-    #[serde(rename = "Content")]
-    content: String,
-    #[serde(rename = "Size")]
-    size: u64,
+/// First `diskutil` in the directories of `path`, falling back to its standard location
+fn find_diskutil(path: Option<&OsStr>) -> PathBuf {
+    path.iter()
+        .flat_map(std::env::split_paths)
+        .map(|x| x.join("diskutil"))
+        .find(|x| x.is_file())
+        .unwrap_or_else(|| PathBuf::from("/usr/sbin/diskutil"))
 }
 
-impl From<Disk> for DeviceDescriptor {
-    fn from(value: Disk) -> Self {
-        DeviceDescriptor {
-            enumerator: "diskutil".to_string(),
-            description: value.content,
-            size: value.size,
-            mountpoints: value.partitions.into_iter().map(MountPoint::from).collect(),
-            device: format!("/dev/{}", value.device_identifier),
-            raw: format!("/dev/r{}", value.device_identifier),
-            is_system: value.os_internal,
-            is_removable: !value.os_internal,
-            ..Default::default()
+impl DiskUtilCommand {
+    fn run(&self, command: &str, args: &[&str]) -> anyhow::Result<DiskUtilOutput> {
+        // Usually means diskutil is missing, e.g. in a sandbox
+        let output = Command::new(diskutil_path())
+            .arg(command)
+            .arg("-plist")
+            .args(args)
+            .output()
+            .map_err(|x| {
+                anyhow::Error::from(DriveListError::Io(x))
+                    .context(format!("Couldn't run {}", diskutil_path().display()))
+            })?;
+
+        if !output.status.success() {
+            return Err(DriveListError::command(format!("diskutil {}", command), &output).into());
         }
+
+        Ok(DiskUtilOutput {
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            stdout: output.stdout,
+        })
     }
 }
 
-impl From<Partition> for MountPoint {
-    fn from(value: Partition) -> Self {
-        MountPoint {
-            path: value.mount_point.unwrap_or_default(),
-            label: Some(value.content),
-            total_bytes: Some(value.size),
-            available_bytes: None,
-        }
+impl DiskUtilSource for DiskUtilCommand {
+    fn list(&self) -> anyhow::Result<DiskUtilOutput> {
+        self.run("list", &[])
+    }
+
+    fn info(&self, device_identifier: &str) -> anyhow::Result<DiskUtilOutput> {
+        self.run("info", &[device_identifier])
     }
 }
 
-pub(crate) fn diskutil() -> anyhow::Result<Vec<DeviceDescriptor>> {
-    let output = Command::new("diskutil").args(["list", "-plist"]).output()?;
+/// SMART status as reported by `diskutil info`
+pub(crate) fn smart_health(device: &DeviceDescriptor) -> SmartHealth {
+    let id = device.device.trim_start_matches("/dev/");
+
+    match diskutil_info(&DiskUtilCommand, id)
+        .ok()
+        .and_then(|x| x.smart_status)
+        .as_deref()
+    {
+        Some("Verified") => SmartHealth::Passed,
+        Some("Failing") => SmartHealth::Failing,
+        _ => SmartHealth::Unknown,
+    }
+}
+
+/// `diskutil info` is the only query made per device
+pub(crate) fn probe_capabilities(device: &str) -> anyhow::Result<Vec<ProbeResult>> {
+    let id = device.trim_start_matches("/dev/");
+    let info = diskutil_info(&DiskUtilCommand, id)
+        .map(|_| ())
+        .map_err(|err| {
+            err.downcast_ref::<DriveListError>()
+                .and_then(|x| match x {
+                    DriveListError::Command { status, .. } => status.code(),
+                    DriveListError::Io(err) => err.raw_os_error(),
+                    _ => None,
+                })
+                .unwrap_or(-1)
+        });
+
+    Ok(vec![ProbeResult::new("info", info)])
+}
+
+/// Power state queries need IOKit, which isn't used yet
+pub(crate) fn is_spun_down(_device: &str) -> anyhow::Result<bool> {
+    Err(DriveListError::Unsupported.into())
+}
+
+/// Security protocol support isn't reported by `diskutil`
+pub(crate) fn self_encrypting(_device: &DeviceDescriptor) -> Option<bool> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
-    if !output.status.success() {
-        return Err(anyhow::Error::msg("diskutil fail"));
+    use super::*;
+
+    #[test]
+    fn resolved_once() {
+        let cell = OnceLock::new();
+        let calls = AtomicUsize::new(0);
+        let resolve = || {
+            calls.fetch_add(1, Ordering::SeqCst);
+            PathBuf::from("/opt/bin/diskutil")
+        };
+
+        for _ in 0..3 {
+            assert_eq!(resolve_once(&cell, resolve), Path::new("/opt/bin/diskutil"));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
     }
 
-    let parsed: Disks = plist::from_bytes(&output.stdout).unwrap();
+    #[test]
+    fn diskutil_from_path() {
+        let dir = std::env::temp_dir().join(format!("bb-drivelist-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("diskutil"), "").unwrap();
 
-    Ok(parsed
-        .all_disks_and_partitions
-        .into_iter()
-        .map(DeviceDescriptor::from)
-        .collect())
+        let path = std::env::join_paths(["/nonexistent".as_ref(), dir.as_path()]).unwrap();
+        let found = find_diskutil(Some(&path));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(found, dir.join("diskutil"));
+        assert_eq!(find_diskutil(None), Path::new("/usr/sbin/diskutil"));
+    }
 }
@@ -1,83 +1,495 @@
+#[cfg(any(target_os = "windows", test))]
+mod decode;
+#[cfg(any(target_os = "macos", test, feature = "fuzzing"))]
+mod diskutil;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+#[cfg(any(target_os = "windows", test, feature = "fuzzing"))]
+mod layout;
 #[cfg(target_os = "linux")]
 mod linux;
 #[cfg(target_os = "macos")]
 mod macos;
+#[cfg(any(target_os = "windows", test))]
+mod topology;
+#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+mod unsupported;
+#[cfg(any(target_os = "windows", test))]
+mod win32;
 #[cfg(target_os = "windows")]
 mod windows;
 
-use crate::DeviceDescriptor;
+use std::{
+    sync::{Arc, PoisonError, RwLock},
+    time::{Duration, Instant},
+};
+
+use crate::{DeviceDescriptor, DriveListQuery};
+
+/// Backend which lists the drives of a platform.
+///
+/// All listing functions go through the active backend, which is the native one unless replaced
+/// with [`set_pal`]. Replacing it lets tests run against canned drives on any host. Shared
+/// post-processing (normalization, query options) still applies to drives from any backend.
+pub trait Pal: Send + Sync {
+    /// List all drives, adding problems not tied to a single drive to `diagnostics`
+    fn enumerate(
+        &self,
+        query: &DriveListQuery,
+        diagnostics: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<DeviceDescriptor>>;
+
+    /// Value which changes whenever drives are added, removed, changed, mounted or unmounted, if
+    /// there is a cheap one.
+    ///
+    /// [`drive_list_since`](crate::drive_list_since) doesn't list the drives again while it stays
+    /// the same. Defaults to `None`, which always lists.
+    fn change_sequence(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Backend of the platform this crate was compiled for
+struct Native;
+
+impl Pal for Native {
+    fn enumerate(
+        &self,
+        query: &DriveListQuery,
+        diagnostics: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<DeviceDescriptor>> {
+        drive_list(query, diagnostics)
+    }
+
+    fn change_sequence(&self) -> Option<u64> {
+        change_sequence()
+    }
+}
+
+static ACTIVE: RwLock<Option<Arc<dyn Pal>>> = RwLock::new(None);
+
+/// Replace the backend used by all listing functions in this process
+pub fn set_pal(pal: impl Pal + 'static) {
+    *ACTIVE.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(pal));
+}
+
+/// Go back to the native backend
+pub fn reset_pal() {
+    *ACTIVE.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// Run `f` with `pal` as the backend, one test at a time since the backend is process wide
+#[cfg(test)]
+pub(crate) fn with_pal<T>(pal: impl Pal + 'static, f: impl FnOnce() -> T) -> T {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    let _guard = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    set_pal(pal);
+    let result = f();
+    reset_pal();
+
+    result
+}
+
+/// Backend returning a fixed sequence of drive lists, repeating the last one
+#[cfg(test)]
+pub(crate) struct Scripted(std::sync::Mutex<Vec<Vec<DeviceDescriptor>>>);
+
+#[cfg(test)]
+impl Scripted {
+    pub(crate) fn new(mut lists: Vec<Vec<DeviceDescriptor>>) -> Self {
+        lists.reverse();
+        Self(std::sync::Mutex::new(lists))
+    }
+}
+
+#[cfg(test)]
+impl Pal for Scripted {
+    fn enumerate(
+        &self,
+        _query: &DriveListQuery,
+        _diagnostics: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<DeviceDescriptor>> {
+        let mut lists = self.0.lock().unwrap();
+
+        Ok(if lists.len() > 1 {
+            lists.pop().unwrap()
+        } else {
+            lists.last().cloned().unwrap_or_default()
+        })
+    }
+}
+
+/// Backend used for listing
+pub(crate) fn active() -> Arc<dyn Pal> {
+    ACTIVE
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+        .unwrap_or_else(|| Arc::new(Native))
+}
+
+/// Warns about enumeration stages which take longer than a soft deadline.
+///
+/// Stages are never interrupted, the warning is only added to the diagnostics.
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(
+    not(any(target_os = "windows", target_os = "linux", target_os = "macos")),
+    allow(dead_code)
+)]
+pub(crate) struct Watchdog(Option<Duration>);
+
+#[cfg_attr(
+    not(any(target_os = "windows", target_os = "linux", target_os = "macos")),
+    allow(dead_code)
+)]
+impl Watchdog {
+    pub(crate) fn new(deadline: Option<Duration>) -> Self {
+        Self(deadline)
+    }
+
+    /// Check a stage of `device` which started at `start`
+    pub(crate) fn check(
+        &self,
+        start: Instant,
+        device: &str,
+        stage: &str,
+        diagnostics: &mut Vec<String>,
+    ) {
+        let elapsed = start.elapsed();
+
+        if let Some(deadline) = self.0
+            && elapsed > deadline
+        {
+            diagnostics.push(format!(
+                "{}: {} took {:?} (deadline {:?})",
+                device, stage, elapsed, deadline
+            ));
+        }
+    }
+}
+
+/// Post-processing shared by all platforms
+pub(crate) fn finish(drives: &mut [DeviceDescriptor]) {
+    for drive in drives.iter_mut() {
+        drive.normalize_mountpoints();
+        drive.normalize_strings();
+        drive.compute_partition_alignment();
+        drive.check_size_consistency();
+    }
+
+    crate::list::flag_nested_mountpoints(drives);
+    crate::mark_user_home(drives);
+}
 
 #[cfg(target_os = "windows")]
-pub(crate) fn drive_list() -> anyhow::Result<Vec<DeviceDescriptor>> {
-    use std::{
-        mem::{size_of, zeroed},
-        ptr::null_mut,
-    };
-    use windows::*;
+fn drive_list(
+    query: &DriveListQuery,
+    diagnostics: &mut Vec<String>,
+) -> anyhow::Result<Vec<DeviceDescriptor>> {
+    use std::ptr::null_mut;
 
     use winapi::um::{
         handleapi::INVALID_HANDLE_VALUE,
         setupapi::{
-            SetupDiDestroyDeviceInfoList, SetupDiEnumDeviceInfo, SetupDiGetClassDevsA,
-            DIGCF_DEVICEINTERFACE, DIGCF_PRESENT, SP_DEVINFO_DATA,
+            SetupDiDestroyDeviceInfoList, SetupDiGetClassDevsA, DIGCF_DEVICEINTERFACE,
+            DIGCF_PRESENT,
         },
-        winioctl::GUID_DEVINTERFACE_DISK,
     };
 
     let mut drives: Vec<DeviceDescriptor> = Vec::new();
 
-    unsafe {
-        let h_device_info = SetupDiGetClassDevsA(
-            &GUID_DEVINTERFACE_DISK,
-            null_mut(),
-            null_mut(),
-            DIGCF_PRESENT | DIGCF_DEVICEINTERFACE,
-        );
+    for interface_guid in interface_guids(query) {
+        unsafe {
+            let h_device_info = SetupDiGetClassDevsA(
+                interface_guid,
+                null_mut(),
+                null_mut(),
+                DIGCF_PRESENT | DIGCF_DEVICEINTERFACE,
+            );
 
-        if h_device_info != INVALID_HANDLE_VALUE {
-            let mut i = 0;
-            let mut device_info_data: SP_DEVINFO_DATA = zeroed();
-            device_info_data.cbSize = size_of::<SP_DEVINFO_DATA>() as _;
-
-            while SetupDiEnumDeviceInfo(h_device_info, i, &mut device_info_data) != 0 {
-                let enumerator_name = get_enumerator_name(h_device_info, &mut device_info_data);
-                let friendly_name = get_friendly_name(h_device_info, &mut device_info_data);
-
-                if friendly_name.is_empty() {
-                    continue;
-                }
-
-                let mut item = DeviceDescriptor {
-                    description: friendly_name.clone(),
-                    enumerator: enumerator_name.clone(),
-                    is_usb: is_usb_drive(&enumerator_name),
-                    is_removable: is_removable(h_device_info, &mut device_info_data),
-                    ..Default::default()
-                };
-
-                get_detail_data(&mut item, h_device_info, &mut device_info_data);
-                let bt = item.bus_type.clone().unwrap_or("UNKNOWN".to_string());
-                item.is_system = item.is_system || is_system_device(&item);
-                item.is_card = ["SDCARD", "MMC"].contains(&bt.as_str());
-                item.is_uas = Some(&item.enumerator == "SCSI" && bt == "USB");
-                item.is_virtual = item.is_virtual || bt == "VIRTUAL" || bt == "FILEBACKEDVIRTUAL";
-                drives.push(item);
-                i += 1;
+            if h_device_info != INVALID_HANDLE_VALUE {
+                drives.extend(windows::drive_list_from_device_info(
+                    h_device_info,
+                    interface_guid,
+                    Watchdog::new(query.slow_stage_deadline),
+                    query.minimal,
+                    query.cancel.as_deref(),
+                    diagnostics,
+                ));
+            } else {
+                diagnostics.push(format!(
+                    "SetupDiGetClassDevsA failed: {}",
+                    std::io::Error::last_os_error()
+                ));
             }
+
+            SetupDiDestroyDeviceInfoList(h_device_info);
         }
 
-        SetupDiDestroyDeviceInfoList(h_device_info);
+        query.check_cancelled()?;
     }
 
+    win32::mark_usb_enumerators(&mut drives, &query.usb_enumerators);
+
     Ok(drives)
 }
 
+/// Device interface classes to enumerate, `GUID_DEVINTERFACE_DISK` unless the query has some
+#[cfg(target_os = "windows")]
+fn interface_guids(query: &DriveListQuery) -> &[winapi::shared::guiddef::GUID] {
+    use winapi::um::winioctl::GUID_DEVINTERFACE_DISK;
+
+    if query.interface_guids.is_empty() {
+        &[GUID_DEVINTERFACE_DISK]
+    } else {
+        &query.interface_guids
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub(crate) use windows::drive_list_from_device_info;
+
 #[cfg(target_os = "linux")]
-pub(crate) fn drive_list() -> anyhow::Result<Vec<DeviceDescriptor>> {
-    linux::lsblk()
+fn drive_list(
+    query: &DriveListQuery,
+    diagnostics: &mut Vec<String>,
+) -> anyhow::Result<Vec<DeviceDescriptor>> {
+    query.check_cancelled()?;
+
+    let start = Instant::now();
+    let drives = linux::lsblk();
+    Watchdog::new(query.slow_stage_deadline).check(start, "lsblk", "enumeration", diagnostics);
+
+    // lsblk isn't installed everywhere (e.g. minimal containers)
+    let mut drives = match drives {
+        Err(err)
+            if err
+                .downcast_ref::<crate::DriveListError>()
+                .is_some_and(|x| matches!(x, crate::DriveListError::Io(_))) =>
+        {
+            diagnostics.push(format!("lsblk unavailable, falling back to sysfs: {}", err));
+            linux::sysfs()
+        }
+        drives => drives,
+    }?;
+
+    if query.pseudo_filesystems {
+        drives.extend(linux::pseudo_filesystems());
+    }
+
+    Ok(drives)
 }
 
 #[cfg(target_os = "macos")]
-pub(crate) fn drive_list() -> anyhow::Result<Vec<DeviceDescriptor>> {
-    macos::diskutil()
+fn drive_list(
+    query: &DriveListQuery,
+    diagnostics: &mut Vec<String>,
+) -> anyhow::Result<Vec<DeviceDescriptor>> {
+    diskutil::diskutil(
+        &macos::DiskUtilCommand,
+        Watchdog::new(query.slow_stage_deadline),
+        query.cancel.as_deref(),
+        diagnostics,
+    )
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+fn drive_list(
+    _query: &DriveListQuery,
+    _diagnostics: &mut Vec<String>,
+) -> anyhow::Result<Vec<DeviceDescriptor>> {
+    unsupported::drive_list()
+}
+
+#[cfg(target_os = "windows")]
+pub(crate) use windows::{is_spun_down, probe_capabilities, self_encrypting, smart_health};
+
+#[cfg(target_os = "linux")]
+pub(crate) use linux::{is_spun_down, probe_capabilities, self_encrypting, smart_health};
+
+#[cfg(target_os = "macos")]
+pub(crate) use macos::{is_spun_down, probe_capabilities, self_encrypting, smart_health};
+
+#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+pub(crate) use unsupported::{
+    has_required_privileges, is_spun_down, probe_capabilities, self_encrypting, smart_health,
+};
+
+/// Kernel uevent sequence number, which changes whenever a device is added, removed or changed,
+/// combined with a fingerprint of the mount table, since mounting emits no uevent
+#[cfg(target_os = "linux")]
+fn change_sequence() -> Option<u64> {
+    use std::hash::{DefaultHasher, Hasher};
+
+    let seqnum: u64 = std::fs::read_to_string("/sys/kernel/uevent_seqnum")
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    let mountinfo = std::fs::read("/proc/self/mountinfo").ok()?;
+
+    let mut hasher = DefaultHasher::new();
+    hasher.write_u64(seqnum);
+    hasher.write(&mountinfo);
+
+    Some(hasher.finish())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn change_sequence() -> Option<u64> {
+    None
+}
+
+#[cfg(target_os = "windows")]
+pub(crate) fn has_required_privileges() -> bool {
+    windows::is_elevated()
+}
+
+/// Credentials of the process which decide access to raw block devices
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+struct Credentials {
+    euid: libc::uid_t,
+    egid: libc::gid_t,
+    groups: Vec<libc::gid_t>,
+}
+
+/// Root, or a member of the group owning the raw block devices, if it exists
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn is_privileged(credentials: &Credentials, raw_disk_gid: Option<libc::gid_t>) -> bool {
+    credentials.euid == 0
+        || raw_disk_gid
+            .is_some_and(|gid| credentials.egid == gid || credentials.groups.contains(&gid))
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub(crate) fn has_required_privileges() -> bool {
+    use std::ptr::null_mut;
+
+    // Group owning the raw block devices
+    #[cfg(target_os = "linux")]
+    const RAW_DISK_GROUP: &[u8] = b"disk\0";
+    #[cfg(target_os = "macos")]
+    const RAW_DISK_GROUP: &[u8] = b"operator\0";
+
+    unsafe {
+        let group = libc::getgrnam(RAW_DISK_GROUP.as_ptr() as _);
+        let raw_disk_gid = (!group.is_null()).then(|| (*group).gr_gid);
+
+        let mut groups = Vec::new();
+        let count = libc::getgroups(0, null_mut());
+
+        if count > 0 {
+            groups.resize(count as usize, 0);
+            let count = libc::getgroups(count, groups.as_mut_ptr());
+            groups.truncate(count.max(0) as usize);
+        }
+
+        let credentials = Credentials {
+            euid: libc::geteuid(),
+            egid: libc::getegid(),
+            groups,
+        };
+
+        is_privileged(&credentials, raw_disk_gid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchdog() {
+        let mut diagnostics = Vec::new();
+        let start = Instant::now() - Duration::from_millis(20);
+
+        Watchdog::new(None).check(start, "sda", "geometry", &mut diagnostics);
+        Watchdog::new(Some(Duration::from_secs(60))).check(
+            start,
+            "sda",
+            "geometry",
+            &mut diagnostics,
+        );
+        assert!(diagnostics.is_empty());
+
+        Watchdog::new(Some(Duration::from_millis(10))).check(
+            start,
+            "sda",
+            "geometry",
+            &mut diagnostics,
+        );
+        assert!(matches!(&diagnostics[..], [x] if x.starts_with("sda: geometry took")));
+    }
+
+    struct Stub;
+
+    impl Pal for Stub {
+        fn enumerate(
+            &self,
+            _query: &DriveListQuery,
+            diagnostics: &mut Vec<String>,
+        ) -> anyhow::Result<Vec<DeviceDescriptor>> {
+            diagnostics.push("stub".to_string());
+
+            Ok(vec![DeviceDescriptor {
+                device: "/dev/stub".to_string(),
+                description: " Stub  Disk ".to_string(),
+                ..Default::default()
+            }])
+        }
+    }
+
+    #[test]
+    fn stub_pal() {
+        let (drives, diagnostics) = with_pal(Stub, crate::list_with_warnings).unwrap();
+
+        let [drive] = &drives[..] else {
+            panic!("expected the stub drive, got {:?}", drives);
+        };
+        assert_eq!(drive.device, "/dev/stub");
+        // Shared post-processing still applies
+        assert_eq!(drive.description, "Stub Disk");
+        assert!(diagnostics.contains(&"stub".to_string()));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn privileges() {
+        const DISK: libc::gid_t = 6;
+
+        let user = |euid, egid, groups: &[libc::gid_t]| Credentials {
+            euid,
+            egid,
+            groups: groups.to_vec(),
+        };
+
+        assert!(is_privileged(&user(0, 0, &[]), Some(DISK)));
+        assert!(is_privileged(&user(0, 0, &[]), None));
+        assert!(is_privileged(&user(1000, DISK, &[]), Some(DISK)));
+        assert!(is_privileged(&user(1000, 1000, &[27, DISK]), Some(DISK)));
+        assert!(!is_privileged(&user(1000, 1000, &[27]), Some(DISK)));
+        assert!(!is_privileged(&user(1000, 1000, &[DISK]), None));
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn interface_guids_default_to_disk() {
+        use winapi::{
+            shared::guiddef::IsEqualGUID,
+            um::winioctl::{GUID_DEVINTERFACE_CDROM, GUID_DEVINTERFACE_DISK},
+        };
+
+        let default = DriveListQuery::new();
+        assert!(
+            matches!(interface_guids(&default), [x] if IsEqualGUID(x, &GUID_DEVINTERFACE_DISK))
+        );
+
+        let query = DriveListQuery::new()
+            .interface_guids([GUID_DEVINTERFACE_DISK, GUID_DEVINTERFACE_CDROM]);
+        assert!(matches!(
+            interface_guids(&query),
+            [_, x] if IsEqualGUID(x, &GUID_DEVINTERFACE_CDROM)
+        ));
+    }
 }
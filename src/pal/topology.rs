@@ -0,0 +1,123 @@
+//! Parsing of the `STORAGE_PHYSICAL_TOPOLOGY_DESCRIPTOR` returned for
+//! `StorageDevicePhysicalTopologyProperty`.
+//!
+//! This is where Windows reports the form factor, which storport miniports (e.g. `stornvme`) fill
+//! in from the device. Like the drive layout, fields are read at their fixed offsets so the parsing
+//! can be tested on any platform.
+
+/// Offset of `Node` in `STORAGE_PHYSICAL_TOPOLOGY_DESCRIPTOR`
+const NODES_OFFSET: usize = 16;
+/// Size of `STORAGE_PHYSICAL_NODE_DATA`
+const NODE_SIZE: usize = 40;
+/// Offset of `DeviceCount` in `STORAGE_PHYSICAL_NODE_DATA`, followed by `DeviceDataLength` and
+/// `DeviceDataOffset`
+const NODE_DEVICES: usize = 16;
+/// Offset of `FormFactor` in `STORAGE_PHYSICAL_DEVICE_DATA`
+const DEVICE_FORM_FACTOR: usize = 20;
+
+/// Only read by the Windows backend
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+pub(crate) const BUFFER_SIZE: usize = 4096;
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Name of a `STORAGE_DEVICE_FORM_FACTOR`, `None` for `FormFactorUnknown` and unknown values
+pub(crate) fn form_factor_name(code: u32) -> Option<&'static str> {
+    match code {
+        1 => Some("3.5\""),
+        2 => Some("2.5\""),
+        3 => Some("1.8\""),
+        4 => Some("<1.8\""),
+        5 => Some("Embedded"),
+        6 => Some("Memory card"),
+        7 => Some("mSATA"),
+        8 => Some("M.2"),
+        9 => Some("PCIe card"),
+        10 => Some("DIMM"),
+        _ => None,
+    }
+}
+
+/// Form factor of the first device of the first node.
+///
+/// A disk handle only reports its own node, so there is at most one device to look at. Offsets
+/// outside of `bytes` give `None`.
+pub(crate) fn parse(bytes: &[u8]) -> Option<&'static str> {
+    if u32_at(bytes, 8)? == 0 {
+        return None;
+    }
+
+    let node = bytes.get(NODES_OFFSET..NODES_OFFSET + NODE_SIZE)?;
+    if u32_at(node, NODE_DEVICES)? == 0 {
+        return None;
+    }
+
+    // Relative to the start of the descriptor
+    let device = u32_at(node, NODE_DEVICES + 8)? as usize;
+
+    form_factor_name(u32_at(bytes, device.checked_add(DEVICE_FORM_FACTOR)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Size of `STORAGE_PHYSICAL_DEVICE_DATA`
+    const DEVICE_SIZE: usize = 136;
+
+    /// Topology of an NVMe drive as `stornvme` reports it: one node with one adapter and one
+    /// device, the data of both following the node
+    fn nvme(form_factor: u32) -> Vec<u8> {
+        let adapter_offset = NODES_OFFSET + NODE_SIZE;
+        let device_offset = adapter_offset + 64;
+        let mut bytes = vec![0; device_offset + DEVICE_SIZE];
+
+        let mut put = |offset: usize, value: usize| {
+            bytes[offset..offset + 4].copy_from_slice(&(value as u32).to_le_bytes())
+        };
+        put(0, 1);
+        put(4, device_offset + DEVICE_SIZE);
+        put(8, 1);
+        put(NODES_OFFSET + 4, 1);
+        put(NODES_OFFSET + 8, 64);
+        put(NODES_OFFSET + 12, adapter_offset);
+        put(NODES_OFFSET + NODE_DEVICES, 1);
+        put(NODES_OFFSET + NODE_DEVICES + 4, DEVICE_SIZE);
+        put(NODES_OFFSET + NODE_DEVICES + 8, device_offset);
+        put(device_offset + DEVICE_FORM_FACTOR, form_factor as usize);
+
+        bytes
+    }
+
+    #[test]
+    fn nvme_m2() {
+        assert_eq!(parse(&nvme(8)), Some("M.2"));
+        assert_eq!(parse(&nvme(2)), Some("2.5\""));
+        assert_eq!(parse(&nvme(0)), None);
+        assert_eq!(parse(&nvme(11)), None);
+    }
+
+    #[test]
+    fn every_truncation() {
+        let bytes = nvme(8);
+        let end = NODES_OFFSET + NODE_SIZE + 64 + DEVICE_FORM_FACTOR + 4;
+
+        for len in 0..bytes.len() {
+            let expected = (len >= end).then_some("M.2");
+            assert_eq!(parse(&bytes[..len]), expected);
+        }
+    }
+
+    #[test]
+    fn offset_out_of_bounds() {
+        let mut bytes = nvme(8);
+        let at = NODES_OFFSET + NODE_DEVICES + 8;
+        bytes[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        assert_eq!(parse(&bytes), None);
+    }
+}
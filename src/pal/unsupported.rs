@@ -0,0 +1,27 @@
+//! Fallback for targets without a backend, so the crate still builds there
+
+use crate::{device::SmartHealth, probe::ProbeResult, DeviceDescriptor, DriveListError};
+
+pub(crate) fn drive_list() -> anyhow::Result<Vec<DeviceDescriptor>> {
+    Err(DriveListError::Unsupported.into())
+}
+
+pub(crate) fn probe_capabilities(_device: &str) -> anyhow::Result<Vec<ProbeResult>> {
+    Err(DriveListError::Unsupported.into())
+}
+
+pub(crate) fn is_spun_down(_device: &str) -> anyhow::Result<bool> {
+    Err(DriveListError::Unsupported.into())
+}
+
+pub(crate) fn smart_health(_device: &DeviceDescriptor) -> SmartHealth {
+    SmartHealth::Unknown
+}
+
+pub(crate) fn self_encrypting(_device: &DeviceDescriptor) -> Option<bool> {
+    None
+}
+
+pub(crate) fn has_required_privileges() -> bool {
+    false
+}
@@ -0,0 +1,1452 @@
+//! Enumeration of the Windows backend over the few Win32 calls it makes.
+//!
+//! SetupAPI and configuration manager calls go through [`DeviceInfoSource`], file, volume and
+//! ioctl calls through [`Win32Source`]. Like the `diskutil` parsing, everything on top of them is
+//! plain Rust, so the enumeration can be tested against a fake device tree on any platform.
+
+use std::{
+    io,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
+
+use super::{
+    decode::{self, has_windows_boot_layout, DeviceNumber},
+    layout, topology, Watchdog,
+};
+use crate::{
+    checked::checked_mul_u64, device::*, probe::ProbeResult, vm::is_virtual_disk_name,
+    DriveListError,
+};
+
+/// `IOCTL_STORAGE_GET_DEVICE_NUMBER`
+pub(crate) const IOCTL_STORAGE_GET_DEVICE_NUMBER: u32 = 0x002d_1080;
+/// `IOCTL_STORAGE_QUERY_PROPERTY`
+pub(crate) const IOCTL_STORAGE_QUERY_PROPERTY: u32 = 0x002d_1400;
+/// `IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS`
+pub(crate) const IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS: u32 = 0x0056_0000;
+/// `IOCTL_DISK_GET_DRIVE_GEOMETRY_EX`
+pub(crate) const IOCTL_DISK_GET_DRIVE_GEOMETRY_EX: u32 = 0x0007_00a0;
+/// `IOCTL_DISK_GET_DRIVE_LAYOUT_EX`
+pub(crate) const IOCTL_DISK_GET_DRIVE_LAYOUT_EX: u32 = 0x0007_0050;
+/// `IOCTL_DISK_IS_WRITABLE`
+pub(crate) const IOCTL_DISK_IS_WRITABLE: u32 = 0x0007_0024;
+/// `FSCTL_IS_VOLUME_DIRTY`
+pub(crate) const FSCTL_IS_VOLUME_DIRTY: u32 = 0x0009_0078;
+
+/// `StorageDeviceProperty` of `STORAGE_PROPERTY_ID`
+pub(crate) const STORAGE_DEVICE_PROPERTY: u32 = 0;
+/// `StorageAdapterProperty` of `STORAGE_PROPERTY_ID`
+pub(crate) const STORAGE_ADAPTER_PROPERTY: u32 = 1;
+/// `StorageDeviceWriteCacheProperty` of `STORAGE_PROPERTY_ID`
+pub(crate) const STORAGE_WRITE_CACHE_PROPERTY: u32 = 4;
+/// `StorageAccessAlignmentProperty` of `STORAGE_PROPERTY_ID`
+pub(crate) const STORAGE_ACCESS_ALIGNMENT_PROPERTY: u32 = 6;
+/// `StorageDeviceTrimProperty` of `STORAGE_PROPERTY_ID`
+pub(crate) const STORAGE_TRIM_PROPERTY: u32 = 8;
+/// `StorageDeviceLBProvisioningProperty` of `STORAGE_PROPERTY_ID`
+pub(crate) const STORAGE_PROVISIONING_PROPERTY: u32 = 11;
+/// `StorageDevicePhysicalTopologyProperty` of `STORAGE_PROPERTY_ID`
+pub(crate) const STORAGE_TOPOLOGY_PROPERTY: u32 = 58;
+/// Size of `STORAGE_PROPERTY_QUERY`
+pub(crate) const PROPERTY_QUERY_SIZE: usize = 12;
+
+/// `SPDRP_DEVICEDESC` registry property
+pub(crate) const SPDRP_DEVICEDESC: u32 = 0x00;
+/// `SPDRP_FRIENDLYNAME` registry property
+pub(crate) const SPDRP_FRIENDLYNAME: u32 = 0x0c;
+/// `SPDRP_ENUMERATOR_NAME` registry property
+pub(crate) const SPDRP_ENUMERATOR_NAME: u32 = 0x16;
+
+/// `CM_REMOVAL_POLICY_EXPECT_ORDERLY_REMOVAL`
+pub(crate) const CM_REMOVAL_POLICY_EXPECT_ORDERLY_REMOVAL: u32 = 2;
+/// `CM_REMOVAL_POLICY_EXPECT_SURPRISE_REMOVAL`
+pub(crate) const CM_REMOVAL_POLICY_EXPECT_SURPRISE_REMOVAL: u32 = 3;
+
+/// `GetDriveType` of a volume with a removable medium
+pub(crate) const DRIVE_REMOVABLE: u32 = 2;
+/// `GetDriveType` of a volume with a fixed medium
+pub(crate) const DRIVE_FIXED: u32 = 3;
+/// `GetDriveType` of a RAM disk volume
+pub(crate) const DRIVE_RAMDISK: u32 = 6;
+
+/// `ERROR_NOT_READY` system error code
+pub(crate) const ERROR_NOT_READY: u32 = 21;
+/// `ERROR_INVALID_DATA` system error code
+pub(crate) const ERROR_INVALID_DATA: u32 = 13;
+/// `ERROR_NO_MEDIA_IN_DRIVE` system error code
+pub(crate) const ERROR_NO_MEDIA_IN_DRIVE: u32 = 1112;
+
+/// Size of the `IOCTL_DISK_GET_DRIVE_LAYOUT_EX` output buffer, enough for 256 partitions
+pub(crate) const LAYOUT_BUFFER_SIZE: usize = layout::ENTRIES_OFFSET + 257 * layout::ENTRY_SIZE;
+/// Size of the `STORAGE_DEVICE_DESCRIPTOR` output buffer, with room for the ID strings
+const DEVICE_DESCRIPTOR_SIZE: usize = 1024;
+
+/// File, volume and ioctl calls of the Windows backend.
+///
+/// Failures are the system error code (`GetLastError`) of the call.
+pub(crate) trait Win32Source {
+    /// Handle of an open device or volume, closed when dropped
+    type Handle;
+
+    /// Open a device or volume path without read or write access, for ioctls (`CreateFileW`)
+    fn open(&self, path: &str) -> Result<Self::Handle, u32>;
+
+    /// Send `code` with `input`, returning the part of a `len` byte output buffer written by the
+    /// driver (`DeviceIoControl`)
+    fn ioctl(
+        &self,
+        handle: &Self::Handle,
+        code: u32,
+        input: &[u8],
+        len: usize,
+    ) -> Result<Vec<u8>, u32>;
+
+    /// Bitmask of the drive letters in use, bit 0 being `A:` (`GetLogicalDrives`)
+    fn logical_drives(&self) -> Result<u32, u32>;
+
+    /// Type of the volume with root path `root`, e.g. `C:\` (`GetDriveTypeW`)
+    fn drive_type(&self, root: &str) -> u32;
+
+    /// Root path of the volume `path` is on (`GetVolumePathNameW`)
+    fn volume_path_name(&self, path: &str) -> Result<String, u32>;
+
+    /// Label and file system flags of a volume (`GetVolumeInformationW`)
+    fn volume_information(&self, root: &str) -> Option<(String, u32)>;
+
+    /// Cluster size and counts of a volume (`GetDiskFreeSpaceW`)
+    fn disk_free_space(&self, root: &str) -> Result<FreeSpace, u32>;
+
+    /// Windows and program files directories (`ExpandEnvironmentStringsW`)
+    fn system_directories(&self) -> Vec<String>;
+}
+
+/// Devices of a SetupAPI device information set, and the device tree they are in
+pub(crate) trait DeviceInfoSource {
+    /// Device of the set (`SP_DEVINFO_DATA`)
+    type Device;
+    /// Node of the device tree (`DEVINST`)
+    type Node: Copy;
+
+    /// Device `index` of the set, `None` after the last one (`SetupDiEnumDeviceInfo`)
+    fn device(&self, index: u32) -> Option<Self::Device>;
+
+    /// String registry property of `device`, empty if it has none
+    /// (`SetupDiGetDeviceRegistryPropertyW`)
+    fn registry_string(&self, device: &Self::Device, property: u32) -> String;
+
+    /// `CM_REMOVAL_POLICY` of `device`, 0 if unknown
+    fn removal_policy(&self, device: &Self::Device) -> u32;
+
+    /// Device instance ID of `device` (`SetupDiGetDeviceInstanceIdW`)
+    fn instance_id(&self, device: &Self::Device) -> Option<String>;
+
+    /// Device tree node of `device`
+    fn node(&self, device: &Self::Device) -> Self::Node;
+
+    /// Parent of `node` (`CM_Get_Parent`)
+    fn parent(&self, node: Self::Node) -> Option<Self::Node>;
+
+    /// Device instance ID of `node` (`CM_Get_Device_IDW`)
+    fn device_id(&self, node: Self::Node) -> Option<String>;
+
+    /// Path of interface `index` of `device`, `None` after the last one
+    /// (`SetupDiEnumDeviceInterfaces`, `SetupDiGetDeviceInterfaceDetailW`)
+    fn interface_path(&self, device: &Self::Device, index: u32) -> anyhow::Result<Option<String>>;
+}
+
+/// Output of `GetDiskFreeSpaceW`
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct FreeSpace {
+    pub(crate) sectors_per_cluster: u32,
+    pub(crate) bytes_per_sector: u32,
+    pub(crate) free_clusters: u32,
+    pub(crate) total_clusters: u32,
+}
+
+fn os_error(code: u32) -> io::Error {
+    io::Error::from_raw_os_error(code as i32)
+}
+
+/// Input of `IOCTL_STORAGE_QUERY_PROPERTY` for a `PropertyStandardQuery` of `id`
+fn property_query(id: u32) -> [u8; PROPERTY_QUERY_SIZE] {
+    let mut query = [0; PROPERTY_QUERY_SIZE];
+    query[..4].copy_from_slice(&id.to_le_bytes());
+    query
+}
+
+/// Storage property `id`, decoded with `decode`.
+///
+/// Output which `decode` rejects (e.g. cut off) fails with `ERROR_INVALID_DATA`.
+fn query_property<S: Win32Source, T>(
+    win32: &S,
+    handle: &S::Handle,
+    id: u32,
+    len: usize,
+    decode: impl FnOnce(&[u8]) -> Option<T>,
+) -> Result<T, u32> {
+    let bytes = win32.ioctl(
+        handle,
+        IOCTL_STORAGE_QUERY_PROPERTY,
+        &property_query(id),
+        len,
+    )?;
+
+    decode(&bytes).ok_or(ERROR_INVALID_DATA)
+}
+
+/// Enumerate all disk devices of `set`, named `\\.\<prefix><number>`
+pub(crate) fn drive_list<S: DeviceInfoSource, W: Win32Source>(
+    set: &S,
+    win32: &W,
+    prefix: &str,
+    watchdog: Watchdog,
+    minimal: bool,
+    cancel: Option<&AtomicBool>,
+    diagnostics: &mut Vec<String>,
+) -> Vec<DeviceDescriptor> {
+    let mut drives: Vec<DeviceDescriptor> = Vec::new();
+    let system_directories = win32.system_directories();
+    let mut index = 0;
+
+    while let Some(info) = set.device(index) {
+        // The caller turns this into an error
+        if cancel.is_some_and(|x| x.load(Ordering::Relaxed)) {
+            break;
+        }
+
+        index += 1;
+
+        let enumerator_name = set.registry_string(&info, SPDRP_ENUMERATOR_NAME);
+        let friendly_name = get_friendly_name(set, &info);
+
+        if friendly_name.is_empty() {
+            diagnostics.push(format!(
+                "Skipped device {} ({}): no friendly name",
+                index - 1,
+                enumerator_name
+            ));
+            continue;
+        }
+
+        let node = set.node(&info);
+        let is_usb = is_usb_drive(&enumerator_name);
+        let mut item = DeviceDescriptor {
+            is_usb,
+            behind_hub: is_usb.then(|| is_behind_hub(set, node)).flatten(),
+            description: friendly_name,
+            enumerator: enumerator_name,
+            is_removable: matches!(
+                set.removal_policy(&info),
+                CM_REMOVAL_POLICY_EXPECT_SURPRISE_REMOVAL
+                    | CM_REMOVAL_POLICY_EXPECT_ORDERLY_REMOVAL
+            ),
+            instance_id: set.instance_id(&info),
+            parent_path: get_parent_path(set, node),
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        // The device is still listed with the error, like failures of later stages
+        if let Err(err) = get_detail_data(
+            set,
+            win32,
+            &mut item,
+            &info,
+            prefix,
+            watchdog,
+            minimal,
+            diagnostics,
+        ) {
+            diagnostics.push(format!("{}: {}", item.description, err));
+            add_error(&mut item, err.to_string());
+        }
+        item.enumeration_duration = Some(start.elapsed());
+        let bt = item.bus_type.as_ref().unwrap_or(&BusType::Unknown);
+        item.is_system = item.is_system || is_system_device(&item, &system_directories);
+        item.is_card = matches!(bt, BusType::Sd | BusType::Mmc);
+        // More specific than the "Memory card" or "Embedded" of the topology
+        item.form_factor = match bt {
+            BusType::Sd => Some("SD".to_string()),
+            BusType::Mmc => Some("eMMC".to_string()),
+            _ => item.form_factor.take(),
+        };
+        item.is_uas = Some(item.enumerator == "SCSI" && *bt == BusType::Usb);
+        item.is_virtual = item.is_virtual
+            || item.ram_disk
+            || *bt == BusType::Virtual
+            || *bt == BusType::FileBackedVirtual
+            || is_virtual_disk_name(&item.description);
+
+        if item.is_card || item.media_present == Some(false) {
+            item.media_type = MediaType::RemovableMedia;
+        } else if item.media_type == MediaType::FixedMedia
+            && (item.is_removable || matches!(bt, BusType::Usb | BusType::Ieee1394))
+        {
+            item.media_type = MediaType::ExternalFixed;
+        }
+
+        drives.push(item);
+    }
+
+    drives
+}
+
+/// The drive has a system directory on one of its volumes, or the Windows boot partition layout
+fn is_system_device(device: &DeviceDescriptor, system_directories: &[String]) -> bool {
+    has_windows_boot_layout(device)
+        || system_directories
+            .iter()
+            .any(|dir| device.mountpoints.iter().any(|mp| dir.contains(&mp.path)))
+}
+
+/// Record the failure of a stage, keeping the first error and adding later ones to the warnings
+fn add_error(device: &mut DeviceDescriptor, error: String) {
+    if device.error.is_none() {
+        device.error = Some(error);
+    } else {
+        device.warnings.push(error);
+    }
+}
+
+/// Fill `device` from its device interfaces.
+///
+/// Only a failure to enumerate the interfaces themselves is returned, failures of the individual
+/// stages are recorded in `device`.
+#[allow(clippy::too_many_arguments)]
+fn get_detail_data<S: DeviceInfoSource, W: Win32Source>(
+    set: &S,
+    win32: &W,
+    device: &mut DeviceDescriptor,
+    info: &S::Device,
+    prefix: &str,
+    watchdog: Watchdog,
+    minimal: bool,
+    diagnostics: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    let mut index = 0_u32;
+
+    while let Some(path) = set.interface_path(info, index)? {
+        let h_device = match win32.open(&path) {
+            Ok(handle) => handle,
+            Err(code) => {
+                diagnostics.push(format!(
+                    "{}: Couldn't open handle to device: Error {}",
+                    device.description, code
+                ));
+                break;
+            }
+        };
+
+        let start = Instant::now();
+        let device_number = get_device_number(win32, &h_device);
+        watchdog.check(start, &device.description, "device number", diagnostics);
+
+        // Other interfaces of the device may still work, and the remaining devices are
+        // enumerated by the caller either way. `h_device` is closed on drop, so moving on
+        // doesn't leak it.
+        let Some(device_number) = device_number else {
+            add_error(device, "Couldn't get device number".to_string());
+            index += 1;
+            continue;
+        };
+
+        device.raw = crate::dos_device_path(prefix, device_number.number);
+        device.device = device.raw.clone();
+
+        let start = Instant::now();
+        let mount_points = get_mount_points(win32, device_number, &mut device.mountpoints);
+        watchdog.check(start, &device.device, "mount points", diagnostics);
+
+        if let Err(err) = mount_points {
+            add_error(device, err.to_string());
+            break;
+        }
+
+        // RAM disk drivers only identify themselves through the type of their volumes
+        device.ram_disk = device
+            .mountpoints
+            .iter()
+            .any(|x| win32.drive_type(&x.path) == DRIVE_RAMDISK);
+
+        // The geometry ioctl needs no access rights, so the interface handle is enough
+        if minimal {
+            let start = Instant::now();
+            let _ = get_device_size(win32, device, &h_device);
+            watchdog.check(start, &device.device, "geometry", diagnostics);
+            break;
+        }
+
+        let h_physical = match win32.open(&device.device) {
+            Ok(handle) => handle,
+            Err(code) => {
+                add_error(
+                    device,
+                    format!("Cannot open: {}: Error {}", device.device, code),
+                );
+                break;
+            }
+        };
+
+        let start = Instant::now();
+        let size = get_device_size(win32, device, &h_physical);
+        watchdog.check(start, &device.device, "geometry", diagnostics);
+
+        match size {
+            Ok(()) => {}
+            // Empty card reader slots and optical drives are still listed, without media
+            Err(ERROR_NOT_READY | ERROR_NO_MEDIA_IN_DRIVE) => {
+                device.media_present = Some(false);
+                let _ = get_adapter_info(win32, device, &h_physical);
+                break;
+            }
+            Err(code) => {
+                add_error(
+                    device,
+                    format!("Couldn't get disk geometry: Error {}", code),
+                );
+                break;
+            }
+        }
+
+        device.media_present = Some(true);
+
+        let start = Instant::now();
+        let partition_table = get_partition_table_type(win32, device, &h_physical);
+        watchdog.check(start, &device.device, "partition table", diagnostics);
+
+        // The remaining stages don't depend on the partition table
+        if let Err(err) = partition_table {
+            add_error(device, format!("Couldn't get partition type: {}", err));
+        }
+
+        let start = Instant::now();
+        let adapter_info = get_adapter_info(win32, device, &h_physical);
+        watchdog.check(start, &device.device, "adapter info", diagnostics);
+
+        // Some USB bridges don't answer the adapter query, the drive is still usable with
+        // an unknown bus type
+        if let Err(code) = adapter_info {
+            device
+                .warnings
+                .push(format!("Couldn't get adapter info: Error {}", code));
+        }
+
+        let start = Instant::now();
+        let block_size = query_property(
+            win32,
+            &h_physical,
+            STORAGE_ACCESS_ALIGNMENT_PROPERTY,
+            decode::ACCESS_ALIGNMENT_SIZE,
+            |bytes| decode::access_alignment(device, bytes),
+        );
+        watchdog.check(start, &device.device, "block size", diagnostics);
+
+        if let Err(code) = block_size {
+            add_error(
+                device,
+                format!("Couldn't get device block size: Error {}", code),
+            );
+            break;
+        }
+
+        let start = Instant::now();
+        device.serial = query_property(
+            win32,
+            &h_physical,
+            STORAGE_DEVICE_PROPERTY,
+            DEVICE_DESCRIPTOR_SIZE,
+            decode::serial_number,
+        )
+        .ok();
+        watchdog.check(start, &device.device, "serial", diagnostics);
+
+        let start = Instant::now();
+        device.thin_provisioned = query_property(
+            win32,
+            &h_physical,
+            STORAGE_PROVISIONING_PROPERTY,
+            decode::PROVISIONING_SIZE,
+            decode::thin_provisioning,
+        )
+        .ok();
+        watchdog.check(start, &device.device, "provisioning", diagnostics);
+
+        // Form factor reported by the miniport (Windows 10 and later).
+        // `StorageDeviceLocationProperty` only describes the slot the device is in, not its
+        // shape, so it isn't used.
+        let start = Instant::now();
+        device.form_factor = query_property(
+            win32,
+            &h_physical,
+            STORAGE_TOPOLOGY_PROPERTY,
+            topology::BUFFER_SIZE,
+            topology::parse,
+        )
+        .ok()
+        .map(str::to_string);
+        watchdog.check(start, &device.device, "form factor", diagnostics);
+
+        // `IOCTL_DISK_GET_CACHE_INFORMATION` needs read access to the drive, the storage property
+        // works with the query-only handle
+        let start = Instant::now();
+        device.write_cache_enabled = query_property(
+            win32,
+            &h_physical,
+            STORAGE_WRITE_CACHE_PROPERTY,
+            decode::WRITE_CACHE_SIZE,
+            decode::write_cache_enabled,
+        )
+        .ok();
+        watchdog.check(start, &device.device, "write cache", diagnostics);
+
+        let start = Instant::now();
+        let writable = win32
+            .ioctl(&h_physical, IOCTL_DISK_IS_WRITABLE, &[], 0)
+            .map(drop);
+        device.is_readonly = decode::is_write_protected(writable);
+        watchdog.check(start, &device.device, "writable check", diagnostics);
+
+        index += 1;
+    }
+
+    Ok(())
+}
+
+fn get_device_size<W: Win32Source>(
+    win32: &W,
+    device: &mut DeviceDescriptor,
+    handle: &W::Handle,
+) -> Result<(), u32> {
+    let bytes = win32.ioctl(
+        handle,
+        IOCTL_DISK_GET_DRIVE_GEOMETRY_EX,
+        &[],
+        decode::GEOMETRY_SIZE,
+    )?;
+
+    decode::geometry(device, &bytes).ok_or(ERROR_INVALID_DATA)
+}
+
+fn get_adapter_info<W: Win32Source>(
+    win32: &W,
+    device: &mut DeviceDescriptor,
+    h_physical: &W::Handle,
+) -> Result<(), u32> {
+    query_property(
+        win32,
+        h_physical,
+        STORAGE_ADAPTER_PROPERTY,
+        decode::ADAPTER_SIZE,
+        |bytes| decode::adapter(device, bytes),
+    )
+}
+
+fn get_partition_table_type<W: Win32Source>(
+    win32: &W,
+    device: &mut DeviceDescriptor,
+    h_physical: &W::Handle,
+) -> Result<(), DriveListError> {
+    // Only the part which was actually written by the ioctl is returned
+    let bytes = win32
+        .ioctl(
+            h_physical,
+            IOCTL_DISK_GET_DRIVE_LAYOUT_EX,
+            &[],
+            LAYOUT_BUFFER_SIZE,
+        )
+        .map_err(|code| DriveListError::Io(os_error(code)))?;
+
+    let disk_number = device.device.trim_start_matches(r"\\.\PhysicalDrive");
+    let layout = layout::parse(&bytes, disk_number)?;
+    device.partition_table_type = layout.partition_table_type;
+    device.partitions.extend(layout.partitions);
+
+    Ok(())
+}
+
+/// Device number of a disk, CD-ROM, tape or volume handle.
+///
+/// A volume spanning several disks belongs to none of them.
+fn get_device_number<W: Win32Source>(win32: &W, handle: &W::Handle) -> Option<DeviceNumber> {
+    let mut device_number = None;
+
+    if let Ok(bytes) = win32.ioctl(
+        handle,
+        IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS,
+        &[],
+        decode::DISK_EXTENTS_SIZE,
+    ) {
+        device_number = Some(decode::volume_disk(&bytes)?);
+    }
+
+    if let Ok(bytes) = win32.ioctl(
+        handle,
+        IOCTL_STORAGE_GET_DEVICE_NUMBER,
+        &[],
+        decode::DEVICE_NUMBER_SIZE,
+    ) && let Some(number) = decode::device_number(&bytes)
+    {
+        device_number = Some(number);
+    }
+
+    device_number
+}
+
+/// Drive letters in use
+fn get_available_volumes<W: Win32Source>(win32: &W) -> anyhow::Result<Vec<char>> {
+    let mask = win32.logical_drives().map_err(|code| {
+        anyhow::Error::from(DriveListError::Io(os_error(code))).context("GetLogicalDrives failed")
+    })?;
+
+    Ok((b'A'..=b'Z')
+        .enumerate()
+        .filter(|(i, _)| mask & (1 << i) != 0)
+        .map(|(_, letter)| letter as char)
+        .collect())
+}
+
+/// Add the volumes with a drive letter on `device_number`.
+///
+/// CD-ROM volumes are skipped, so CD-ROM and tape devices never have mountpoints.
+fn get_mount_points<W: Win32Source>(
+    win32: &W,
+    device_number: DeviceNumber,
+    mount_points: &mut Vec<MountPoint>,
+) -> anyhow::Result<()> {
+    for volume_name in get_available_volumes(win32)? {
+        let mut drive = MountPoint::new(format!(r"{}:\", volume_name));
+
+        if !matches!(
+            win32.drive_type(&drive.path),
+            DRIVE_FIXED | DRIVE_REMOVABLE | DRIVE_RAMDISK
+        ) {
+            continue;
+        }
+
+        let Ok(h_logical) = win32.open(&format!(r"\\.\{}:", volume_name)) else {
+            continue;
+        };
+
+        if get_device_number(win32, &h_logical) != Some(device_number) {
+            continue;
+        }
+
+        let root_path = win32
+            .volume_path_name(&drive.path)
+            .map_err(|code| anyhow::Error::new(os_error(code)))?;
+
+        if let Some((label, file_system_flags)) = win32.volume_information(&root_path) {
+            drive.label = Some(label).filter(|x| !x.is_empty());
+            decode::volume_flags(&mut drive, file_system_flags);
+        }
+
+        if let Some(flags) = win32
+            .ioctl(&h_logical, FSCTL_IS_VOLUME_DIRTY, &[], 4)
+            .ok()
+            .and_then(|x| decode::u32_at(&x, 0))
+        {
+            decode::volume_dirty(&mut drive, flags);
+        }
+
+        let space = win32
+            .disk_free_space(&root_path)
+            .map_err(|code| anyhow::Error::new(os_error(code)))?;
+
+        let bytes_per_cluster = checked_mul_u64(
+            space.sectors_per_cluster as u64,
+            space.bytes_per_sector as u64,
+        );
+        drive.total_bytes =
+            bytes_per_cluster.and_then(|x| checked_mul_u64(x, space.total_clusters as u64));
+        drive.available_bytes =
+            bytes_per_cluster.and_then(|x| checked_mul_u64(x, space.free_clusters as u64));
+        mount_points.push(drive);
+    }
+
+    Ok(())
+}
+
+/// Friendly name, or the device description for devices without one (e.g. many optical drives)
+fn get_friendly_name<S: DeviceInfoSource>(set: &S, device: &S::Device) -> String {
+    let name = set.registry_string(device, SPDRP_FRIENDLYNAME);
+
+    if name.is_empty() {
+        set.registry_string(device, SPDRP_DEVICEDESC)
+    } else {
+        name
+    }
+}
+
+/// Instance ID of the parent device node, e.g. the USB hub or storage controller
+fn get_parent_path<S: DeviceInfoSource>(set: &S, node: S::Node) -> Option<String> {
+    set.device_id(set.parent(node)?)
+}
+
+/// Check if the USB device above `node` is attached to a hub instead of a root hub
+fn is_behind_hub<S: DeviceInfoSource>(set: &S, node: S::Node) -> Option<bool> {
+    let mut current = set.parent(node)?;
+
+    // Find the USB device itself, skipping the interfaces of composite devices
+    loop {
+        let id = set.device_id(current)?.to_uppercase();
+
+        if id.starts_with(r"USB") && !id.contains("&MI_") {
+            break;
+        }
+
+        current = set.parent(current)?;
+    }
+
+    let hub = set.device_id(set.parent(current)?)?.to_uppercase();
+
+    if hub.starts_with(r"USB\ROOT_HUB") {
+        Some(false)
+    } else if hub.starts_with(r"USB") {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn is_usb_drive(enumerator_name: &str) -> bool {
+    [
+        "USBSTOR",
+        "UASPSTOR",
+        "VUSBSTOR",
+        "RTUSER",
+        "CMIUCR",
+        "EUCR",
+        "ETRONSTOR",
+        "ASUSSTPT",
+    ]
+    .contains(&enumerator_name)
+}
+
+/// Mark the drives of the additional USB storage `enumerators` of a query as USB
+pub(crate) fn mark_usb_enumerators(drives: &mut [DeviceDescriptor], enumerators: &[String]) {
+    for drive in drives.iter_mut().filter(|x| {
+        enumerators
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(&x.enumerator))
+    }) {
+        drive.is_usb = true;
+
+        if drive.media_type == MediaType::FixedMedia {
+            drive.media_type = MediaType::ExternalFixed;
+        }
+    }
+}
+
+/// Run each query of a capability probe against the device at `path`
+pub(crate) fn probe_capabilities<W: Win32Source>(
+    win32: &W,
+    path: &str,
+) -> anyhow::Result<Vec<ProbeResult>> {
+    let h_physical = win32
+        .open(path)
+        .map_err(|code| DriveListError::Io(os_error(code)))?;
+
+    let ioctl = |code: u32, input: &[u8]| {
+        // Sized for the largest output, the drive layout, so a disk with many partitions doesn't
+        // fail the probe with ERROR_INSUFFICIENT_BUFFER
+        win32
+            .ioctl(&h_physical, code, input, LAYOUT_BUFFER_SIZE)
+            .map(drop)
+            .map_err(|code| code as i32)
+    };
+    let property = |id: u32| ioctl(IOCTL_STORAGE_QUERY_PROPERTY, &property_query(id));
+
+    Ok(vec![
+        ProbeResult::new("adapter", property(STORAGE_ADAPTER_PROPERTY)),
+        ProbeResult::new("alignment", property(STORAGE_ACCESS_ALIGNMENT_PROPERTY)),
+        ProbeResult::new("serial", property(STORAGE_DEVICE_PROPERTY)),
+        ProbeResult::new("trim", property(STORAGE_TRIM_PROPERTY)),
+        ProbeResult::new("geometry", ioctl(IOCTL_DISK_GET_DRIVE_GEOMETRY_EX, &[])),
+        ProbeResult::new("layout", ioctl(IOCTL_DISK_GET_DRIVE_LAYOUT_EX, &[])),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        collections::HashMap,
+        rc::Rc,
+    };
+
+    use super::*;
+
+    /// Device of [`FakeSet`], with its registry properties
+    #[derive(Default)]
+    struct FakeDevice {
+        enumerator: &'static str,
+        friendly_name: &'static str,
+        description: &'static str,
+        removal_policy: u32,
+        instance_id: Option<&'static str>,
+        /// Index of its node in [`FakeSet::nodes`]
+        node: usize,
+        interfaces: Vec<&'static str>,
+    }
+
+    /// Node of the fake device tree
+    struct FakeNode {
+        id: &'static str,
+        parent: Option<usize>,
+    }
+
+    #[derive(Default)]
+    struct FakeSet {
+        devices: Vec<FakeDevice>,
+        nodes: Vec<FakeNode>,
+    }
+
+    impl DeviceInfoSource for FakeSet {
+        type Device = usize;
+        type Node = usize;
+
+        fn device(&self, index: u32) -> Option<usize> {
+            Some(index as usize).filter(|x| *x < self.devices.len())
+        }
+
+        fn registry_string(&self, device: &usize, property: u32) -> String {
+            let device = &self.devices[*device];
+
+            match property {
+                SPDRP_ENUMERATOR_NAME => device.enumerator,
+                SPDRP_FRIENDLYNAME => device.friendly_name,
+                SPDRP_DEVICEDESC => device.description,
+                _ => "",
+            }
+            .to_string()
+        }
+
+        fn removal_policy(&self, device: &usize) -> u32 {
+            self.devices[*device].removal_policy
+        }
+
+        fn instance_id(&self, device: &usize) -> Option<String> {
+            self.devices[*device].instance_id.map(str::to_string)
+        }
+
+        fn node(&self, device: &usize) -> usize {
+            self.devices[*device].node
+        }
+
+        fn parent(&self, node: usize) -> Option<usize> {
+            self.nodes.get(node)?.parent
+        }
+
+        fn device_id(&self, node: usize) -> Option<String> {
+            self.nodes.get(node).map(|x| x.id.to_string())
+        }
+
+        fn interface_path(&self, device: &usize, index: u32) -> anyhow::Result<Option<String>> {
+            let interfaces = &self.devices[*device].interfaces;
+
+            Ok(interfaces.get(index as usize).map(|x| x.to_string()))
+        }
+    }
+
+    /// Handle of [`FakeWin32`], counted while open
+    struct FakeHandle {
+        path: String,
+        open: Rc<Cell<usize>>,
+    }
+
+    impl Drop for FakeHandle {
+        fn drop(&mut self) {
+            self.open.set(self.open.get() - 1);
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeWin32 {
+        /// Output of the ioctls by path, code and storage property ID (0 for other ioctls).
+        /// Missing ones fail with `ERROR_INVALID_FUNCTION`.
+        replies: HashMap<(String, u32, u32), Result<Vec<u8>, u32>>,
+        /// Paths which fail to open, with the error code
+        unopenable: HashMap<String, u32>,
+        /// Error code of `GetLogicalDrives`, which otherwise reports no drive letters
+        logical_drives_error: Option<u32>,
+        /// Every path opened, in order
+        opened: RefCell<Vec<String>>,
+        open_handles: Rc<Cell<usize>>,
+    }
+
+    impl FakeWin32 {
+        fn reply(&mut self, path: &str, code: u32, id: u32, reply: Result<Vec<u8>, u32>) {
+            self.replies.insert((path.to_string(), code, id), reply);
+        }
+
+        /// Interface `interface` of disk `number`, a healthy [`SIZE`] byte disk without a
+        /// partition table
+        fn disk(&mut self, interface: &str, number: u32) {
+            let physical = format!(r"\\.\PhysicalDrive{}", number);
+            let property = |id| (IOCTL_STORAGE_QUERY_PROPERTY, id);
+
+            self.reply(
+                interface,
+                IOCTL_STORAGE_GET_DEVICE_NUMBER,
+                0,
+                Ok(device_number(number)),
+            );
+
+            for ((code, id), bytes) in [
+                ((IOCTL_DISK_GET_DRIVE_GEOMETRY_EX, 0), geometry(SIZE)),
+                ((IOCTL_DISK_GET_DRIVE_LAYOUT_EX, 0), raw_layout()),
+                ((IOCTL_DISK_IS_WRITABLE, 0), vec![]),
+                (property(STORAGE_ADAPTER_PROPERTY), adapter(7)),
+                (property(STORAGE_ACCESS_ALIGNMENT_PROPERTY), alignment(512)),
+            ] {
+                self.reply(&physical, code, id, Ok(bytes));
+            }
+        }
+    }
+
+    impl Win32Source for FakeWin32 {
+        type Handle = FakeHandle;
+
+        fn open(&self, path: &str) -> Result<FakeHandle, u32> {
+            self.opened.borrow_mut().push(path.to_string());
+
+            if let Some(code) = self.unopenable.get(path) {
+                return Err(*code);
+            }
+
+            self.open_handles.set(self.open_handles.get() + 1);
+
+            Ok(FakeHandle {
+                path: path.to_string(),
+                open: self.open_handles.clone(),
+            })
+        }
+
+        fn ioctl(
+            &self,
+            handle: &FakeHandle,
+            code: u32,
+            input: &[u8],
+            len: usize,
+        ) -> Result<Vec<u8>, u32> {
+            let id = u32_at(input, 0).unwrap_or(0);
+            let mut bytes = self
+                .replies
+                .get(&(handle.path.clone(), code, id))
+                .cloned()
+                .unwrap_or(Err(1))?;
+
+            bytes.truncate(len);
+            Ok(bytes)
+        }
+
+        fn logical_drives(&self) -> Result<u32, u32> {
+            self.logical_drives_error.map_or(Ok(0), Err)
+        }
+
+        fn drive_type(&self, _root: &str) -> u32 {
+            DRIVE_FIXED
+        }
+
+        fn volume_path_name(&self, path: &str) -> Result<String, u32> {
+            Ok(path.to_string())
+        }
+
+        fn volume_information(&self, _root: &str) -> Option<(String, u32)> {
+            None
+        }
+
+        fn disk_free_space(&self, _root: &str) -> Result<FreeSpace, u32> {
+            Ok(FreeSpace::default())
+        }
+
+        fn system_directories(&self) -> Vec<String> {
+            vec![r"C:\Windows".to_string()]
+        }
+    }
+
+    /// Size of the fake disks, 16 GiB
+    const SIZE: u64 = 16 << 30;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+        decode::u32_at(bytes, offset)
+    }
+
+    fn put(bytes: &mut [u8], offset: usize, value: &[u8]) {
+        bytes[offset..offset + value.len()].copy_from_slice(value);
+    }
+
+    fn device_number(number: u32) -> Vec<u8> {
+        let mut bytes = vec![0; decode::DEVICE_NUMBER_SIZE];
+        put(&mut bytes, 0, &decode::FILE_DEVICE_DISK.to_le_bytes());
+        put(&mut bytes, 4, &number.to_le_bytes());
+        bytes
+    }
+
+    /// `DISK_GEOMETRY_EX` of a fixed disk with 512 byte sectors
+    fn geometry(size: u64) -> Vec<u8> {
+        let mut bytes = vec![0; decode::GEOMETRY_SIZE];
+        put(
+            &mut bytes,
+            decode::GEOMETRY_MEDIA_TYPE_OFFSET,
+            &12_u32.to_le_bytes(),
+        );
+        put(
+            &mut bytes,
+            decode::GEOMETRY_SECTOR_OFFSET,
+            &512_u32.to_le_bytes(),
+        );
+        put(
+            &mut bytes,
+            decode::GEOMETRY_SIZE_OFFSET,
+            &size.to_le_bytes(),
+        );
+        bytes
+    }
+
+    /// Drive layout of a disk without a partition table
+    fn raw_layout() -> Vec<u8> {
+        let mut bytes = vec![0; layout::ENTRIES_OFFSET];
+        put(&mut bytes, 0, &2_u32.to_le_bytes());
+        bytes
+    }
+
+    fn adapter(bus_type: u8) -> Vec<u8> {
+        let mut bytes = vec![0; decode::ADAPTER_SIZE];
+        bytes[decode::BUS_TYPE_OFFSET] = bus_type;
+        bytes
+    }
+
+    /// Access alignment of a disk with `sector` byte logical and physical sectors
+    fn alignment(sector: u32) -> Vec<u8> {
+        let mut bytes = vec![0; decode::ACCESS_ALIGNMENT_SIZE];
+        put(
+            &mut bytes,
+            decode::LOGICAL_SECTOR_OFFSET,
+            &sector.to_le_bytes(),
+        );
+        put(
+            &mut bytes,
+            decode::LOGICAL_SECTOR_OFFSET + 4,
+            &sector.to_le_bytes(),
+        );
+        bytes
+    }
+
+    fn list(set: &FakeSet, win32: &FakeWin32, minimal: bool) -> Vec<DeviceDescriptor> {
+        drive_list(
+            set,
+            win32,
+            "PhysicalDrive",
+            Watchdog::new(None),
+            minimal,
+            None,
+            &mut vec![],
+        )
+    }
+
+    /// A set of one USB stick on disk interface `interface`
+    fn usb_stick(interface: &'static str) -> FakeSet {
+        FakeSet {
+            devices: vec![FakeDevice {
+                enumerator: "USBSTOR",
+                friendly_name: "SanDisk Cruzer Blade USB Device",
+                removal_policy: CM_REMOVAL_POLICY_EXPECT_SURPRISE_REMOVAL,
+                instance_id: Some(STICK_ID),
+                interfaces: vec![interface],
+                ..Default::default()
+            }],
+            nodes: vec![FakeNode {
+                id: STICK_ID,
+                parent: None,
+            }],
+        }
+    }
+
+    const STICK: &str = r"\\?\usbstor#disk#4c530001#{53f56307-b6bf-11d0-94f2-00a0c91efb8b}";
+    const STICK_ID: &str = r"USBSTOR\DISK&VEN_SANDISK&PROD_CRUZER_BLADE\4C530001\0";
+
+    #[test]
+    fn logical_drives_failure() {
+        let mut win32 = FakeWin32 {
+            logical_drives_error: Some(5),
+            ..Default::default()
+        };
+        win32.disk(STICK, 1);
+
+        let drives = list(&usb_stick(STICK), &win32, false);
+        assert_eq!(drives.len(), 1);
+        assert_eq!(drives[0].device, r"\\.\PhysicalDrive1");
+        assert_eq!(drives[0].error.as_deref(), Some("GetLogicalDrives failed"));
+
+        let err = get_available_volumes(&win32).unwrap_err();
+        let Some(DriveListError::Io(err)) = err.downcast_ref::<DriveListError>() else {
+            panic!("{:?}", err);
+        };
+        assert_eq!(err.raw_os_error(), Some(5));
+    }
+
+    /// Card reader with an empty slot: the geometry fails with `ERROR_NOT_READY`
+    #[test]
+    fn empty_card_reader_slot() {
+        let mut win32 = FakeWin32::default();
+        win32.disk(STICK, 1);
+        win32.reply(
+            r"\\.\PhysicalDrive1",
+            IOCTL_DISK_GET_DRIVE_GEOMETRY_EX,
+            0,
+            Err(ERROR_NOT_READY),
+        );
+
+        let drives = list(&usb_stick(STICK), &win32, false);
+        assert_eq!(drives.len(), 1);
+        assert_eq!(drives[0].device, r"\\.\PhysicalDrive1");
+        assert_eq!(drives[0].media_present, Some(false));
+        assert_eq!(drives[0].media_type, MediaType::RemovableMedia);
+        assert_eq!(drives[0].size, 0);
+        assert_eq!(drives[0].error, None);
+        // The adapter is still queried
+        assert_eq!(drives[0].bus_type, Some(BusType::Usb));
+    }
+
+    /// USB bridge which doesn't answer the adapter query
+    #[test]
+    fn adapter_failure() {
+        let mut win32 = FakeWin32::default();
+        win32.disk(STICK, 1);
+        win32.reply(
+            r"\\.\PhysicalDrive1",
+            IOCTL_STORAGE_QUERY_PROPERTY,
+            STORAGE_ADAPTER_PROPERTY,
+            Err(ERROR_INVALID_DATA),
+        );
+
+        let drives = list(&usb_stick(STICK), &win32, false);
+        assert_eq!(drives.len(), 1);
+        assert_eq!(drives[0].description, "SanDisk Cruzer Blade USB Device");
+        assert_eq!(drives[0].size, SIZE);
+        assert_eq!(drives[0].block_size, 512);
+        assert_eq!(drives[0].bus_type, None);
+        assert_eq!(drives[0].error, None);
+        assert_eq!(drives[0].warnings, ["Couldn't get adapter info: Error 13"]);
+    }
+
+    /// Minimal listing works without elevation, so only the interface is opened
+    #[test]
+    fn minimal_never_opens_physical_drive() {
+        let mut win32 = FakeWin32::default();
+        win32.disk(STICK, 1);
+        win32.reply(
+            STICK,
+            IOCTL_DISK_GET_DRIVE_GEOMETRY_EX,
+            0,
+            Ok(geometry(SIZE)),
+        );
+
+        let drives = list(&usb_stick(STICK), &win32, true);
+        assert_eq!(drives.len(), 1);
+        assert_eq!(drives[0].device, r"\\.\PhysicalDrive1");
+        assert_eq!(drives[0].size, SIZE);
+        assert_eq!(*win32.opened.borrow(), [STICK]);
+    }
+
+    /// Disks on the interfaces `paths`, each its own device
+    fn disks(paths: &[&'static str]) -> FakeSet {
+        FakeSet {
+            devices: paths
+                .iter()
+                .enumerate()
+                .map(|(i, path)| FakeDevice {
+                    enumerator: "SCSI",
+                    friendly_name: "Samsung SSD 870 EVO 500GB",
+                    node: i,
+                    interfaces: vec![*path],
+                    ..Default::default()
+                })
+                .collect(),
+            nodes: paths
+                .iter()
+                .map(|_| FakeNode {
+                    id: r"SCSI\DISK&VEN_&PROD_SAMSUNG_SSD_870\4&1A2B3C4D&0&000000",
+                    parent: None,
+                })
+                .collect(),
+        }
+    }
+
+    const DISKS: [&str; 3] = [
+        r"\\?\scsi#disk#0#{53f56307-b6bf-11d0-94f2-00a0c91efb8b}",
+        r"\\?\scsi#disk#1#{53f56307-b6bf-11d0-94f2-00a0c91efb8b}",
+        r"\\?\scsi#disk#2#{53f56307-b6bf-11d0-94f2-00a0c91efb8b}",
+    ];
+
+    #[test]
+    fn device_number_failure() {
+        let mut win32 = FakeWin32::default();
+        win32.disk(DISKS[0], 0);
+        win32.disk(DISKS[2], 2);
+
+        let drives = list(&disks(&DISKS), &win32, false);
+        assert_eq!(drives.len(), 3);
+        assert_eq!(drives[0].device, r"\\.\PhysicalDrive0");
+        assert_eq!(drives[0].error, None);
+        assert_eq!(drives[1].device, "");
+        assert_eq!(
+            drives[1].error.as_deref(),
+            Some("Couldn't get device number")
+        );
+        assert_eq!(drives[2].device, r"\\.\PhysicalDrive2");
+        assert_eq!(drives[2].error, None);
+        assert_eq!(drives[2].size, SIZE);
+    }
+
+    #[test]
+    fn handles_closed() {
+        let mut win32 = FakeWin32::default();
+        win32.disk(DISKS[0], 0);
+        win32.disk(DISKS[2], 2);
+
+        let drives = list(&disks(&DISKS), &win32, false);
+        assert_eq!(drives.len(), 3);
+        // Each interface and both physical drives
+        assert_eq!(win32.opened.borrow().len(), 5);
+        assert_eq!(win32.open_handles.get(), 0);
+    }
+
+    /// Without elevation the physical drive fails to open, after the interface was opened
+    #[test]
+    fn physical_open_failure() {
+        let mut win32 = FakeWin32::default();
+        win32.disk(STICK, 1);
+        win32
+            .unopenable
+            .insert(r"\\.\PhysicalDrive1".to_string(), 5);
+
+        let drives = list(&usb_stick(STICK), &win32, false);
+        assert_eq!(drives.len(), 1);
+        assert_eq!(
+            drives[0].error.as_deref(),
+            Some(r"Cannot open: \\.\PhysicalDrive1: Error 5")
+        );
+        assert_eq!(win32.opened.borrow().len(), 2);
+        assert_eq!(win32.open_handles.get(), 0);
+    }
+
+    #[test]
+    fn failed_stages_retained() {
+        let mut win32 = FakeWin32::default();
+        win32.disk(STICK, 1);
+        win32.reply(
+            r"\\.\PhysicalDrive1",
+            IOCTL_DISK_GET_DRIVE_LAYOUT_EX,
+            0,
+            Err(1),
+        );
+        win32.reply(
+            r"\\.\PhysicalDrive1",
+            IOCTL_STORAGE_QUERY_PROPERTY,
+            STORAGE_ACCESS_ALIGNMENT_PROPERTY,
+            Err(50),
+        );
+
+        let drives = list(&usb_stick(STICK), &win32, false);
+        let error = drives[0].error.as_deref().unwrap();
+        assert!(
+            error.starts_with("Couldn't get partition type: "),
+            "{}",
+            error
+        );
+        assert_eq!(
+            drives[0].warnings,
+            ["Couldn't get device block size: Error 50"]
+        );
+
+        let mut device = DeviceDescriptor::default();
+        add_error(&mut device, "first".to_string());
+        add_error(&mut device, "second".to_string());
+        add_error(&mut device, "third".to_string());
+        assert_eq!(device.error.as_deref(), Some("first"));
+        assert_eq!(device.warnings, ["second", "third"]);
+    }
+
+    /// USB stick whose device node is the first of `ids`, each the child of the next
+    fn usb_tree(ids: &[&'static str]) -> FakeSet {
+        let mut set = usb_stick(STICK);
+        set.nodes = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| FakeNode {
+                id,
+                parent: Some(i + 1).filter(|x| *x < ids.len()),
+            })
+            .collect();
+        set
+    }
+
+    const USB_DEVICE: &str = r"USB\VID_0781&PID_5567\4C530001";
+    const HUB: &str = r"USB\VID_05E3&PID_0610\5&2A9D3F1C&0&4";
+    const ROOT_HUB: &str = r"USB\ROOT_HUB30\4&1C5C3D4E&0&0";
+
+    #[test]
+    fn parent_is_hub() {
+        let set = usb_tree(&[STICK_ID, USB_DEVICE, HUB, ROOT_HUB]);
+
+        assert_eq!(get_parent_path(&set, 1).as_deref(), Some(HUB));
+        assert_eq!(get_parent_path(&set, 3), None);
+
+        let drives = list(&set, &FakeWin32::default(), false);
+        assert_eq!(drives[0].parent_path.as_deref(), Some(USB_DEVICE));
+    }
+
+    #[test]
+    fn hub_topology() {
+        let on_hub = usb_tree(&[STICK_ID, USB_DEVICE, HUB, ROOT_HUB]);
+        assert_eq!(is_behind_hub(&on_hub, 0), Some(true));
+
+        let drives = list(&on_hub, &FakeWin32::default(), false);
+        assert_eq!(drives[0].behind_hub, Some(true));
+
+        let on_root_hub = usb_tree(&[STICK_ID, USB_DEVICE, ROOT_HUB]);
+        assert_eq!(is_behind_hub(&on_root_hub, 0), Some(false));
+
+        // The interface of a composite device is skipped
+        let composite = usb_tree(&[
+            STICK_ID,
+            r"USB\VID_0781&PID_5567&MI_00\6&1B2C3D4E&0&0000",
+            USB_DEVICE,
+            ROOT_HUB,
+        ]);
+        assert_eq!(is_behind_hub(&composite, 0), Some(false));
+
+        // The top of the tree is no hub
+        assert_eq!(is_behind_hub(&usb_tree(&[STICK_ID, USB_DEVICE]), 0), None);
+        let controller = usb_tree(&[
+            STICK_ID,
+            USB_DEVICE,
+            r"PCI\VEN_8086&DEV_A36D\3&11583659&0&A0",
+        ]);
+        assert_eq!(is_behind_hub(&controller, 0), None);
+    }
+
+    /// Many optical drives only have a device description
+    #[test]
+    fn optical_drive_name() {
+        let mut set = disks(&DISKS[..2]);
+        set.devices[0].friendly_name = "";
+        set.devices[0].description = "HL-DT-ST DVDRAM GP65NB60";
+        set.devices[1].friendly_name = "";
+
+        let mut win32 = FakeWin32::default();
+        win32.disk(DISKS[0], 0);
+        let mut diagnostics = vec![];
+        let drives = drive_list(
+            &set,
+            &win32,
+            "CdRom",
+            Watchdog::new(None),
+            true,
+            None,
+            &mut diagnostics,
+        );
+
+        assert_eq!(drives.len(), 1);
+        assert_eq!(drives[0].description, "HL-DT-ST DVDRAM GP65NB60");
+        assert_eq!(drives[0].device, r"\\.\CdRom0");
+        assert_eq!(get_friendly_name(&set, &0), "HL-DT-ST DVDRAM GP65NB60");
+        assert_eq!(diagnostics, ["Skipped device 1 (SCSI): no friendly name"]);
+    }
+
+    #[test]
+    fn usb_enumerators() {
+        assert!(is_usb_drive("USBSTOR"));
+        assert!(is_usb_drive("UASPSTOR"));
+        assert!(!is_usb_drive("SCSI"));
+        assert!(!is_usb_drive("JMCR"));
+
+        let mut set = disks(&DISKS[..2]);
+        set.devices[0].enumerator = "JMCR";
+        let mut win32 = FakeWin32::default();
+        win32.disk(DISKS[0], 0);
+        win32.disk(DISKS[1], 1);
+
+        // Behind a SATA bridge, so only the enumerator tells it's USB
+        for path in [r"\\.\PhysicalDrive0", r"\\.\PhysicalDrive1"] {
+            win32.reply(
+                path,
+                IOCTL_STORAGE_QUERY_PROPERTY,
+                STORAGE_ADAPTER_PROPERTY,
+                Ok(adapter(11)),
+            );
+        }
+
+        let mut drives = list(&set, &win32, false);
+        assert!(!drives[0].is_usb);
+        assert_eq!(drives[0].media_type, MediaType::FixedMedia);
+        mark_usb_enumerators(&mut drives, &["jmcr".to_string()]);
+
+        assert!(drives[0].is_usb);
+        assert_eq!(drives[0].media_type, MediaType::ExternalFixed);
+        assert!(!drives[1].is_usb);
+        assert_eq!(drives[1].media_type, MediaType::FixedMedia);
+    }
+
+    /// Instance IDs aren't always upper case
+    #[test]
+    fn root_hub_prefix() {
+        let set = usb_tree(&[STICK_ID, USB_DEVICE, r"usb\root_hub\4&2b5ef2a7&0"]);
+        assert_eq!(is_behind_hub(&set, 0), Some(false));
+
+        // Only the prefix counts, not a hub which happens to contain it
+        let set = usb_tree(&[STICK_ID, USB_DEVICE, r"USB\VID_05E3&PID_0610\USB\ROOT_HUB"]);
+        assert_eq!(is_behind_hub(&set, 0), Some(true));
+    }
+
+    #[test]
+    fn instance_id() {
+        let drives = list(&usb_stick(STICK), &FakeWin32::default(), false);
+        let instance_id = drives[0].instance_id.as_deref().unwrap();
+
+        assert_eq!(instance_id, STICK_ID);
+        assert!(instance_id.starts_with(&format!(r"{}\", drives[0].enumerator)));
+        assert!(drives[0].is_removable);
+    }
+
+    #[test]
+    fn probe_adapter_failure() {
+        let mut win32 = FakeWin32::default();
+        win32.disk(STICK, 1);
+        win32.reply(
+            r"\\.\PhysicalDrive1",
+            IOCTL_STORAGE_QUERY_PROPERTY,
+            STORAGE_ADAPTER_PROPERTY,
+            Err(50),
+        );
+
+        let results = probe_capabilities(&win32, r"\\.\PhysicalDrive1").unwrap();
+        let failed: Vec<_> = results
+            .iter()
+            .filter(|x| !x.succeeded())
+            .map(|x| (x.query, x.error))
+            .collect();
+        // No serial or trim property in the fake either
+        assert_eq!(
+            failed,
+            [
+                ("adapter", Some(50)),
+                ("serial", Some(1)),
+                ("trim", Some(1))
+            ]
+        );
+        assert_eq!(win32.open_handles.get(), 0);
+
+        win32
+            .unopenable
+            .insert(r"\\.\PhysicalDrive2".to_string(), 2);
+        let err = probe_capabilities(&win32, r"\\.\PhysicalDrive2").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DriveListError>(),
+            Some(DriveListError::Io(err)) if err.raw_os_error() == Some(2)
+        ));
+    }
+}
@@ -1,68 +1,165 @@
-use crate::device::*;
+use super::{
+    decode::{self, ansi_to_string, wide_to_string},
+    layout,
+    win32::{self, DeviceInfoSource, FreeSpace, Win32Source},
+    Watchdog,
+};
+use crate::{device::*, probe::ProbeResult, DriveListError};
 use std::{
     ffi::{CString, OsStr},
-    mem::{align_of, size_of, transmute_copy, zeroed, MaybeUninit},
+    mem::{align_of, offset_of, size_of, zeroed, MaybeUninit},
     os::windows::prelude::OsStrExt,
-    ptr::null_mut,
-    str::from_utf8,
+    ptr::{copy_nonoverlapping, null_mut},
+    sync::atomic::AtomicBool,
 };
 use winapi::{
     ctypes::c_void,
     shared::{
+        guiddef::{IsEqualGUID, GUID},
         minwindef::{BYTE, DWORD, MAX_PATH, WORD},
-        winerror::{ERROR_INSUFFICIENT_BUFFER, ERROR_NO_MORE_ITEMS},
+        winerror::{
+            ERROR_INSUFFICIENT_BUFFER, ERROR_INVALID_NAME, ERROR_NOT_READY,
+            ERROR_NO_MEDIA_IN_DRIVE, ERROR_NO_MORE_ITEMS, ERROR_WRITE_PROTECT,
+        },
     },
     um::{
         cfgmgr32::{
-            CM_REMOVAL_POLICY_EXPECT_ORDERLY_REMOVAL, CM_REMOVAL_POLICY_EXPECT_SURPRISE_REMOVAL,
+            CM_Get_Device_IDW, CM_Get_Parent, CM_REMOVAL_POLICY_EXPECT_ORDERLY_REMOVAL,
+            CM_REMOVAL_POLICY_EXPECT_SURPRISE_REMOVAL, CR_SUCCESS, DEVINST, MAX_DEVICE_ID_LEN,
         },
-        errhandlingapi::GetLastError,
+        errhandlingapi::{GetLastError, SetLastError},
         fileapi::{
-            CreateFileA, CreateFileW, GetDiskFreeSpaceW, GetDriveTypeA, GetLogicalDrives,
+            CreateFileW, GetDiskFreeSpaceW, GetDriveTypeA, GetLogicalDrives, GetVolumeInformationW,
             GetVolumePathNameW, OPEN_EXISTING,
         },
         handleapi::{CloseHandle, INVALID_HANDLE_VALUE},
         ioapiset::DeviceIoControl,
         processenv::ExpandEnvironmentStringsA,
+        processthreadsapi::{GetCurrentProcess, OpenProcessToken},
+        securitybaseapi::GetTokenInformation,
         setupapi::{
-            SetupDiEnumDeviceInterfaces, SetupDiGetDeviceInterfaceDetailW,
-            SetupDiGetDeviceRegistryPropertyW, HDEVINFO, PSP_DEVINFO_DATA, SPDRP_ENUMERATOR_NAME,
-            SPDRP_FRIENDLYNAME, SPDRP_REMOVAL_POLICY, SP_DEVICE_INTERFACE_DATA,
-            SP_DEVICE_INTERFACE_DETAIL_DATA_W,
+            SetupDiEnumDeviceInfo, SetupDiEnumDeviceInterfaces, SetupDiGetDeviceInstanceIdW,
+            SetupDiGetDeviceInterfaceDetailW, SetupDiGetDeviceRegistryPropertyW, HDEVINFO,
+            PSP_DEVINFO_DATA, SPDRP_DEVICEDESC, SPDRP_ENUMERATOR_NAME, SPDRP_FRIENDLYNAME,
+            SPDRP_REMOVAL_POLICY, SP_DEVICE_INTERFACE_DATA, SP_DEVICE_INTERFACE_DETAIL_DATA_W,
+            SP_DEVINFO_DATA,
+        },
+        winbase::{
+            GetDevicePowerState, DRIVE_FIXED, DRIVE_RAMDISK, DRIVE_REMOVABLE, DRIVE_UNKNOWN,
         },
-        winbase::{DRIVE_FIXED, DRIVE_REMOVABLE},
         winioctl::{
-            PropertyStandardQuery, StorageAccessAlignmentProperty, StorageAdapterProperty,
-            DISK_GEOMETRY_EX, DRIVE_LAYOUT_INFORMATION_EX, GUID_DEVINTERFACE_DISK,
-            IOCTL_DISK_GET_DRIVE_GEOMETRY_EX, IOCTL_DISK_GET_DRIVE_LAYOUT_EX,
-            IOCTL_DISK_IS_WRITABLE, IOCTL_STORAGE_GET_DEVICE_NUMBER, IOCTL_STORAGE_QUERY_PROPERTY,
-            IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS, PARTITION_INFORMATION_EX, PARTITION_STYLE_GPT,
-            PARTITION_STYLE_MBR, STORAGE_DEVICE_NUMBER, STORAGE_PROPERTY_QUERY,
-            VOLUME_DISK_EXTENTS,
+            PropertyStandardQuery, RemovableMedia, StorageAccessAlignmentProperty,
+            StorageAdapterProperty, StorageDeviceLBProvisioningProperty, StorageDeviceProperty,
+            StorageDeviceTrimProperty, StorageDeviceWriteCacheProperty, DISK_EXTENT, DISK_GEOMETRY,
+            DISK_GEOMETRY_EX, DRIVE_LAYOUT_INFORMATION_EX, FILE_DEVICE_DISK, FSCTL_IS_VOLUME_DIRTY,
+            GUID_DEVINTERFACE_CDROM, GUID_DEVINTERFACE_TAPE, IOCTL_DISK_GET_DRIVE_GEOMETRY_EX,
+            IOCTL_DISK_GET_DRIVE_LAYOUT_EX, IOCTL_DISK_IS_WRITABLE,
+            IOCTL_STORAGE_GET_DEVICE_NUMBER, IOCTL_STORAGE_PREDICT_FAILURE,
+            IOCTL_STORAGE_QUERY_PROPERTY, IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS,
+            PARTITION_INFORMATION_EX, STORAGE_DEVICE_NUMBER, STORAGE_PROPERTY_ID,
+            STORAGE_PROPERTY_QUERY, VOLUME_DISK_EXTENTS,
+        },
+        winnt::{
+            TokenElevation, BOOLEAN, FILE_ATTRIBUTE_NORMAL, FILE_CASE_SENSITIVE_SEARCH,
+            FILE_SHARE_READ, FILE_VOLUME_IS_COMPRESSED, HANDLE, TOKEN_ELEVATION, TOKEN_QUERY,
         },
-        winnt::{BOOLEAN, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ},
     },
 };
 
-pub(crate) fn ansi_to_string(unsafe_utf8: &[u8]) -> String {
-    match from_utf8(
-        &unsafe_utf8
-            .iter()
-            .filter(|c| **c != 0)
-            .copied()
-            .collect::<Vec<u8>>() as _,
-    ) {
-        Err(err) => {
-            println!("Error {}", err);
-            "".to_string()
-        }
-        Ok(res) => res.trim().to_string(),
-    }
+/// Enumerate all disk devices present in `h_device_info`.
+///
+/// # Safety
+///
+/// `h_device_info` must be a valid device information set. It is not destroyed.
+pub(crate) unsafe fn drive_list_from_device_info(
+    h_device_info: HDEVINFO,
+    interface_guid: &GUID,
+    watchdog: Watchdog,
+    minimal: bool,
+    cancel: Option<&AtomicBool>,
+    diagnostics: &mut Vec<String>,
+) -> Vec<DeviceDescriptor> {
+    let set = DeviceInfoSet {
+        handle: h_device_info,
+        interface_guid,
+    };
+
+    win32::drive_list(
+        &set,
+        &Win32,
+        device_name_prefix(interface_guid),
+        watchdog,
+        minimal,
+        cancel,
+        diagnostics,
+    )
 }
 
+// The Win32 values used by the cross-platform enumeration
+const _: () = assert!(win32::IOCTL_STORAGE_GET_DEVICE_NUMBER == IOCTL_STORAGE_GET_DEVICE_NUMBER);
+const _: () = assert!(win32::IOCTL_STORAGE_QUERY_PROPERTY == IOCTL_STORAGE_QUERY_PROPERTY);
+const _: () =
+    assert!(win32::IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS == IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS);
+const _: () = assert!(win32::IOCTL_DISK_GET_DRIVE_GEOMETRY_EX == IOCTL_DISK_GET_DRIVE_GEOMETRY_EX);
+const _: () = assert!(win32::IOCTL_DISK_GET_DRIVE_LAYOUT_EX == IOCTL_DISK_GET_DRIVE_LAYOUT_EX);
+const _: () = assert!(win32::IOCTL_DISK_IS_WRITABLE == IOCTL_DISK_IS_WRITABLE);
+const _: () = assert!(win32::FSCTL_IS_VOLUME_DIRTY == FSCTL_IS_VOLUME_DIRTY);
+const _: () = assert!(win32::STORAGE_DEVICE_PROPERTY == StorageDeviceProperty);
+const _: () = assert!(win32::STORAGE_ADAPTER_PROPERTY == StorageAdapterProperty);
+const _: () = assert!(win32::STORAGE_WRITE_CACHE_PROPERTY == StorageDeviceWriteCacheProperty);
+const _: () = assert!(win32::STORAGE_ACCESS_ALIGNMENT_PROPERTY == StorageAccessAlignmentProperty);
+const _: () = assert!(win32::STORAGE_TRIM_PROPERTY == StorageDeviceTrimProperty);
+const _: () = assert!(win32::STORAGE_PROVISIONING_PROPERTY == StorageDeviceLBProvisioningProperty);
+const _: () = assert!(win32::PROPERTY_QUERY_SIZE == size_of::<STORAGE_PROPERTY_QUERY>());
+const _: () = assert!(win32::SPDRP_DEVICEDESC == SPDRP_DEVICEDESC);
+const _: () = assert!(win32::SPDRP_FRIENDLYNAME == SPDRP_FRIENDLYNAME);
+const _: () = assert!(win32::SPDRP_ENUMERATOR_NAME == SPDRP_ENUMERATOR_NAME);
+const _: () = assert!(
+    win32::CM_REMOVAL_POLICY_EXPECT_ORDERLY_REMOVAL == CM_REMOVAL_POLICY_EXPECT_ORDERLY_REMOVAL
+);
+const _: () = assert!(
+    win32::CM_REMOVAL_POLICY_EXPECT_SURPRISE_REMOVAL == CM_REMOVAL_POLICY_EXPECT_SURPRISE_REMOVAL
+);
+const _: () = assert!(win32::DRIVE_REMOVABLE == DRIVE_REMOVABLE);
+const _: () = assert!(win32::DRIVE_FIXED == DRIVE_FIXED);
+const _: () = assert!(win32::DRIVE_RAMDISK == DRIVE_RAMDISK);
+const _: () = assert!(win32::ERROR_NOT_READY == ERROR_NOT_READY);
+const _: () = assert!(win32::ERROR_NO_MEDIA_IN_DRIVE == ERROR_NO_MEDIA_IN_DRIVE);
+const _: () = assert!(decode::ERROR_WRITE_PROTECT == ERROR_WRITE_PROTECT);
+const _: () = assert!(decode::FILE_CASE_SENSITIVE_SEARCH == FILE_CASE_SENSITIVE_SEARCH);
+const _: () = assert!(decode::FILE_VOLUME_IS_COMPRESSED == FILE_VOLUME_IS_COMPRESSED);
+const _: () = assert!(decode::FILE_DEVICE_DISK == FILE_DEVICE_DISK);
+const _: () = assert!(decode::REMOVABLE_MEDIA == RemovableMedia);
+
+// Descriptors are read from the bytes at fixed offsets
+const _: () = assert!(decode::DEVICE_NUMBER_SIZE == size_of::<STORAGE_DEVICE_NUMBER>());
+const _: () = assert!(decode::DISK_EXTENTS_SIZE == size_of::<VOLUME_DISK_EXTENTS>());
+const _: () = assert!(
+    decode::DISK_EXTENTS_NUMBER_OFFSET
+        == offset_of!(VOLUME_DISK_EXTENTS, Extents) + offset_of!(DISK_EXTENT, DiskNumber)
+);
+const _: () = assert!(decode::GEOMETRY_SIZE == size_of::<DISK_GEOMETRY_EX>());
+const _: () = assert!(
+    decode::GEOMETRY_MEDIA_TYPE_OFFSET
+        == offset_of!(DISK_GEOMETRY_EX, Geometry) + offset_of!(DISK_GEOMETRY, MediaType)
+);
+const _: () = assert!(
+    decode::GEOMETRY_SECTOR_OFFSET
+        == offset_of!(DISK_GEOMETRY_EX, Geometry) + offset_of!(DISK_GEOMETRY, BytesPerSector)
+);
+const _: () = assert!(decode::GEOMETRY_SIZE_OFFSET == offset_of!(DISK_GEOMETRY_EX, DiskSize));
+const _: () = assert!(
+    win32::LAYOUT_BUFFER_SIZE
+        == size_of::<DRIVE_LAYOUT_INFORMATION_EX>() + 256 * size_of::<PARTITION_INFORMATION_EX>()
+);
+const _: () =
+    assert!(offset_of!(DRIVE_LAYOUT_INFORMATION_EX, PartitionEntry) == layout::ENTRIES_OFFSET);
+const _: () = assert!(size_of::<PARTITION_INFORMATION_EX>() == layout::ENTRY_SIZE);
+
 #[repr(C)]
 #[derive(Copy)]
 #[allow(non_snake_case)]
+#[allow(dead_code)]
 struct STORAGE_ADAPTER_DESCRIPTOR {
     Version: DWORD,
     Size: DWORD,
@@ -92,254 +189,322 @@ impl Default for STORAGE_ADAPTER_DESCRIPTOR {
     }
 }
 
-type StorageBusType = u32;
-const BUS_TYPE_UNKNOWN: StorageBusType = 0;
-const BUS_TYPE_SCSI: StorageBusType = 1;
-const BUS_TYPE_ATAPI: StorageBusType = 2;
-const BUS_TYPE_ATA: StorageBusType = 3;
-const BUS_TYPE1394: StorageBusType = 4;
-const BUS_TYPE_SSA: StorageBusType = 5;
-const BUS_TYPE_FIBRE: StorageBusType = 6;
-const BUS_TYPE_USB: StorageBusType = 7;
-const BUS_TYPE_RAID: StorageBusType = 8;
-const BUS_TYPEI_SCSI: StorageBusType = 9;
-const BUS_TYPE_SAS: StorageBusType = 10;
-const BUS_TYPE_SATA: StorageBusType = 11;
-const BUS_TYPE_SD: StorageBusType = 12;
-const BUS_TYPE_MMC: StorageBusType = 13;
-const BUS_TYPE_VIRTUAL: StorageBusType = 14;
-const BUS_TYPE_FILE_BACKED_VIRTUAL: StorageBusType = 15;
-//const BusTypeSpaces:STORAGE_BUS_TYPE=16;
-const BUS_TYPE_NVME: StorageBusType = 17;
-const BUS_TYPE_SCM: StorageBusType = 18;
-const BUS_TYPE_UFS: StorageBusType = 19;
-//const BusTypeMax:STORAGE_BUS_TYPE=20;
-//const BusTypeMaxReserved:STORAGE_BUS_TYPE=0x7F;
-
-fn get_adapter_info(device: &mut DeviceDescriptor, h_physical: *mut c_void) -> bool {
-    unsafe {
-        let mut query = MaybeUninit::<STORAGE_PROPERTY_QUERY>::zeroed();
-        let mut adapter_descriptor = MaybeUninit::<STORAGE_ADAPTER_DESCRIPTOR>::zeroed();
-        let mut size = 0_u32;
+const _: () = assert!(
+    offset_of!(STORAGE_ADAPTER_DESCRIPTOR, MaximumTransferLength) == decode::MAX_TRANSFER_OFFSET
+);
+const _: () = assert!(
+    offset_of!(STORAGE_ADAPTER_DESCRIPTOR, CommandQueueing) == decode::COMMAND_QUEUEING_OFFSET
+);
+const _: () = assert!(offset_of!(STORAGE_ADAPTER_DESCRIPTOR, BusType) == decode::BUS_TYPE_OFFSET);
+const _: () =
+    assert!(offset_of!(STORAGE_ADAPTER_DESCRIPTOR, BusMajorVersion) == decode::BUS_VERSION_OFFSET);
 
-        query.assume_init_mut().QueryType = PropertyStandardQuery;
-        query.assume_init_mut().PropertyId = StorageAdapterProperty;
+const _: () = assert!(size_of::<STORAGE_ADAPTER_DESCRIPTOR>() == decode::ADAPTER_SIZE);
 
-        let has_adapter_info = DeviceIoControl(
-            h_physical,
-            IOCTL_STORAGE_QUERY_PROPERTY,
-            query.as_mut_ptr() as _,
-            size_of::<STORAGE_PROPERTY_QUERY>() as u32,
-            adapter_descriptor.as_mut_ptr() as _,
-            size_of::<STORAGE_ADAPTER_DESCRIPTOR>() as u32,
-            &mut size,
-            null_mut(),
-        );
+/// `cbSize` of `SP_DEVICE_INTERFACE_DETAIL_DATA_W`, which is packed on 32-bit x86
+const DETAIL_DATA_SIZE: DWORD = size_of::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>() as DWORD;
+const _: () = assert!(DETAIL_DATA_SIZE == if cfg!(target_arch = "x86") { 6 } else { 8 });
 
-        if has_adapter_info != 0 {
-            let val = adapter_descriptor.assume_init_ref();
-            device.bus_type = Some(get_bus_type(val));
-            device.bus_version = Some(format!("{}.{}", val.BusMajorVersion, val.BusMinorVersion));
-            return true;
-        }
+/// Alignment of the detail data buffer. The packed struct is only byte aligned, but SetupAPI
+/// still writes a `DWORD` followed by a wide string into it.
+const DETAIL_DATA_ALIGN: usize = align_of::<DWORD>();
+
+/// Offset of `DevicePath` in `SP_DEVICE_INTERFACE_DETAIL_DATA_W`
+const DEVICE_PATH_OFFSET: usize = offset_of!(SP_DEVICE_INTERFACE_DETAIL_DATA_W, DevicePath);
+
+/// Owned Windows handle, closed on drop
+struct SafeHandle(HANDLE);
+
+impl SafeHandle {
+    /// Take ownership of `handle`, or `None` if it is `INVALID_HANDLE_VALUE`
+    fn new(handle: HANDLE) -> Option<Self> {
+        (handle != INVALID_HANDLE_VALUE).then_some(Self(handle))
     }
 
-    false
+    fn as_raw(&self) -> HANDLE {
+        self.0
+    }
 }
 
-fn get_available_volumes() -> Vec<char> {
-    unsafe {
-        let mut logical_drive_mask = GetLogicalDrives();
-        let mut current_drive_letter = b'A';
-        let mut vec_char: Vec<char> = Vec::new();
+impl Drop for SafeHandle {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0);
+        }
+    }
+}
 
-        while logical_drive_mask != 0 {
-            if (logical_drive_mask & 1) != 0 {
-                vec_char.push(current_drive_letter as _);
-            }
+/// Win32 calls of the enumeration
+struct Win32;
 
-            current_drive_letter += 1;
-            logical_drive_mask >>= 1;
-        }
+impl Win32Source for Win32 {
+    type Handle = SafeHandle;
+
+    fn open(&self, path: &str) -> Result<SafeHandle, u32> {
+        let path = to_wide(path).ok_or(ERROR_INVALID_NAME)?;
 
-        vec_char
+        unsafe {
+            SafeHandle::new(CreateFileW(
+                path.as_ptr(),
+                0,
+                FILE_SHARE_READ,
+                null_mut(),
+                OPEN_EXISTING,
+                FILE_ATTRIBUTE_NORMAL,
+                null_mut(),
+            ))
+            .ok_or_else(|| GetLastError())
+        }
     }
-}
 
-fn get_bus_type(adapter: &STORAGE_ADAPTER_DESCRIPTOR) -> String {
-    match adapter.BusType as u32 {
-        BUS_TYPE_UNKNOWN => "UNKNOWN",
-        BUS_TYPE_SCSI => "SCSI",
-        BUS_TYPE_ATAPI => "ATAPI",
-        BUS_TYPE_ATA => "ATA",
-        BUS_TYPE1394 => "1394", // IEEE 1394
-        BUS_TYPE_SSA => "SSA",
-        BUS_TYPE_FIBRE => "FIBRE",
-        BUS_TYPE_USB => "USB",
-        BUS_TYPE_RAID => "RAID",
-        BUS_TYPEI_SCSI => "iSCSI",
-        BUS_TYPE_SAS => "SAS", // Serial-Attached SCSI
-        BUS_TYPE_SATA => "SATA",
-        BUS_TYPE_SD => "SDCARD", // Secure Digital (SD)
-        BUS_TYPE_MMC => "MMC",   // Multimedia card
-        BUS_TYPE_VIRTUAL => "VIRTUAL",
-        BUS_TYPE_FILE_BACKED_VIRTUAL => "FILEBACKEDVIRTUAL",
-        BUS_TYPE_NVME => "NVME",
-        BUS_TYPE_UFS => "UFS",
-        BUS_TYPE_SCM => "SCM",
-        _ => "INVALID",
-    }
-    .to_string()
-}
+    fn ioctl(
+        &self,
+        handle: &SafeHandle,
+        code: u32,
+        input: &[u8],
+        len: usize,
+    ) -> Result<Vec<u8>, u32> {
+        let mut input = input.to_vec();
+        let mut bytes = vec![0_u8; len];
+        let mut size = 0_u32;
 
-pub(crate) fn is_system_device(device: &DeviceDescriptor) -> bool {
-    unsafe {
-        for sys_var in ["%windir%\0", "%ProgramFiles%\0"] {
-            let mut buffer: [i8; MAX_PATH] = zeroed();
-            let res = ExpandEnvironmentStringsA(
-                sys_var.as_ptr() as _,
-                &mut buffer as _,
-                (size_of::<u8>() * MAX_PATH) as u32,
-            );
+        let res = unsafe {
+            DeviceIoControl(
+                handle.as_raw(),
+                code,
+                if input.is_empty() {
+                    null_mut()
+                } else {
+                    input.as_mut_ptr() as _
+                },
+                input.len() as _,
+                if bytes.is_empty() {
+                    null_mut()
+                } else {
+                    bytes.as_mut_ptr() as _
+                },
+                len as _,
+                &mut size,
+                null_mut(),
+            )
+        };
 
-            if res > 0 {
-                let mut tmp_buffer = vec![0_u8; res as usize];
+        if res == 0 {
+            return Err(unsafe { GetLastError() });
+        }
 
-                for i in buffer {
-                    tmp_buffer.push(i as u8);
-                }
+        bytes.truncate(size as usize);
+        Ok(bytes)
+    }
 
-                let val = ansi_to_string(&tmp_buffer);
+    fn logical_drives(&self) -> Result<u32, u32> {
+        unsafe {
+            SetLastError(0);
+            let mask = GetLogicalDrives();
 
-                for mp in device.mountpoints.iter() {
-                    if val.contains(&mp.path) {
-                        return true;
-                    }
-                }
+            // A zero mask is only an error if the call actually failed
+            match GetLastError() {
+                0 => Ok(mask),
+                code if mask == 0 => Err(code),
+                _ => Ok(mask),
             }
         }
     }
-    false
+
+    fn drive_type(&self, root: &str) -> u32 {
+        get_drive_type(root)
+    }
+
+    fn volume_path_name(&self, path: &str) -> Result<String, u32> {
+        let path = to_wide(path).ok_or(ERROR_INVALID_NAME)?;
+        let mut root_path = [0_u16; MAX_PATH + 1];
+
+        if unsafe {
+            GetVolumePathNameW(path.as_ptr(), root_path.as_mut_ptr(), root_path.len() as _)
+        } == 0
+        {
+            return Err(unsafe { GetLastError() });
+        }
+
+        Ok(wide_to_string(&root_path))
+    }
+
+    fn volume_information(&self, root: &str) -> Option<(String, u32)> {
+        let root = to_wide(root)?;
+        let mut file_system_flags = 0;
+        let mut volume_name = [0_u16; MAX_PATH + 1];
+
+        let res = unsafe {
+            GetVolumeInformationW(
+                root.as_ptr(),
+                volume_name.as_mut_ptr(),
+                volume_name.len() as _,
+                null_mut(),
+                null_mut(),
+                &mut file_system_flags,
+                null_mut(),
+                0,
+            )
+        };
+
+        (res != 0).then(|| (wide_to_string(&volume_name), file_system_flags))
+    }
+
+    fn disk_free_space(&self, root: &str) -> Result<FreeSpace, u32> {
+        let root = to_wide(root).ok_or(ERROR_INVALID_NAME)?;
+        let mut space = FreeSpace::default();
+
+        if unsafe {
+            GetDiskFreeSpaceW(
+                root.as_ptr(),
+                &mut space.sectors_per_cluster,
+                &mut space.bytes_per_sector,
+                &mut space.free_clusters,
+                &mut space.total_clusters,
+            )
+        } == 0
+        {
+            return Err(unsafe { GetLastError() });
+        }
+
+        Ok(space)
+    }
+
+    fn system_directories(&self) -> Vec<String> {
+        ["%windir%\0", "%ProgramFiles%\0"]
+            .iter()
+            .filter_map(|sys_var| {
+                let mut buffer = [0_u8; MAX_PATH];
+                let res = unsafe {
+                    ExpandEnvironmentStringsA(
+                        sys_var.as_ptr() as _,
+                        buffer.as_mut_ptr() as _,
+                        buffer.len() as _,
+                    )
+                };
+
+                // Longer values don't fit and leave the buffer untouched
+                (res > 0 && res as usize <= buffer.len()).then(|| ansi_to_string(&buffer))
+            })
+            .collect()
+    }
 }
 
-#[allow(non_snake_case)]
-#[allow(non_camel_case_types)]
-#[allow(dead_code)]
-struct STORAGE_ACCESS_ALIGNMENT_DESCRIPTOR {
-    Version: DWORD,
-    Size: DWORD,
-    BytesPerCacheLine: DWORD,
-    BytesOffsetForCacheAlignment: DWORD,
-    BytesPerLogicalSector: DWORD,
-    BytesPerPhysicalSector: DWORD,
-    BytesOffsetForSectorAlignment: DWORD,
+/// NUL terminated UTF-16 copy of `path`, `None` if it contains a NUL
+fn to_wide(path: &str) -> Option<Vec<u16>> {
+    if path.contains('\0') {
+        return None;
+    }
+
+    Some(OsStr::new(path).encode_wide().chain(Some(0)).collect())
 }
 
-fn get_device_block_size(device: &mut DeviceDescriptor, h_physical: *mut c_void) -> bool {
-    unsafe {
-        let mut query = MaybeUninit::<STORAGE_PROPERTY_QUERY>::zeroed();
-        let mut descriptor = MaybeUninit::<STORAGE_ACCESS_ALIGNMENT_DESCRIPTOR>::zeroed();
-        let mut size = 0_u32;
+/// Device information set of one interface class, from `SetupDiGetClassDevs`
+struct DeviceInfoSet<'a> {
+    handle: HDEVINFO,
+    interface_guid: &'a GUID,
+}
 
-        query.assume_init_mut().QueryType = PropertyStandardQuery;
-        query.assume_init_mut().PropertyId = StorageAccessAlignmentProperty;
+impl DeviceInfoSource for DeviceInfoSet<'_> {
+    type Device = SP_DEVINFO_DATA;
+    type Node = DEVINST;
 
-        let has_adapter_info = DeviceIoControl(
-            h_physical,
-            IOCTL_STORAGE_QUERY_PROPERTY,
-            query.as_mut_ptr() as _,
-            size_of::<STORAGE_PROPERTY_QUERY>() as u32,
-            descriptor.as_mut_ptr() as _,
-            size_of::<STORAGE_ACCESS_ALIGNMENT_DESCRIPTOR>() as u32,
-            &mut size,
-            null_mut(),
-        );
+    fn device(&self, index: u32) -> Option<SP_DEVINFO_DATA> {
+        unsafe {
+            let mut device_info_data: SP_DEVINFO_DATA = zeroed();
+            device_info_data.cbSize = size_of::<SP_DEVINFO_DATA>() as _;
 
-        if has_adapter_info != 0 {
-            let val = descriptor.assume_init_ref();
-            device.block_size = val.BytesPerPhysicalSector;
-            device.logical_block_size = val.BytesPerLogicalSector;
-            return true;
+            (SetupDiEnumDeviceInfo(self.handle, index, &mut device_info_data) != 0)
+                .then_some(device_info_data)
         }
     }
 
-    false
-}
+    fn registry_string(&self, device: &SP_DEVINFO_DATA, property: u32) -> String {
+        let mut device = *device;
 
-fn get_device_number(h_device: *mut c_void) -> i32 {
-    unsafe {
-        let mut size = 0_u32;
-        let mut disk_number = -1;
-        //let mut void_buffer=null_mut();
-        //let mut disk_extents=MaybeUninit::<VOLUME_DISK_EXTENTS>::uninit();
-
-        let mut disk_extents = MaybeUninit::<VOLUME_DISK_EXTENTS>::uninit();
-        disk_extents.write(zeroed());
-        let mut result = DeviceIoControl(
-            h_device,
-            IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS,
-            null_mut(),
-            0,
-            disk_extents.as_mut_ptr() as _,
-            size_of::<VOLUME_DISK_EXTENTS>() as _,
-            &mut size,
-            null_mut(),
-        );
+        get_registry_string(self.handle, &mut device, property)
+    }
 
-        if result != 0 {
-            let de = disk_extents.assume_init_ref();
+    fn removal_policy(&self, device: &SP_DEVINFO_DATA) -> u32 {
+        let mut device = *device;
+        let mut policy = 0_u32;
 
-            if de.NumberOfDiskExtents >= 2 {
-                return -1;
-            }
+        unsafe {
+            SetupDiGetDeviceRegistryPropertyW(
+                self.handle,
+                &mut device,
+                SPDRP_REMOVAL_POLICY,
+                null_mut(),
+                &mut policy as *mut _ as _,
+                size_of::<u32>() as _,
+                null_mut(),
+            );
+        }
+
+        policy
+    }
+
+    fn instance_id(&self, device: &SP_DEVINFO_DATA) -> Option<String> {
+        let mut device = *device;
+        let mut buffer = [0_u16; MAX_DEVICE_ID_LEN];
 
-            disk_number = de.Extents[0].DiskNumber as _;
+        if unsafe {
+            SetupDiGetDeviceInstanceIdW(
+                self.handle,
+                &mut device,
+                buffer.as_mut_ptr(),
+                buffer.len() as _,
+                null_mut(),
+            )
+        } == 0
+        {
+            return None;
         }
 
-        let mut device_number = MaybeUninit::<STORAGE_DEVICE_NUMBER>::uninit();
-        device_number.write(zeroed());
+        Some(wide_to_string(&buffer)).filter(|x| !x.is_empty())
+    }
 
-        result = DeviceIoControl(
-            h_device,
-            IOCTL_STORAGE_GET_DEVICE_NUMBER,
-            null_mut(),
-            0,
-            device_number.as_mut_ptr() as _,
-            size_of::<STORAGE_DEVICE_NUMBER>() as _,
-            &mut size,
-            null_mut(),
-        );
+    fn node(&self, device: &SP_DEVINFO_DATA) -> DEVINST {
+        device.DevInst
+    }
+
+    fn parent(&self, node: DEVINST) -> Option<DEVINST> {
+        let mut parent: DEVINST = 0;
 
-        if result != 0 {
-            disk_number = device_number.assume_init_ref().DeviceNumber as _;
+        if unsafe { CM_Get_Parent(&mut parent, node, 0) } != CR_SUCCESS {
+            return None;
         }
 
-        disk_number
+        Some(parent)
     }
-}
 
-pub(crate) fn get_detail_data(
-    device: &mut DeviceDescriptor,
-    h_dev_info: HDEVINFO,
-    device_info_data: PSP_DEVINFO_DATA,
-) {
-    let mut h_device = INVALID_HANDLE_VALUE;
-    let mut index = 0_u32;
+    fn device_id(&self, node: DEVINST) -> Option<String> {
+        let mut buffer = [0_u16; MAX_DEVICE_ID_LEN];
 
-    unsafe {
-        loop {
-            if h_device != INVALID_HANDLE_VALUE {
-                CloseHandle(h_device);
-                h_device = INVALID_HANDLE_VALUE;
-            }
+        if unsafe { CM_Get_Device_IDW(node, buffer.as_mut_ptr(), buffer.len() as _, 0) }
+            != CR_SUCCESS
+        {
+            return None;
+        }
+
+        Some(wide_to_string(&buffer)).filter(|x| !x.is_empty())
+    }
 
+    fn interface_path(
+        &self,
+        device: &SP_DEVINFO_DATA,
+        index: u32,
+    ) -> anyhow::Result<Option<String>> {
+        let mut device = *device;
+
+        unsafe {
             let mut device_interface_data: SP_DEVICE_INTERFACE_DATA = zeroed();
             device_interface_data.cbSize = size_of::<SP_DEVICE_INTERFACE_DATA>() as _;
 
             if SetupDiEnumDeviceInterfaces(
-                h_dev_info,
-                device_info_data,
-                &GUID_DEVINTERFACE_DISK,
+                self.handle,
+                &mut device,
+                self.interface_guid,
                 index,
                 &mut device_interface_data,
             ) == 0
@@ -347,399 +512,365 @@ pub(crate) fn get_detail_data(
                 let error_code = GetLastError();
 
                 if error_code != ERROR_NO_MORE_ITEMS {
-                    panic!("SetupDiEnumDeviceInterfaces: Error {}", error_code);
+                    anyhow::bail!("SetupDiEnumDeviceInterfaces: Error {}", error_code);
                 }
 
-                break;
-            } else {
-                let mut size = {
-                    let mut required_size = MaybeUninit::<u32>::uninit();
-
-                    if SetupDiGetDeviceInterfaceDetailW(
-                        h_dev_info,
-                        &mut device_interface_data,
-                        null_mut(),
-                        0,
-                        required_size.as_mut_ptr(),
-                        null_mut(),
-                    ) == 0
-                    {
-                        if GetLastError() == ERROR_INSUFFICIENT_BUFFER {
-                            required_size.assume_init()
-                        } else {
-                            panic!("Error SetupDiGetDeviceInterfaceDetailW");
-                        }
-                    } else {
-                        0
-                    }
-                };
-                let mut buf: Vec<u8> = Vec::with_capacity(
-                    TryInto::<usize>::try_into(size).unwrap()
-                        + align_of::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>()
-                        - 1,
-                );
-                let align_offset = buf
-                    .as_mut_ptr()
-                    .align_offset(align_of::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>());
-                let device_iface_detail =
-                    &mut *(buf.as_mut_ptr().offset(align_offset.try_into().unwrap())
-                        as *mut MaybeUninit<SP_DEVICE_INTERFACE_DETAIL_DATA_W>);
-                device_iface_detail.write(SP_DEVICE_INTERFACE_DETAIL_DATA_W {
-                    cbSize: size_of::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>()
-                        .try_into()
-                        .unwrap(),
-                    DevicePath: [0],
-                });
-
-                if SetupDiGetDeviceInterfaceDetailW(
-                    h_dev_info,
-                    &mut device_interface_data,
-                    device_iface_detail.as_mut_ptr(),
-                    size,
-                    &mut size,
-                    null_mut(),
-                ) == 0
-                {
-                    println!(
-                        "Error {}, Couldn't SetupDiGetDeviceInterfaceDetailW",
-                        GetLastError()
-                    );
-                    break;
-                }
+                return Ok(None);
+            }
 
-                let device_detail_data = device_iface_detail.assume_init_ref();
-
-                h_device = CreateFileW(
-                    device_detail_data.DevicePath.as_ptr(),
-                    0,
-                    FILE_SHARE_READ,
-                    null_mut(),
-                    OPEN_EXISTING,
-                    FILE_ATTRIBUTE_NORMAL,
-                    null_mut(),
-                );
-
-                if h_device == INVALID_HANDLE_VALUE {
-                    println!("Couldn't open handle to device: Error {}", GetLastError());
-                    break;
-                }
+            let mut size = 0_u32;
 
-                let device_number = get_device_number(h_device);
+            if SetupDiGetDeviceInterfaceDetailW(
+                self.handle,
+                &mut device_interface_data,
+                null_mut(),
+                0,
+                &mut size,
+                null_mut(),
+            ) == 0
+                && GetLastError() != ERROR_INSUFFICIENT_BUFFER
+            {
+                anyhow::bail!("SetupDiGetDeviceInterfaceDetailW: Error {}", GetLastError());
+            }
 
-                if device_number < 0 {
-                    device.error = Some("Couldn't get device number".to_string());
-                    break;
-                }
+            let mut buf: Vec<u8> = Vec::with_capacity(size as usize + DETAIL_DATA_ALIGN - 1);
+            let align_offset = buf.as_mut_ptr().align_offset(DETAIL_DATA_ALIGN);
+            let device_iface_detail = &mut *(buf.as_mut_ptr().add(align_offset)
+                as *mut MaybeUninit<SP_DEVICE_INTERFACE_DETAIL_DATA_W>);
+            device_iface_detail.write(SP_DEVICE_INTERFACE_DETAIL_DATA_W {
+                cbSize: DETAIL_DATA_SIZE,
+                DevicePath: [0],
+            });
+
+            if SetupDiGetDeviceInterfaceDetailW(
+                self.handle,
+                &mut device_interface_data,
+                device_iface_detail.as_mut_ptr(),
+                size,
+                &mut size,
+                null_mut(),
+            ) == 0
+            {
+                anyhow::bail!("SetupDiGetDeviceInterfaceDetailW: Error {}", GetLastError());
+            }
 
-                device.raw = format!(r"\\.\PhysicalDrive{}", device_number);
-                device.device = device.raw.clone();
+            // A raw pointer, since the field is unaligned on x86 as far as the compiler knows
+            let device_path = (&raw const (*device_iface_detail.as_ptr()).DevicePath).cast::<u16>();
+            let len = (size as usize).saturating_sub(DEVICE_PATH_OFFSET) / size_of::<u16>();
+            let path = std::slice::from_raw_parts(device_path, len);
 
-                if let Err(err) = get_mount_points(device_number, &mut device.mountpoints) {
-                    device.error = Some(err.to_string());
-                    break;
-                }
+            Ok(Some(wide_to_string(path)))
+        }
+    }
+}
 
-                let h_physical = CreateFileA(
-                    CString::new(device.device.clone()).unwrap().as_ptr(),
-                    0,
-                    FILE_SHARE_READ,
-                    null_mut(),
-                    OPEN_EXISTING,
-                    FILE_ATTRIBUTE_NORMAL,
-                    null_mut(),
-                );
-
-                if h_physical == INVALID_HANDLE_VALUE {
-                    device.error = Some(format!(
-                        "Cannot open: {}",
-                        device.device_path.as_ref().unwrap()
-                    ));
-                    break;
-                }
+/// Check whether the process token is elevated, which is required to open `\\.\PhysicalDriveN`
+pub(crate) fn is_elevated() -> bool {
+    unsafe {
+        let mut token = null_mut();
 
-                if !get_device_size(device, h_physical) {
-                    let error_code = GetLastError();
-                    device.error =
-                        Some(format!("Couldn't get disk geometry: Error {}", error_code));
-                    break;
-                }
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+            return false;
+        }
 
-                if !get_partition_table_type(device, h_physical) {
-                    device.error = Some(format!(
-                        "Couldn't get partition type: Error {}",
-                        GetLastError()
-                    ));
-                    break;
-                }
+        let token = SafeHandle(token);
+        let mut elevation: TOKEN_ELEVATION = zeroed();
+        let mut size = 0_u32;
+        let res = GetTokenInformation(
+            token.as_raw(),
+            TokenElevation,
+            &mut elevation as *mut _ as _,
+            size_of::<TOKEN_ELEVATION>() as _,
+            &mut size,
+        );
 
-                if !get_adapter_info(device, h_physical) {
-                    device.error = Some(format!(
-                        "Couldn't get adapter info: Error {}",
-                        GetLastError()
-                    ));
-                    break;
-                }
+        res != 0 && elevation.TokenIsElevated != 0
+    }
+}
 
-                if !get_device_block_size(device, h_physical) {
-                    device.error = Some(format!(
-                        "Couldn't get device block size: Error {}",
-                        GetLastError()
-                    ));
-                    break;
-                }
+pub(crate) fn probe_capabilities(device: &str) -> anyhow::Result<Vec<ProbeResult>> {
+    win32::probe_capabilities(&Win32, device)
+}
 
-                device.is_readonly = DeviceIoControl(
-                    h_physical,
-                    IOCTL_DISK_IS_WRITABLE,
-                    null_mut(),
-                    0,
-                    null_mut(),
-                    0,
-                    &mut size,
-                    null_mut(),
-                ) == 0;
-                CloseHandle(h_physical);
-            }
+#[repr(C)]
+#[allow(non_snake_case)]
+#[allow(non_camel_case_types)]
+struct STORAGE_PREDICT_FAILURE {
+    PredictFailure: DWORD,
+    VendorSpecific: [BYTE; 512],
+}
 
-            index += 1;
-        }
+/// Check whether a drive is powered down, without waking it up
+pub(crate) fn is_spun_down(device: &str) -> anyhow::Result<bool> {
+    let h_physical = Win32
+        .open(device)
+        .map_err(|code| DriveListError::Io(std::io::Error::from_raw_os_error(code as i32)))?;
 
-        if h_device != INVALID_HANDLE_VALUE {
-            CloseHandle(h_device);
-        }
+    let mut on = 0;
+
+    if unsafe { GetDevicePowerState(h_physical.as_raw(), &mut on) } == 0 {
+        return Err(DriveListError::Io(std::io::Error::last_os_error()).into());
     }
+
+    Ok(on == 0)
 }
 
-fn get_device_size(device_descriptor: &mut DeviceDescriptor, h_physical: *mut c_void) -> bool {
+/// Self-encryption support of NVMe drives.
+///
+/// ATA drives would need IDENTIFY DEVICE through pass-through, which needs write access.
+pub(crate) fn self_encrypting(device: &DeviceDescriptor) -> Option<bool> {
+    if device.bus_type != Some(BusType::Nvme) {
+        return None;
+    }
+
+    let h_physical = Win32.open(&device.device).ok()?;
+    get_self_encrypting_nvme(h_physical.as_raw())
+}
+
+/// Failure prediction of the drive's SMART implementation
+pub(crate) fn smart_health(device: &DeviceDescriptor) -> SmartHealth {
     unsafe {
-        let mut disk_geometry = MaybeUninit::<DISK_GEOMETRY_EX>::uninit();
-        disk_geometry.write(zeroed());
-        let mut size = 0;
-        let has_disk_geometry = DeviceIoControl(
-            h_physical,
-            IOCTL_DISK_GET_DRIVE_GEOMETRY_EX,
+        let Ok(h_physical) = Win32.open(&device.device) else {
+            return SmartHealth::Unknown;
+        };
+
+        let mut prediction: STORAGE_PREDICT_FAILURE = zeroed();
+        let mut size = 0_u32;
+        let res = DeviceIoControl(
+            h_physical.as_raw(),
+            IOCTL_STORAGE_PREDICT_FAILURE,
             null_mut(),
             0,
-            disk_geometry.as_mut_ptr() as _,
-            size_of::<DISK_GEOMETRY_EX>() as _,
+            &mut prediction as *mut _ as _,
+            size_of::<STORAGE_PREDICT_FAILURE>() as _,
             &mut size,
             null_mut(),
         );
 
-        if has_disk_geometry != 0 {
-            let dm = disk_geometry.assume_init_ref();
-            device_descriptor.size = (*dm.DiskSize.QuadPart()) as u64;
-            device_descriptor.block_size = dm.Geometry.BytesPerSector;
+        match (res, prediction.PredictFailure) {
+            (0, _) => SmartHealth::Unknown,
+            (_, 0) => SmartHealth::Passed,
+            _ => SmartHealth::Failing,
         }
-
-        has_disk_geometry != 0
     }
 }
 
-pub(crate) fn get_enumerator_name(
-    h_dev_info: HDEVINFO,
-    device_info_data: PSP_DEVINFO_DATA,
-) -> String {
+#[repr(C)]
+#[allow(non_snake_case)]
+#[allow(non_camel_case_types)]
+#[allow(dead_code)]
+struct STORAGE_ACCESS_ALIGNMENT_DESCRIPTOR {
+    Version: DWORD,
+    Size: DWORD,
+    BytesPerCacheLine: DWORD,
+    BytesOffsetForCacheAlignment: DWORD,
+    BytesPerLogicalSector: DWORD,
+    BytesPerPhysicalSector: DWORD,
+    BytesOffsetForSectorAlignment: DWORD,
+}
+
+// The alignment is read from the bytes at fixed offsets
+const _: () = assert!(
+    offset_of!(STORAGE_ACCESS_ALIGNMENT_DESCRIPTOR, BytesPerCacheLine) == decode::CACHE_LINE_OFFSET
+);
+const _: () = assert!(
+    offset_of!(STORAGE_ACCESS_ALIGNMENT_DESCRIPTOR, BytesPerLogicalSector)
+        == decode::LOGICAL_SECTOR_OFFSET
+);
+const _: () =
+    assert!(size_of::<STORAGE_ACCESS_ALIGNMENT_DESCRIPTOR>() == decode::ACCESS_ALIGNMENT_SIZE);
+
+#[repr(C)]
+#[allow(non_snake_case)]
+#[allow(non_camel_case_types)]
+#[allow(dead_code)]
+struct DEVICE_LB_PROVISIONING_DESCRIPTOR {
+    Version: DWORD,
+    Size: DWORD,
+    /// `ThinProvisioningEnabled` is bit 0, followed by more flags
+    Flags: BYTE,
+    Reserved1: [BYTE; 7],
+    OptimalUnmapGranularity: u64,
+    UnmapGranularityAlignment: u64,
+    MaxUnmapLbaCount: DWORD,
+    MaxUnmapBlockDescriptorCount: DWORD,
+}
+
+const _: () = assert!(
+    offset_of!(DEVICE_LB_PROVISIONING_DESCRIPTOR, Flags) == decode::PROVISIONING_FLAGS_OFFSET
+);
+const _: () = assert!(size_of::<DEVICE_LB_PROVISIONING_DESCRIPTOR>() == decode::PROVISIONING_SIZE);
+
+#[repr(C)]
+#[allow(non_snake_case)]
+#[allow(non_camel_case_types)]
+#[derive(Default)]
+struct STORAGE_PROTOCOL_SPECIFIC_DATA {
+    ProtocolType: DWORD,
+    DataType: DWORD,
+    ProtocolDataRequestValue: DWORD,
+    ProtocolDataRequestSubValue: DWORD,
+    ProtocolDataOffset: DWORD,
+    ProtocolDataLength: DWORD,
+    FixedProtocolReturnData: DWORD,
+    ProtocolDataRequestSubValue2: DWORD,
+    ProtocolDataRequestSubValue3: DWORD,
+    ProtocolDataRequestSubValue4: DWORD,
+}
+
+/// `StorageAdapterProtocolSpecificProperty` of `STORAGE_PROPERTY_ID`
+const STORAGE_ADAPTER_PROTOCOL_SPECIFIC_PROPERTY: STORAGE_PROPERTY_ID = 49;
+/// `ProtocolTypeNvme` of `STORAGE_PROTOCOL_TYPE`
+const PROTOCOL_TYPE_NVME: DWORD = 3;
+/// `NVMeDataTypeIdentify` of `STORAGE_PROTOCOL_NVME_DATA_TYPE`
+const NVME_DATA_TYPE_IDENTIFY: DWORD = 1;
+/// `NVME_IDENTIFY_CNS_CONTROLLER`
+const NVME_IDENTIFY_CNS_CONTROLLER: DWORD = 1;
+/// Size of an NVMe Identify data structure
+const NVME_IDENTIFY_SIZE: usize = 4096;
+
+/// Check whether an NVMe drive supports Security Send / Receive (OACS bit 0), which
+/// self-encrypting drives use for the TCG protocols
+fn get_self_encrypting_nvme(h_physical: *mut c_void) -> Option<bool> {
+    // `STORAGE_PROPERTY_QUERY` up to `AdditionalParameters`, on input, and
+    // `STORAGE_PROTOCOL_DATA_DESCRIPTOR` up to `ProtocolSpecificData`, on output
+    const HEADER: usize = 8;
+    const DATA: usize = HEADER + size_of::<STORAGE_PROTOCOL_SPECIFIC_DATA>();
+
     unsafe {
-        let mut buffer: [u8; MAX_PATH] = zeroed();
+        let mut buffer = vec![0_u8; DATA + NVME_IDENTIFY_SIZE];
+        let mut size = 0_u32;
 
-        if SetupDiGetDeviceRegistryPropertyW(
-            h_dev_info,
-            device_info_data,
-            SPDRP_ENUMERATOR_NAME,
-            null_mut(),
-            &mut buffer as _,
-            (size_of::<u8>() * MAX_PATH) as _,
+        let mut query: STORAGE_PROPERTY_QUERY = zeroed();
+        query.PropertyId = STORAGE_ADAPTER_PROTOCOL_SPECIFIC_PROPERTY;
+        query.QueryType = PropertyStandardQuery;
+        copy_nonoverlapping(&query as *const _ as *const u8, buffer.as_mut_ptr(), HEADER);
+
+        let protocol = STORAGE_PROTOCOL_SPECIFIC_DATA {
+            ProtocolType: PROTOCOL_TYPE_NVME,
+            DataType: NVME_DATA_TYPE_IDENTIFY,
+            ProtocolDataRequestValue: NVME_IDENTIFY_CNS_CONTROLLER,
+            ProtocolDataOffset: size_of::<STORAGE_PROTOCOL_SPECIFIC_DATA>() as _,
+            ProtocolDataLength: NVME_IDENTIFY_SIZE as _,
+            ..Default::default()
+        };
+        copy_nonoverlapping(
+            &protocol as *const _ as *const u8,
+            buffer.as_mut_ptr().add(HEADER),
+            size_of::<STORAGE_PROTOCOL_SPECIFIC_DATA>(),
+        );
+
+        if DeviceIoControl(
+            h_physical,
+            IOCTL_STORAGE_QUERY_PROPERTY,
+            buffer.as_mut_ptr() as _,
+            buffer.len() as _,
+            buffer.as_mut_ptr() as _,
+            buffer.len() as _,
+            &mut size,
             null_mut(),
-        ) != 0
+        ) == 0
         {
-            ansi_to_string(&buffer)
-        } else {
-            "".to_string()
+            return None;
         }
+
+        let identify = buffer.get(DATA..(size as usize).min(buffer.len()))?;
+        let oacs = u16::from_le_bytes([*identify.get(256)?, *identify.get(257)?]);
+
+        Some(oacs & 0x1 != 0)
     }
 }
 
-pub(crate) fn get_friendly_name(
+#[repr(C)]
+#[allow(non_snake_case)]
+#[allow(non_camel_case_types)]
+#[allow(dead_code)]
+struct STORAGE_WRITE_CACHE_PROPERTY {
+    Version: DWORD,
+    Size: DWORD,
+    WriteCacheType: DWORD,
+    WriteCacheEnabled: DWORD,
+    WriteCacheChangeable: DWORD,
+    WriteThroughSupported: DWORD,
+    FlushCacheSupported: BOOLEAN,
+    UserDefinedPowerProtection: BOOLEAN,
+    NVCacheEnabled: BOOLEAN,
+}
+
+const _: () = assert!(
+    offset_of!(STORAGE_WRITE_CACHE_PROPERTY, WriteCacheEnabled)
+        == decode::WRITE_CACHE_ENABLED_OFFSET
+);
+
+const _: () = assert!(size_of::<STORAGE_WRITE_CACHE_PROPERTY>() == decode::WRITE_CACHE_SIZE);
+
+fn get_registry_string(
     h_dev_info: HDEVINFO,
     device_info_data: PSP_DEVINFO_DATA,
+    property: DWORD,
 ) -> String {
     unsafe {
-        let mut buffer: [u8; MAX_PATH] = zeroed();
+        let mut buffer: [u16; MAX_PATH] = zeroed();
 
         if SetupDiGetDeviceRegistryPropertyW(
             h_dev_info,
             device_info_data,
-            SPDRP_FRIENDLYNAME,
+            property,
             null_mut(),
-            &mut buffer as _,
-            (size_of::<u8>() * MAX_PATH) as _,
+            buffer.as_mut_ptr() as _,
+            (size_of::<u16>() * MAX_PATH) as _,
             null_mut(),
         ) != 0
         {
-            ansi_to_string(&buffer)
+            wide_to_string(&buffer)
         } else {
             "".to_string()
         }
     }
 }
 
-fn get_mount_points(device_number: i32, mount_points: &mut Vec<MountPoint>) -> anyhow::Result<()> {
-    unsafe {
-        let mut h_logical = INVALID_HANDLE_VALUE;
-
-        for volume_name in get_available_volumes() {
-            if h_logical != INVALID_HANDLE_VALUE {
-                CloseHandle(h_logical);
-                h_logical = INVALID_HANDLE_VALUE;
-            }
-
-            let mut drive = MountPoint::new(format!(r"{}:\", volume_name));
-            let drive_type = GetDriveTypeA(CString::new(drive.path.clone()).unwrap().as_ptr());
-
-            if drive_type != DRIVE_FIXED && drive_type != DRIVE_REMOVABLE {
-                continue;
-            }
-
-            let h_logical = CreateFileA(
-                CString::new(format!(r"\\.\{}:", volume_name))
-                    .unwrap()
-                    .as_ptr(),
-                0,
-                FILE_SHARE_READ,
-                null_mut(),
-                OPEN_EXISTING,
-                FILE_ATTRIBUTE_NORMAL,
-                null_mut(),
-            );
-
-            if h_logical == INVALID_HANDLE_VALUE {
-                continue;
-            }
-
-            let logical_volume_device_number = get_device_number(h_logical);
-
-            if logical_volume_device_number < 0 {
-                continue;
-            }
-
-            if logical_volume_device_number == device_number {
-                let root_path = &mut [0_u16; 261];
-                let path_os: Vec<u16> = OsStr::new(&drive.path)
-                    .encode_wide()
-                    .chain(Some(0))
-                    .collect();
-
-                let mut ret = GetVolumePathNameW(
-                    path_os.as_ptr(),
-                    root_path.as_mut_ptr(),
-                    root_path.len() as _,
-                );
-
-                if ret == 0 {
-                    return Err(anyhow::Error::new(std::io::Error::last_os_error()));
-                }
-
-                let mut sectors_per_cluster = 0;
-                let mut bytes_per_sector = 0;
-                let mut number_of_free_clusters = 0;
-                let mut total_number_of_clusters = 0;
-                ret = GetDiskFreeSpaceW(
-                    root_path.as_ptr(),
-                    &mut sectors_per_cluster,
-                    &mut bytes_per_sector,
-                    &mut number_of_free_clusters,
-                    &mut total_number_of_clusters,
-                );
-
-                if ret == 0 {
-                    return Err(anyhow::Error::new(std::io::Error::last_os_error()));
-                }
-
-                let bytes_per_cluster = sectors_per_cluster as u64 * bytes_per_sector as u64;
-                drive.total_bytes = Some(bytes_per_cluster * total_number_of_clusters as u64);
-                drive.available_bytes = Some(bytes_per_cluster * number_of_free_clusters as u64);
-                mount_points.push(drive);
-            }
-        }
-
-        if h_logical != INVALID_HANDLE_VALUE {
-            CloseHandle(h_logical);
-        }
-    }
+/// `GetDriveType` of a volume root path, e.g. `C:\`, `DRIVE_UNKNOWN` if it contains a NUL
+fn get_drive_type(path: &str) -> DWORD {
+    let Ok(path) = CString::new(path) else {
+        return DRIVE_UNKNOWN;
+    };
 
-    Ok(())
+    unsafe { GetDriveTypeA(path.as_ptr()) }
 }
 
-fn get_partition_table_type(device: &mut DeviceDescriptor, h_physical: *mut c_void) -> bool {
-    unsafe {
-        const LSIZE: usize =
-            size_of::<DRIVE_LAYOUT_INFORMATION_EX>() + 256 * size_of::<PARTITION_INFORMATION_EX>();
-        let mut bytes: [u8; LSIZE] = zeroed();
-        let mut disk_layout_size = 0_u32;
-        let has_disk_layout = DeviceIoControl(
-            h_physical,
-            IOCTL_DISK_GET_DRIVE_LAYOUT_EX,
-            null_mut(),
-            0,
-            bytes.as_mut_ptr() as _,
-            LSIZE.try_into().unwrap(),
-            &mut disk_layout_size,
-            null_mut(),
-        );
-
-        if has_disk_layout == 0 {
-            device.error = Some(format!("NOT has_disk_layout. Error {}", GetLastError()));
-            return false;
-        }
-
-        let disk_layout: DRIVE_LAYOUT_INFORMATION_EX = transmute_copy(&bytes);
-
-        if disk_layout.PartitionStyle == PARTITION_STYLE_MBR
-            && ((disk_layout.PartitionCount % 4) == 0)
-        {
-            device.partition_table_type = Some("mbr".to_string());
-        } else if disk_layout.PartitionStyle == PARTITION_STYLE_GPT {
-            device.partition_table_type = Some("gpt".to_string());
-        }
+/// DOS device name prefix for devices of an interface class
+fn device_name_prefix(interface_guid: &GUID) -> &'static str {
+    if IsEqualGUID(interface_guid, &GUID_DEVINTERFACE_CDROM) {
+        "CdRom"
+    } else if IsEqualGUID(interface_guid, &GUID_DEVINTERFACE_TAPE) {
+        "Tape"
+    } else {
+        "PhysicalDrive"
     }
-
-    true
 }
 
-pub(crate) fn is_usb_drive(enumerator_name: &str) -> bool {
-    [
-        "USBSTOR",
-        "UASPSTOR",
-        "VUSBSTOR",
-        "RTUSER",
-        "CMIUCR",
-        "EUCR",
-        "ETRONSTOR",
-        "ASUSSTPT",
-    ]
-    .contains(&enumerator_name)
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-pub(crate) fn is_removable(h_dev_info: HDEVINFO, device_info_data: PSP_DEVINFO_DATA) -> bool {
-    let res = unsafe {
-        let mut result = 0_u8;
-        SetupDiGetDeviceRegistryPropertyW(
-            h_dev_info,
-            device_info_data,
-            SPDRP_REMOVAL_POLICY,
-            null_mut(),
-            &mut result as _,
-            size_of::<u32>() as _,
-            null_mut(),
+    #[test]
+    fn cdrom_interface() {
+        let prefix = device_name_prefix(&GUID_DEVINTERFACE_CDROM);
+
+        assert_eq!(crate::dos_device_path(prefix, 0), r"\\.\CdRom0");
+        assert_eq!(
+            crate::dos_device_path(device_name_prefix(&GUID_DEVINTERFACE_TAPE), 1),
+            r"\\.\Tape1"
         );
+    }
 
-        result
-    };
+    #[test]
+    fn drive_type_with_nul() {
+        assert_eq!(get_drive_type("C:\\\0"), DRIVE_UNKNOWN);
+    }
 
-    matches!(
-        res as u32,
-        CM_REMOVAL_POLICY_EXPECT_SURPRISE_REMOVAL | CM_REMOVAL_POLICY_EXPECT_ORDERLY_REMOVAL
-    )
+    #[test]
+    fn invalid_handle() {
+        assert!(SafeHandle::new(INVALID_HANDLE_VALUE).is_none());
+    }
 }
-
@@ -0,0 +1,39 @@
+#[derive(Debug, Clone)]
+/// Outcome of a single platform query made by [`probe_capabilities`](crate::probe_capabilities)
+pub struct ProbeResult {
+    /// Query name, e.g. `"geometry"`, `"adapter"`, `"alignment"`, `"serial"` or `"trim"`
+    pub query: &'static str,
+    /// Raw OS error code if the query failed
+    pub error: Option<i32>,
+}
+
+impl ProbeResult {
+    #[cfg_attr(
+        not(any(target_os = "windows", target_os = "linux", target_os = "macos")),
+        allow(dead_code)
+    )]
+    pub(crate) fn new(query: &'static str, result: Result<(), i32>) -> Self {
+        Self {
+            query,
+            error: result.err(),
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Which queries succeed for a device
+pub struct DeviceProbe {
+    pub device: String,
+    pub results: Vec<ProbeResult>,
+}
+
+impl DeviceProbe {
+    /// Result of the query named `query`, if it applies to this platform
+    pub fn get(&self, query: &str) -> Option<&ProbeResult> {
+        self.results.iter().find(|x| x.query == query)
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{pal, DeviceDescriptor, DriveListError};
+
+type Classifier = Arc<dyn Fn(&DeviceDescriptor) -> bool + Send + Sync>;
+
+#[derive(Clone, Default)]
+/// Builder for customizing drive enumeration
+pub struct DriveListQuery {
+    #[cfg(target_os = "windows")]
+    pub(crate) interface_guids: Vec<winapi::shared::guiddef::GUID>,
+    #[cfg(target_os = "windows")]
+    pub(crate) usb_enumerators: Vec<String>,
+    #[cfg(target_os = "windows")]
+    pub(crate) minimal: bool,
+    #[cfg(target_os = "linux")]
+    pub(crate) pseudo_filesystems: bool,
+    system_classifier: Option<Classifier>,
+    pub(crate) slow_stage_deadline: Option<Duration>,
+    smart_health: bool,
+    self_encryption: bool,
+    sizes_in_sectors: bool,
+    enumeration_durations: bool,
+    pub(crate) cancel: Option<Arc<AtomicBool>>,
+    min_free_bytes: Option<u64>,
+}
+
+impl DriveListQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Device interface classes to enumerate, e.g. `GUID_DEVINTERFACE_CDROM`.
+    ///
+    /// Defaults to `GUID_DEVINTERFACE_DISK` when empty.
+    #[cfg(target_os = "windows")]
+    pub fn interface_guids(
+        mut self,
+        guids: impl IntoIterator<Item = winapi::shared::guiddef::GUID>,
+    ) -> Self {
+        self.interface_guids = guids.into_iter().collect();
+        self
+    }
+
+    /// Additional enumerator names of USB storage drivers, e.g. from third-party card readers.
+    ///
+    /// Devices with these enumerators are reported as USB, in addition to the built-in ones
+    /// (`USBSTOR`, `UASPSTOR`, ...).
+    #[cfg(target_os = "windows")]
+    pub fn usb_enumerators(mut self, names: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.usb_enumerators
+            .extend(names.into_iter().map(|x| x.to_string()));
+        self
+    }
+
+    /// Only gather identity, size and mountpoints, without opening `\\.\PhysicalDriveN`.
+    ///
+    /// Lets processes without the privileges to open the physical drive still get a basic list.
+    /// Partitions, block sizes, serials and other details are left empty.
+    #[cfg(target_os = "windows")]
+    pub fn minimal(mut self, enable: bool) -> Self {
+        self.minimal = enable;
+        self
+    }
+
+    /// Also list pseudo filesystems (`proc`, `tmpfs`, `cgroup`, ...) from `/proc/self/mounts`.
+    ///
+    /// They aren't storage, so they are skipped by default. When enabled, each filesystem source
+    /// and type becomes a virtual drive named after the source, e.g. `tmpfs`, with its
+    /// mountpoints.
+    #[cfg(target_os = "linux")]
+    pub fn pseudo_filesystems(mut self, enable: bool) -> Self {
+        self.pseudo_filesystems = enable;
+        self
+    }
+
+    /// Override the platform's `is_system` determination.
+    ///
+    /// The classifier is called with each fully populated drive and its result replaces
+    /// `is_system`.
+    pub fn system_classifier(
+        mut self,
+        classifier: impl Fn(&DeviceDescriptor) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.system_classifier = Some(Arc::new(classifier));
+        self
+    }
+
+    /// Warn about slow devices.
+    ///
+    /// Any enumeration stage (e.g. geometry or adapter queries) taking longer than `deadline` adds
+    /// a diagnostic naming the device and stage. Enumeration still continues normally.
+    ///
+    /// Diagnostics are only returned by [`list_with_warnings`](Self::list_with_warnings),
+    /// [`list`](Self::list) discards them.
+    pub fn slow_stage_deadline(mut self, deadline: Duration) -> Self {
+        self.slow_stage_deadline = Some(deadline);
+        self
+    }
+
+    /// Query the SMART health status of each drive.
+    ///
+    /// This opens every raw device, so it is slower and usually needs elevated privileges. Drives
+    /// which can't be queried report [`SmartHealth::Unknown`](crate::SmartHealth::Unknown).
+    pub fn smart_health(mut self, enable: bool) -> Self {
+        self.smart_health = enable;
+        self
+    }
+
+    /// Check whether each drive is self-encrypting, see
+    /// [`DeviceDescriptor::self_encrypting`].
+    ///
+    /// This sends an identify command to every raw device, which can spin up sleeping disks and
+    /// hang some USB bridges. Only NVMe drives are checked on Windows, and macOS always reports
+    /// unknown.
+    pub fn self_encryption(mut self, enable: bool) -> Self {
+        self.self_encryption = enable;
+        self
+    }
+
+    /// Report all size fields in logical sectors instead of bytes.
+    ///
+    /// Affects `size`, partition offsets and sizes, and mountpoint `total_bytes` and
+    /// `available_bytes`, which all keep their names.
+    pub fn sizes_in_sectors(mut self, enable: bool) -> Self {
+        self.sizes_in_sectors = enable;
+        self
+    }
+
+    /// Report how long each device took to enumerate in `enumeration_duration`
+    pub fn enumeration_durations(mut self, enable: bool) -> Self {
+        self.enumeration_durations = enable;
+        self
+    }
+
+    /// Only keep drives with a mountpoint that has at least `bytes` available.
+    ///
+    /// Drives without mountpoints, or whose free space is unknown, are dropped.
+    pub fn min_free_bytes(mut self, bytes: u64) -> Self {
+        self.min_free_bytes = Some(bytes);
+        self
+    }
+
+    /// Stop listing early once `token` is set, failing with [`DriveListError::Cancelled`].
+    ///
+    /// The token is checked between devices on Windows and macOS, and after `lsblk` on Linux.
+    pub fn cancel_token(mut self, token: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    pub(crate) fn check_cancelled(&self) -> Result<(), DriveListError> {
+        match &self.cancel {
+            Some(x) if x.load(Ordering::Relaxed) => Err(DriveListError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    /// Get a list of all drives matching the query
+    pub fn list(&self) -> anyhow::Result<Vec<DeviceDescriptor>> {
+        let mut drives = pal::active().enumerate(self, &mut Vec::new())?;
+        self.check_cancelled()?;
+        self.finish(&mut drives);
+
+        Ok(drives)
+    }
+
+    /// Get a list of all drives matching the query along with backend diagnostics.
+    ///
+    /// Diagnostics cover problems that are not tied to a single device's `error`, such as devices
+    /// that were skipped, handles that failed to open or missing privileges.
+    pub fn list_with_warnings(&self) -> anyhow::Result<(Vec<DeviceDescriptor>, Vec<String>)> {
+        let mut diagnostics = Vec::new();
+
+        if !pal::has_required_privileges() {
+            diagnostics.push(
+                "Insufficient privileges: some device information will be missing".to_string(),
+            );
+        }
+
+        let mut drives = pal::active().enumerate(self, &mut diagnostics)?;
+        self.check_cancelled()?;
+        self.finish(&mut drives);
+
+        Ok((drives, diagnostics))
+    }
+
+    fn finish(&self, drives: &mut Vec<DeviceDescriptor>) {
+        pal::finish(drives);
+
+        // Backends always measure, it is cheap compared to the queries themselves
+        if !self.enumeration_durations {
+            for drive in drives.iter_mut() {
+                drive.enumeration_duration = None;
+            }
+        }
+
+        if self.smart_health {
+            for drive in drives.iter_mut() {
+                drive.smart_health = Some(pal::smart_health(drive));
+            }
+        }
+
+        if self.self_encryption {
+            for drive in drives.iter_mut() {
+                drive.self_encrypting = pal::self_encrypting(drive);
+            }
+        }
+
+        if let Some(classifier) = &self.system_classifier {
+            for drive in drives.iter_mut() {
+                drive.is_system = classifier(drive);
+            }
+        }
+
+        if let Some(min) = self.min_free_bytes {
+            drives.retain(|drive| {
+                drive
+                    .mountpoints
+                    .iter()
+                    .any(|x| x.available_bytes.is_some_and(|x| x >= min))
+            });
+        }
+
+        // Last, so everything above still sees bytes
+        if self.sizes_in_sectors {
+            for drive in drives.iter_mut() {
+                drive.convert_sizes_to_sectors();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pal::{with_pal, Pal, Scripted},
+        MountPoint,
+    };
+
+    /// Backend which sets the cancel token while listing, like a window closing mid-enumeration
+    struct CancelDuringListing(Arc<AtomicBool>);
+
+    impl Pal for CancelDuringListing {
+        fn enumerate(
+            &self,
+            _query: &DriveListQuery,
+            _diagnostics: &mut Vec<String>,
+        ) -> anyhow::Result<Vec<DeviceDescriptor>> {
+            self.0.store(true, Ordering::Relaxed);
+
+            Ok(vec![DeviceDescriptor::default()])
+        }
+    }
+
+    #[test]
+    fn cancelled_during_listing() {
+        let token = Arc::new(AtomicBool::new(false));
+        let query = DriveListQuery::new().cancel_token(token.clone());
+
+        let err = with_pal(CancelDuringListing(token), || query.list()).unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<DriveListError>(),
+            Some(DriveListError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn not_cancelled() {
+        let token = Arc::new(AtomicBool::new(false));
+        let query = DriveListQuery::new().cancel_token(token);
+
+        assert!(query.check_cancelled().is_ok());
+        assert_eq!(
+            with_pal(CancelDuringListing(Arc::default()), || query.list())
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    fn drive(device: &str, available: &[Option<u64>]) -> DeviceDescriptor {
+        DeviceDescriptor {
+            device: device.to_string(),
+            mountpoints: available
+                .iter()
+                .enumerate()
+                .map(|(i, x)| MountPoint {
+                    available_bytes: *x,
+                    ..MountPoint::new(format!("/media/{}{}", device, i))
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn min_free_bytes() {
+        const GB: u64 = 1000 * 1000 * 1000;
+
+        let drives = vec![
+            drive("sda", &[Some(GB), Some(5 * GB)]),
+            drive("sdb", &[Some(3 * GB)]),
+            drive("sdc", &[Some(4 * GB)]),
+            drive("sdd", &[None]),
+            drive("sde", &[]),
+        ];
+        let query = DriveListQuery::new().min_free_bytes(4 * GB);
+
+        let drives = with_pal(Scripted::new(vec![drives]), || query.list()).unwrap();
+
+        let devices: Vec<_> = drives.iter().map(|x| x.device.as_str()).collect();
+        assert_eq!(devices, ["sda", "sdc"]);
+    }
+
+    #[test]
+    fn system_classifier() {
+        let drives = vec![
+            DeviceDescriptor {
+                is_system: true,
+                ..drive("sda", &[])
+            },
+            drive("sdb", &[None]),
+        ];
+        // Only drives with something mounted count as system
+        let query = DriveListQuery::new().system_classifier(|x| !x.mountpoints.is_empty());
+
+        let drives = with_pal(Scripted::new(vec![drives]), || query.list()).unwrap();
+
+        assert!(!drives[0].is_system);
+        assert!(drives[1].is_system);
+    }
+
+    #[test]
+    fn enumeration_durations() {
+        let drives = vec![DeviceDescriptor {
+            enumeration_duration: Some(Duration::from_millis(3)),
+            ..drive("sda", &[])
+        }];
+
+        let (enabled, disabled) = with_pal(Scripted::new(vec![drives]), || {
+            (
+                DriveListQuery::new().enumeration_durations(true).list(),
+                DriveListQuery::new().list(),
+            )
+        });
+
+        assert!(enabled.unwrap()[0]
+            .enumeration_duration
+            .is_some_and(|x| x > Duration::ZERO));
+        assert_eq!(disabled.unwrap()[0].enumeration_duration, None);
+    }
+}
@@ -0,0 +1,153 @@
+use std::{borrow::Cow, fmt::Write};
+
+use crate::DeviceDescriptor;
+
+const HEADER: [&str; 5] = ["Device", "Size", "Bus", "Mount", "Flags"];
+
+/// Format a byte count with binary units, e.g. `14.9 GiB`
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn flags(device: &DeviceDescriptor) -> String {
+    [
+        (device.is_system, "system"),
+        (device.is_removable, "removable"),
+        (device.is_readonly, "readonly"),
+        (device.is_virtual, "virtual"),
+        (device.is_card, "card"),
+    ]
+    .into_iter()
+    .filter_map(|(set, name)| set.then_some(name))
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+fn mountpoints(device: &DeviceDescriptor) -> Cow<'_, str> {
+    match &device.mountpoints[..] {
+        [] => Cow::Borrowed(""),
+        [x] => Cow::Borrowed(&x.path),
+        x => Cow::Owned(
+            x.iter()
+                .map(|x| x.path.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+    }
+}
+
+/// Cells of the row for `device`, borrowing its strings where they are shown as is
+fn row(device: &DeviceDescriptor) -> [Cow<'_, str>; 5] {
+    [
+        Cow::Borrowed(&device.device),
+        Cow::Owned(format_size(device.size)),
+        Cow::Borrowed(device.bus_type.as_ref().map_or("", |x| x.as_str())),
+        mountpoints(device),
+        Cow::Owned(flags(device)),
+    ]
+}
+
+/// Render `devices` as a plain text table with aligned columns, one row per device after the
+/// header row
+pub fn render_table(devices: &[DeviceDescriptor]) -> String {
+    let rows: Vec<[Cow<'_, str>; 5]> = std::iter::once(HEADER.map(Cow::Borrowed))
+        .chain(devices.iter().map(row))
+        .collect();
+
+    let mut widths = [0_usize; 5];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line_width = widths.iter().sum::<usize>() + 2 * (widths.len() - 1) + 1;
+    let mut out = String::with_capacity(line_width * rows.len());
+
+    for row in &rows {
+        let start = out.len();
+
+        for (i, (cell, width)) in row.iter().zip(widths).enumerate() {
+            if i > 0 {
+                out.push_str("  ");
+            }
+            let _ = write!(out, "{:<width$}", cell, width = width);
+        }
+
+        out.truncate(start + out[start..].trim_end().len());
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BusType, MountPoint};
+
+    #[test]
+    fn row_borrows_device_strings() {
+        let device = DeviceDescriptor {
+            device: "/dev/sda".to_string(),
+            bus_type: Some(BusType::Usb),
+            mountpoints: vec![MountPoint::new("/media/stick")],
+            ..Default::default()
+        };
+
+        let [device_cell, _, bus, mountpoint, _] = row(&device);
+        assert!(matches!(device_cell, Cow::Borrowed("/dev/sda")));
+        assert!(matches!(bus, Cow::Borrowed("USB")));
+        assert!(matches!(mountpoint, Cow::Borrowed("/media/stick")));
+    }
+
+    #[test]
+    fn header_and_one_row_per_device() {
+        let devices = [
+            DeviceDescriptor {
+                device: "/dev/sda".to_string(),
+                size: 500 * 1024 * 1024 * 1024,
+                bus_type: Some(BusType::Sata),
+                mountpoints: vec![MountPoint::new("/"), MountPoint::new("/boot")],
+                is_system: true,
+                ..Default::default()
+            },
+            DeviceDescriptor {
+                device: "/dev/sdb".to_string(),
+                size: 512,
+                bus_type: Some(BusType::Usb),
+                is_removable: true,
+                ..Default::default()
+            },
+        ];
+
+        let table = render_table(&devices);
+        let lines: Vec<_> = table.lines().collect();
+
+        assert_eq!(
+            lines,
+            [
+                "Device    Size       Bus   Mount    Flags",
+                "/dev/sda  500.0 GiB  SATA  /,/boot  system",
+                "/dev/sdb  512 B      USB            removable",
+            ]
+        );
+        assert!(render_table(&[])
+            .lines()
+            .eq(["Device  Size  Bus  Mount  Flags"]));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{pal, DeviceDescriptor, DriveEvent, DriveListQuery};
+
+#[derive(Debug, Clone, Default)]
+/// State of the drive list as of a call to [`drive_list_since`](crate::drive_list_since).
+///
+/// Start with `ChangeToken::default()`, which reports every drive as added.
+pub struct ChangeToken {
+    /// [`Pal::change_sequence`](crate::pal::Pal::change_sequence) when the drives were listed
+    sequence: Option<u64>,
+    drives: Vec<DeviceDescriptor>,
+}
+
+impl ChangeToken {
+    /// Drives as of this token
+    pub fn drives(&self) -> &[DeviceDescriptor] {
+        &self.drives
+    }
+}
+
+pub(crate) fn drive_list_since(
+    token: ChangeToken,
+) -> anyhow::Result<(Vec<DriveEvent>, ChangeToken)> {
+    // Read before listing, so changes during the listing are picked up by the next call
+    let sequence = pal::active().change_sequence();
+
+    if sequence.is_some() && sequence == token.sequence {
+        return Ok((Vec::new(), token));
+    }
+
+    let drives = DriveListQuery::new().list()?;
+    let events = DriveEvent::diff(&token.drives, &drives);
+
+    Ok((events, ChangeToken { sequence, drives }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::{
+        pal::{with_pal, Pal, Scripted},
+        ChangedField, MountPoint,
+    };
+
+    /// Scripted backend with a fixed change sequence, counting the listings
+    struct Sequenced {
+        drives: Scripted,
+        sequence: u64,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Pal for Sequenced {
+        fn enumerate(
+            &self,
+            query: &DriveListQuery,
+            diagnostics: &mut Vec<String>,
+        ) -> anyhow::Result<Vec<DeviceDescriptor>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.drives.enumerate(query, diagnostics)
+        }
+
+        fn change_sequence(&self) -> Option<u64> {
+            Some(self.sequence)
+        }
+    }
+
+    fn drive(mountpoints: &[&str]) -> DeviceDescriptor {
+        DeviceDescriptor {
+            device: "/dev/sda".to_string(),
+            mountpoints: mountpoints.iter().map(MountPoint::new).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unchanged_state_is_empty_delta() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let pal = Sequenced {
+            drives: Scripted::new(vec![vec![drive(&[])], vec![drive(&["/mnt"])]]),
+            sequence: 7,
+            calls: calls.clone(),
+        };
+
+        with_pal(pal, || {
+            let (events, token) = drive_list_since(ChangeToken::default()).unwrap();
+            assert!(matches!(&events[..], [DriveEvent::Added(x)] if x.device == "/dev/sda"));
+
+            // The second list would report the new mountpoint if it was used
+            let (events, token) = drive_list_since(token).unwrap();
+            assert!(events.is_empty());
+            assert_eq!(token.drives().len(), 1);
+        });
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    /// Backends without a change sequence list the drives on every call
+    #[test]
+    fn mount_without_sequence() {
+        let pal = Scripted::new(vec![vec![drive(&[])], vec![drive(&["/mnt"])]]);
+
+        with_pal(pal, || {
+            let (_, token) = drive_list_since(ChangeToken::default()).unwrap();
+            let (events, _) = drive_list_since(token).unwrap();
+
+            assert!(matches!(
+                &events[..],
+                [DriveEvent::Changed(_, fields)] if fields.contains(&ChangedField::MountpointsAdded)
+            ));
+        });
+    }
+}
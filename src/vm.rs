@@ -0,0 +1,85 @@
+//! Detection of virtual machines and hypervisor-emulated disks
+
+/// Vendor and model strings used by disks emulated by common hypervisors
+#[cfg(any(target_os = "linux", target_os = "windows", test))]
+const VIRTUAL_DISK_NAMES: &[&str] = &[
+    "qemu",
+    "vbox",
+    "vmware",
+    "virtio",
+    "msft virtual disk",
+    "xen",
+    "red hat",
+    "bochs",
+];
+
+/// System vendors and products reported through DMI by common hypervisors
+#[cfg(target_os = "linux")]
+const VIRTUAL_DMI_NAMES: &[&str] = &[
+    "qemu",
+    "kvm",
+    "vmware",
+    "virtualbox",
+    "innotek",
+    "xen",
+    "bochs",
+    "parallels",
+    "virtual machine",
+];
+
+/// Check if a vendor or model name belongs to a hypervisor-emulated disk
+#[cfg(any(target_os = "linux", target_os = "windows", test))]
+pub(crate) fn is_virtual_disk_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    VIRTUAL_DISK_NAMES.iter().any(|x| name.contains(x))
+}
+
+pub(crate) fn running_in_vm() -> bool {
+    cpuid_hypervisor() || dmi_hypervisor()
+}
+
+/// Hypervisor present bit (CPUID leaf 1, ECX bit 31)
+#[allow(unused_unsafe)]
+fn cpuid_hypervisor() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    let res = unsafe { std::arch::x86_64::__cpuid(1) };
+    #[cfg(target_arch = "x86")]
+    let res = unsafe { std::arch::x86::__cpuid(1) };
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    return res.ecx & (1 << 31) != 0;
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "x86")))]
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn dmi_hypervisor() -> bool {
+    ["sys_vendor", "product_name"].iter().any(|x| {
+        std::fs::read_to_string(format!("/sys/class/dmi/id/{}", x))
+            .map(|x| {
+                let x = x.to_lowercase();
+                VIRTUAL_DMI_NAMES.iter().any(|y| x.contains(y))
+            })
+            .unwrap_or(false)
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn dmi_hypervisor() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn virtio_disk_names() {
+        assert!(is_virtual_disk_name("Virtio Block Device"));
+        assert!(is_virtual_disk_name("virtio-blk"));
+        assert!(is_virtual_disk_name("QEMU HARDDISK"));
+        assert!(is_virtual_disk_name("Msft Virtual Disk"));
+        assert!(!is_virtual_disk_name("Samsung SSD 970 EVO Plus"));
+        assert!(!is_virtual_disk_name(""));
+    }
+}
@@ -0,0 +1,314 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use crate::{DeviceDescriptor, DriveListQuery};
+
+#[derive(Debug, Clone)]
+/// Change in the set of drives between two enumerations
+pub enum DriveEvent {
+    Added(DeviceDescriptor),
+    Removed(DeviceDescriptor),
+    /// Drive is still present but some of its fields changed
+    Changed(DeviceDescriptor, ChangedFields),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// Part of a drive which changed, see [`DriveEvent::Changed`]
+pub enum ChangedField {
+    Size,
+    Description,
+    ReadOnly,
+    MediaPresent,
+    PartitionTable,
+    Partitions,
+    MountpointsAdded,
+    MountpointsRemoved,
+    /// Total or available bytes of a mountpoint present before and after
+    FreeSpace,
+}
+
+/// Set of changed parts of a drive
+pub type ChangedFields = BTreeSet<ChangedField>;
+
+impl DriveEvent {
+    /// Events turning `old` into `new`, matching drives by `device`.
+    ///
+    /// Drives whose only change is [`ChangedField::FreeSpace`] are left out, since free space
+    /// changes with every write to a mounted volume.
+    pub fn diff(old: &[DeviceDescriptor], new: &[DeviceDescriptor]) -> Vec<DriveEvent> {
+        diff(old, new, false)
+    }
+
+    /// Like [`diff`](Self::diff), but also reporting drives whose only change is free space
+    pub fn diff_with_free_space(
+        old: &[DeviceDescriptor],
+        new: &[DeviceDescriptor],
+    ) -> Vec<DriveEvent> {
+        diff(old, new, true)
+    }
+}
+
+fn diff(old: &[DeviceDescriptor], new: &[DeviceDescriptor], free_space: bool) -> Vec<DriveEvent> {
+    let removed = old
+        .iter()
+        .filter(|x| !new.iter().any(|y| y.device == x.device))
+        .map(|x| DriveEvent::Removed(x.clone()));
+    let added_or_changed =
+        new.iter()
+            .filter_map(|x| match old.iter().find(|y| y.device == x.device) {
+                None => Some(DriveEvent::Added(x.clone())),
+                Some(y) => {
+                    let fields = changed_fields(y, x);
+                    let report = if free_space {
+                        !fields.is_empty()
+                    } else {
+                        fields.iter().any(|x| *x != ChangedField::FreeSpace)
+                    };
+
+                    report.then(|| DriveEvent::Changed(x.clone(), fields))
+                }
+            });
+
+    removed.chain(added_or_changed).collect()
+}
+
+/// Parts of a drive which differ between `old` and `new`
+pub fn changed_fields(old: &DeviceDescriptor, new: &DeviceDescriptor) -> ChangedFields {
+    let mut fields = ChangedFields::new();
+    let mut set = |changed: bool, field: ChangedField| {
+        if changed {
+            fields.insert(field);
+        }
+    };
+
+    set(old.size != new.size, ChangedField::Size);
+    set(
+        old.description != new.description,
+        ChangedField::Description,
+    );
+    set(old.is_readonly != new.is_readonly, ChangedField::ReadOnly);
+    set(
+        old.media_present != new.media_present,
+        ChangedField::MediaPresent,
+    );
+    set(
+        old.partition_table_type != new.partition_table_type,
+        ChangedField::PartitionTable,
+    );
+    set(
+        old.partitions.len() != new.partitions.len(),
+        ChangedField::Partitions,
+    );
+
+    let find = |drive: &DeviceDescriptor, path: &str| {
+        drive
+            .mountpoints
+            .iter()
+            .find(|x| x.path == path)
+            .map(|x| (x.total_bytes, x.available_bytes))
+    };
+    set(
+        new.mountpoints.iter().any(|x| find(old, &x.path).is_none()),
+        ChangedField::MountpointsAdded,
+    );
+    set(
+        old.mountpoints.iter().any(|x| find(new, &x.path).is_none()),
+        ChangedField::MountpointsRemoved,
+    );
+    set(
+        new.mountpoints
+            .iter()
+            .any(|x| find(old, &x.path).is_some_and(|y| y != (x.total_bytes, x.available_bytes))),
+        ChangedField::FreeSpace,
+    );
+
+    fields
+}
+
+/// Polling drive watcher, yielding a [`DriveEvent`] for each change.
+///
+/// A single insertion usually shows up over several enumerations (device, partitions, mounts). Once
+/// a change is seen, the watcher keeps polling until nothing has changed for the quiet period and
+/// then reports the net difference, so a burst results in one event per drive.
+pub struct DriveWatcher {
+    query: DriveListQuery,
+    poll: Duration,
+    quiet: Duration,
+    free_space: bool,
+    drives: Vec<DeviceDescriptor>,
+    pending: VecDeque<DriveEvent>,
+}
+
+impl DriveWatcher {
+    /// Start watching from the drives currently present
+    pub fn new(query: DriveListQuery) -> anyhow::Result<Self> {
+        let drives = query.list()?;
+
+        Ok(Self {
+            query,
+            poll: Duration::from_secs(1),
+            quiet: Duration::from_millis(500),
+            free_space: false,
+            drives,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Time between enumerations. Defaults to 1 second.
+    pub fn poll_interval(mut self, poll: Duration) -> Self {
+        self.poll = poll;
+        self
+    }
+
+    /// Time without changes before events are reported. Defaults to 500 milliseconds.
+    pub fn quiet_period(mut self, quiet: Duration) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Also report drives whose only change is free space, see
+    /// [`DriveEvent::diff_with_free_space`]. Defaults to false.
+    ///
+    /// Free space changes don't restart the quiet period, so ongoing writes don't delay events.
+    pub fn report_free_space(mut self, enable: bool) -> Self {
+        self.free_space = enable;
+        self
+    }
+
+    /// Drives as of the last reported events
+    pub fn drives(&self) -> &[DeviceDescriptor] {
+        &self.drives
+    }
+
+    fn diff(&self, old: &[DeviceDescriptor], new: &[DeviceDescriptor]) -> Vec<DriveEvent> {
+        diff(old, new, self.free_space)
+    }
+
+    /// Poll until the drive list stops changing for the quiet period
+    fn settle(&self, mut drives: Vec<DeviceDescriptor>) -> anyhow::Result<Vec<DeviceDescriptor>> {
+        let mut last_change = Instant::now();
+
+        loop {
+            let elapsed = last_change.elapsed();
+
+            if elapsed >= self.quiet {
+                break;
+            }
+
+            sleep(self.poll.min(self.quiet - elapsed));
+
+            let next = self.query.list()?;
+
+            if !DriveEvent::diff(&drives, &next).is_empty() {
+                last_change = Instant::now();
+            }
+
+            drives = next;
+        }
+
+        Ok(drives)
+    }
+}
+
+impl Iterator for DriveWatcher {
+    type Item = anyhow::Result<DriveEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+
+            sleep(self.poll);
+
+            let drives = match self.query.list() {
+                Ok(x) => x,
+                Err(err) => return Some(Err(err)),
+            };
+
+            if self.diff(&self.drives, &drives).is_empty() {
+                continue;
+            }
+
+            let drives = match self.settle(drives) {
+                Ok(x) => x,
+                Err(err) => return Some(Err(err)),
+            };
+
+            self.pending.extend(self.diff(&self.drives, &drives));
+            self.drives = drives;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pal, MountPoint};
+
+    fn drive(available_bytes: u64) -> DeviceDescriptor {
+        DeviceDescriptor {
+            device: "/dev/sdb".to_string(),
+            mountpoints: vec![MountPoint {
+                available_bytes: Some(available_bytes),
+                ..MountPoint::new("/media/usb")
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn free_space_only() {
+        let old = [drive(100)];
+        let new = [drive(50)];
+
+        assert_eq!(
+            changed_fields(&old[0], &new[0]),
+            ChangedFields::from([ChangedField::FreeSpace])
+        );
+        assert!(DriveEvent::diff(&old, &new).is_empty());
+        assert!(matches!(
+            &DriveEvent::diff_with_free_space(&old, &new)[..],
+            [DriveEvent::Changed(_, fields)] if fields.len() == 1
+        ));
+    }
+
+    #[test]
+    fn free_space_with_other_change() {
+        let old = [drive(100)];
+        let mut new = [drive(50)];
+        new[0].is_readonly = true;
+
+        assert!(matches!(
+            &DriveEvent::diff(&old, &new)[..],
+            [DriveEvent::Changed(_, fields)]
+                if *fields == ChangedFields::from([ChangedField::ReadOnly, ChangedField::FreeSpace])
+        ));
+    }
+
+    #[test]
+    fn burst_is_one_event() {
+        let mounted = drive(100);
+        let mut bare = mounted.clone();
+        bare.mountpoints.clear();
+
+        // The drive appears first and is mounted on a later poll
+        let script = vec![vec![], vec![bare], vec![mounted]];
+
+        pal::with_pal(pal::Scripted::new(script), || {
+            let mut watcher = DriveWatcher::new(DriveListQuery::new())
+                .unwrap()
+                .poll_interval(Duration::from_millis(1))
+                .quiet_period(Duration::from_millis(20));
+
+            match watcher.next() {
+                Some(Ok(DriveEvent::Added(drive))) => assert_eq!(drive.mountpoints.len(), 1),
+                x => panic!("unexpected {:?}", x),
+            }
+            assert!(watcher.pending.is_empty());
+        });
+    }
+}